@@ -4,45 +4,111 @@ use iced::stream;
 use iced::widget::text;
 
 use futures::channel::mpsc;
+use futures::future::FutureExt;
 use futures::sink::SinkExt;
 use futures::stream::{Stream, StreamExt};
 
 use async_tungstenite::tungstenite;
-use std::fmt;
+use std::{error::Error, fmt, io};
 
-pub fn connect(access: String, pass: String) -> impl Stream<Item = Event> {
-    stream::channel(100, |mut output| async move {
+use crate::protocol::ProtocolMessage;
+
+// Where sessions are hosted and joined unless another host or port is given
+pub const DEFAULT_HOST: &str = "0.0.0.0";
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Combines a host and port as typed into an address to bind or connect to. Blank fields fall
+/// back to the defaults.
+pub fn server_address(host: &str, port: &str) -> Result<String, String> {
+    let host = match host.trim() {
+        "" => DEFAULT_HOST,
+        host => host,
+    };
+    if host.contains(['/', ' ']) {
+        return Err(format!("{} is not a valid host.", host));
+    }
+
+    let port = match port.trim() {
+        "" => DEFAULT_PORT,
+        port => port
+            .parse::<u16>()
+            .ok()
+            .filter(|&port| port > 0)
+            .ok_or_else(|| "The port must be a number from 1 to 65535.".to_string())?,
+    };
+
+    // IPv6 addresses are bracketed so the port can be told apart
+    if host.contains(':') && !host.starts_with('[') {
+        Ok(format!("[{}]:{}", host, port))
+    } else {
+        Ok(format!("{}:{}", host, port))
+    }
+}
+
+// How long to wait before reconnecting, unless a retry is requested sooner
+const RETRY_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+
+// How long edits stay paced after the server last asked this client to slow down
+const PACING_PERIOD: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+
+/// Connects to the session's websocket at `address`, over TLS when `secure`. An empty `room`
+/// joins the host's own document.
+pub fn connect(
+    address: String,
+    secure: bool,
+    access: String,
+    pass: String,
+    room: String,
+    name: String,
+) -> impl Stream<Item = Event> {
+    stream::channel(100, move |mut output| async move {
         let mut state = State::Disconnected;
 
+        let (control_sender, mut control) = mpsc::channel(10);
+        let _ = output.send(Event::Ready(Controller(control_sender))).await;
+
+        let mut pacer = Pacer::new(tokio::time::Instant::now());
+
         loop {
             match &mut state {
                 State::Disconnected => {
-                    let status_endpoint = "http://0.0.0.0:8080/status";
+                    // Leaving can come while no connection is up to notice it
+                    while let Ok(Some(command)) = control.try_next() {
+                        if let Control::Stop = command {
+                            return;
+                        }
+                    }
+
+                    let scheme = if secure { "https" } else { "http" };
+                    let status_endpoint = format!("{}://{}/status", scheme, address);
                     let client = reqwest::Client::new();
 
                     let resp = client.get(status_endpoint).send().await;
 
-                    if resp.is_err() {
-                        let _ = output.send(Event::ServerDown).await;
-                        continue;
+                    // A server whose certificate fails to verify is still up, which the
+                    // websocket handshake below reports
+                    if let Err(err) = resp {
+                        if !secure || is_unreachable(&err) {
+                            let _ = output.send(Event::ServerDown).await;
+                            continue;
+                        }
                     }
 
-                    let url = format!("ws://0.0.0.0:8080/{}", access);
-                    let request = Request::builder()
-                        .uri(url)
-                        .header("AUTHORIZATION", pass.clone())
-                        .header("sec-websocket-key", "foo")
-                        .header("upgrade", "websocket")
-                        .header("host", "server.example.com")
-                        .header("connection", "upgrade")
-                        .header("sec-websocket-version", 13)
-                        .body(())
-                        .unwrap();
+                    let request = connect_request(&address, secure, &access, &pass, &room);
 
                     match async_tungstenite::tokio::connect_async(request).await {
-                        Ok((websocket, _)) => {
+                        Ok((mut websocket, _)) => {
+                            // The host labels this client's cursor with the name
+                            if !name.trim().is_empty() {
+                                let message = ProtocolMessage::Name(name.trim().to_string());
+                                let _ = websocket
+                                    .send(tungstenite::Message::Text(message.to_ws_text()))
+                                    .await;
+                            }
+
                             // Split the websocket into a channel for seding and receiving messages
-                            let (sender, receiver) = mpsc::channel(100);
+                            // Unbounded, so a burst of edits queues up instead of being dropped
+                            let (sender, receiver) = mpsc::unbounded();
 
                             let _ = output.send(Event::Connected(Connection(sender))).await;
 
@@ -50,13 +116,22 @@ pub fn connect(access: String, pass: String) -> impl Stream<Item = Event> {
                         }
                         //try and get more granular here with the event that's being fired back
                         Err(err) => {
-                            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                            if let tungstenite::Error::Http(code) = err {
-                                let status = code.status();
-                                if status == 401 {
+                            if wait_for_retry(&mut control).await {
+                                return;
+                            }
+                            match err {
+                                tungstenite::Error::Http(code) if code.status() == 401 => {
                                     let _ = output.send(Event::IncorrectPassword).await;
                                     continue;
                                 }
+                                // The TLS handshake reports a certificate it rejects as invalid data
+                                tungstenite::Error::Io(err)
+                                    if secure && err.kind() == io::ErrorKind::InvalidData =>
+                                {
+                                    let _ = output.send(Event::UntrustedCertificate).await;
+                                    continue;
+                                }
+                                _ => {}
                             }
 
                             let _ = output.send(Event::Disconnected).await;
@@ -91,6 +166,14 @@ pub fn connect(access: String, pass: String) -> impl Stream<Item = Event> {
                                     let _ = output.send(Event::Disconnected).await;
                                 }
                                 other_message => {
+                                    let message_text = other_message.as_str();
+                                    let is_edit = ProtocolMessage::from_ws_text(message_text)
+                                        .is_ok_and(|message| message.is_edit());
+                                    if is_edit {
+                                        let send_at = pacer.next_edit(tokio::time::Instant::now());
+                                        tokio::time::sleep_until(send_at).await;
+                                    }
+
                                     // Send other messages to the WebSocket server
                                     let result = websocket.send(tungstenite::Message::Text(other_message.to_string())).await;
 
@@ -102,6 +185,27 @@ pub fn connect(access: String, pass: String) -> impl Stream<Item = Event> {
                                 }
                             }
                         }
+
+                        command = control.select_next_some() => {
+                            match command {
+                                Control::Pace(interval) => {
+                                    pacer.pace(interval, tokio::time::Instant::now());
+                                }
+                                // Reconnecting makes the server send its copy of the document
+                                Control::Resync => {
+                                    let _ = websocket.close(None).await;
+                                    let _ = output.send(Event::Disconnected).await;
+
+                                    state = State::Disconnected;
+                                }
+                                // Ends the stream, so nothing of this session outlives leaving it
+                                Control::Stop => {
+                                    let _ = websocket.close(None).await;
+                                    return;
+                                }
+                                Control::RetryNow => {}
+                            }
+                        }
                     }
                 }
             }
@@ -109,6 +213,108 @@ pub fn connect(access: String, pass: String) -> impl Stream<Item = Event> {
     })
 }
 
+/// Spaces out the edits sent after the server said this client edits too fast
+struct Pacer {
+    // Minimum gap between edits, and when that stops applying
+    pacing: Option<(tokio::time::Duration, tokio::time::Instant)>,
+    last_edit_sent: tokio::time::Instant,
+}
+
+impl Pacer {
+    fn new(now: tokio::time::Instant) -> Self {
+        Self {
+            pacing: None,
+            last_edit_sent: now,
+        }
+    }
+
+    fn pace(&mut self, interval: tokio::time::Duration, now: tokio::time::Instant) {
+        self.pacing = Some((interval, now + PACING_PERIOD));
+    }
+
+    /// When an edit ready at `now` may be sent
+    fn next_edit(&mut self, now: tokio::time::Instant) -> tokio::time::Instant {
+        let send_at = match self.pacing {
+            Some((interval, until)) if now < until => now.max(self.last_edit_sent + interval),
+            Some(_) => {
+                self.pacing = None;
+                now
+            }
+            None => now,
+        };
+        self.last_edit_sent = send_at;
+        send_at
+    }
+}
+
+/// Websocket upgrade request for a session. Passwordless sessions get no credential at all
+/// rather than an empty one.
+fn connect_request(
+    address: &str,
+    secure: bool,
+    access: &str,
+    pass: &str,
+    room: &str,
+) -> Request<()> {
+    let scheme = if secure { "wss" } else { "ws" };
+    let url = if room.is_empty() {
+        format!("{}://{}/{}", scheme, address, access)
+    } else {
+        format!("{}://{}/{}/{}", scheme, address, access, room)
+    };
+
+    let mut request = Request::builder().uri(url);
+    if !pass.is_empty() {
+        request = request.header("AUTHORIZATION", pass);
+    }
+    request
+        .header("sec-websocket-key", "foo")
+        .header("upgrade", "websocket")
+        .header("host", "server.example.com")
+        .header("connection", "upgrade")
+        .header("sec-websocket-version", 13)
+        .body(())
+        .unwrap()
+}
+
+/// Whether a request failed because nothing answered at the address, as opposed to the server
+/// answering in a way the client would not accept
+fn is_unreachable(err: &reqwest::Error) -> bool {
+    let mut source = err.source();
+    while let Some(cause) = source {
+        if let Some(io_err) = cause.downcast_ref::<io::Error>() {
+            return matches!(
+                io_err.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::NotFound
+            );
+        }
+        source = cause.source();
+    }
+    err.is_timeout()
+}
+
+/// Sleeps out the retry delay, returning early if a retry is requested. Returns true when the
+/// stream is asked to stop instead.
+async fn wait_for_retry(control: &mut mpsc::Receiver<Control>) -> bool {
+    let mut delay = Box::pin(tokio::time::sleep(RETRY_DELAY)).fuse();
+
+    loop {
+        futures::select! {
+            _ = delay => return false,
+            command = control.select_next_some() => {
+                match command {
+                    Control::RetryNow => return false,
+                    Control::Stop => return true,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 enum State {
@@ -116,30 +322,93 @@ enum State {
     Connected(
         // Simply a connection to the websocket
         async_tungstenite::WebSocketStream<async_tungstenite::tokio::ConnectStream>,
-        mpsc::Receiver<Message>,
+        mpsc::UnboundedReceiver<Message>,
     ),
 }
 
 #[derive(Debug, Clone)]
 pub enum Event {
+    Ready(Controller),
     Connected(Connection),
     Disconnected,
     MessageReceived(Message),
     ServerDown,
+    // The session is secure but its certificate could not be verified
+    UntrustedCertificate,
     IncorrectPassword, //Add a more granular variant that maps whether there's a success or failure
 }
 
 #[derive(Debug, Clone)]
-pub struct Connection(mpsc::Sender<Message>);
+pub struct Connection(mpsc::UnboundedSender<Message>);
 
 impl Connection {
-    pub fn send(&mut self, message: Message) {
+    /// Queues a message for the server, failing only once the connection has closed
+    pub fn send(&mut self, message: Message) -> Result<(), String> {
         self.0
-            .try_send(message)
-            .expect("Send message to echo server");
+            .unbounded_send(message)
+            .map_err(|_| "Connection closed.".to_string())
     }
     pub fn close(&mut self) {
-        self.send(Message::CloseConnection);
+        // Nothing is left to close if the connection is already gone
+        let _ = self.send(Message::CloseConnection);
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Control {
+    RetryNow,
+    Pace(tokio::time::Duration),
+    Resync,
+    Stop,
+}
+
+/// Handle for steering a running `connect` stream
+#[derive(Debug, Clone)]
+pub struct Controller(mpsc::Sender<Control>);
+
+impl Controller {
+    /// Skips the remaining retry delay and reconnects immediately
+    pub fn retry_now(&mut self) {
+        // A full channel already has a retry pending
+        let _ = self.0.try_send(Control::RetryNow);
+    }
+
+    /// Spaces out the edits sent over the next few seconds by at least `interval`
+    pub fn pace(&mut self, interval: tokio::time::Duration) {
+        let _ = self.0.try_send(Control::Pace(interval));
+    }
+
+    /// Fetches the server's copy of the document again by reconnecting
+    pub fn resync(&mut self) {
+        let _ = self.0.try_send(Control::Resync);
+    }
+
+    /// Closes the connection and ends the stream, without reconnecting
+    pub fn stop(&mut self) {
+        let _ = self.0.try_send(Control::Stop);
+    }
+}
+
+#[cfg(test)]
+impl Connection {
+    /// A connection that has already closed, so nothing sent over it arrives
+    pub(crate) fn closed() -> Self {
+        let (sender, _) = mpsc::unbounded();
+        Self(sender)
+    }
+}
+
+#[cfg(test)]
+impl Controller {
+    /// A controller with no connection behind it, along with a check of whether a resync was
+    /// asked for since the check last ran
+    pub(crate) fn detached() -> (Self, impl FnMut() -> bool) {
+        let (sender, mut receiver) = mpsc::channel(8);
+        let resync_requested = move || {
+            std::iter::from_fn(|| receiver.try_next().ok().flatten())
+                .any(|control| matches!(control, Control::Resync))
+        };
+        (Self(sender), resync_requested)
     }
 }
 
@@ -191,3 +460,202 @@ impl<'a> text::IntoFragment<'a> for &'a Message {
         text::Fragment::Borrowed(self.as_str())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration, Instant};
+
+    #[test]
+    fn host_and_port_fields_make_an_address() {
+        assert_eq!(server_address("", " ").unwrap(), "0.0.0.0:8080");
+        assert_eq!(
+            server_address(" 192.168.1.5 ", "9000").unwrap(),
+            "192.168.1.5:9000"
+        );
+        assert_eq!(server_address("::1", "65535").unwrap(), "[::1]:65535");
+        assert_eq!(server_address("[::1]", "").unwrap(), "[::1]:8080");
+        assert_eq!(server_address("notes.local", "1").unwrap(), "notes.local:1");
+
+        for port in ["0", "65536", "-1", "http", "80 80"] {
+            assert!(server_address("", port).is_err(), "{port}");
+        }
+        for host in ["http://example.com", "two words"] {
+            assert!(server_address(host, "").is_err(), "{host}");
+        }
+    }
+
+    #[test]
+    fn edits_go_out_right_away_until_throttled() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(start);
+        assert_eq!(pacer.next_edit(start), start);
+        assert_eq!(pacer.next_edit(start), start);
+    }
+
+    #[test]
+    fn a_throttled_client_paces_its_next_edits() {
+        let interval = tokio::time::Duration::from_millis(100);
+        let start = Instant::now();
+        let mut pacer = Pacer::new(start);
+        pacer.pace(interval, start);
+
+        // A burst of edits is spread out, the first one only waiting out the gap
+        assert_eq!(pacer.next_edit(start), start + interval);
+        assert_eq!(pacer.next_edit(start), start + interval * 2);
+        assert_eq!(pacer.next_edit(start), start + interval * 3);
+
+        // Edits already spaced further apart are not held back
+        let later = start + interval * 10;
+        assert_eq!(pacer.next_edit(later), later);
+
+        // Pacing stops once the period runs out
+        let after = start + PACING_PERIOD;
+        assert_eq!(pacer.next_edit(after), after);
+        assert_eq!(pacer.next_edit(after), after);
+    }
+
+    #[tokio::test]
+    async fn retrying_now_cuts_the_delay_short() {
+        let (sender, mut control) = mpsc::channel(10);
+        Controller(sender).retry_now();
+
+        let stopped = timeout(RETRY_DELAY / 4, wait_for_retry(&mut control)).await;
+        assert_eq!(stopped, Ok(false));
+    }
+
+    #[tokio::test]
+    async fn stopping_ends_the_wait() {
+        let (sender, mut control) = mpsc::channel(10);
+        let mut controller = Controller(sender);
+        controller.pace(tokio::time::Duration::from_millis(10));
+        controller.stop();
+
+        let stopped = timeout(RETRY_DELAY / 4, wait_for_retry(&mut control)).await;
+        assert_eq!(stopped, Ok(true));
+    }
+
+    #[tokio::test]
+    async fn without_a_request_the_full_delay_passes() {
+        let (_sender, mut control) = mpsc::channel(10);
+        let started = Instant::now();
+        assert!(!wait_for_retry(&mut control).await);
+        assert!(started.elapsed() >= RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn a_burst_of_edits_is_queued_rather_than_dropped() {
+        use crate::server::{
+            tests::{free_address, host_on},
+            Insertion, SessionLimits,
+        };
+
+        const EDITS: usize = 250;
+        let limits = SessionLimits {
+            edits_per_second: 10_000,
+            ..SessionLimits::default()
+        };
+        let host = host_on(free_address(), limits).await.unwrap();
+        let mut events = Box::pin(connect(
+            host.address.clone(),
+            false,
+            String::from("edit"),
+            String::new(),
+            String::new(),
+            String::new(),
+        ));
+        let mut connection = loop {
+            match timeout(Duration::from_secs(5), events.next()).await {
+                Ok(Some(Event::Connected(connection))) => break connection,
+                Ok(Some(_)) => continue,
+                other => panic!("never connected: {:?}", other.map(|event| event.is_some())),
+            }
+        };
+
+        // More edits than the old bounded channel held, queued before any is sent
+        for _ in 0..EDITS {
+            let insert = ProtocolMessage::Insert(Insertion::new(0, 0, String::from("x")));
+            connection.send(Message::User(insert.to_ws_text())).unwrap();
+        }
+        let sending = tokio::spawn(async move { while events.next().await.is_some() {} });
+
+        let all_applied = timeout(Duration::from_secs(10), async {
+            while host.document.lock().await.byte_len() < EDITS {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        sending.abort();
+        assert!(all_applied.is_ok());
+        assert_eq!(host.document.lock().await.byte_len(), EDITS);
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn stopping_ends_the_stream_between_retries() {
+        // Nothing listens there, so the stream keeps retrying until it is stopped
+        let mut events = Box::pin(connect(
+            crate::server::tests::free_address(),
+            false,
+            String::from("edit"),
+            String::new(),
+            String::new(),
+            String::new(),
+        ));
+        let Ok(Some(Event::Ready(mut controller))) =
+            timeout(Duration::from_secs(5), events.next()).await
+        else {
+            panic!("the stream never became ready");
+        };
+        controller.stop();
+
+        let ended = timeout(Duration::from_secs(5), async {
+            while events.next().await.is_some() {}
+        })
+        .await;
+        assert!(ended.is_ok());
+    }
+
+    #[tokio::test]
+    async fn an_unverifiable_certificate_is_reported_as_untrusted() {
+        let host = crate::server::tests::host_with_tls().await.unwrap();
+        let mut events = Box::pin(connect(
+            host.address.clone(),
+            true,
+            String::from("edit"),
+            String::new(),
+            String::new(),
+            String::new(),
+        ));
+
+        let reported = timeout(Duration::from_secs(10), async {
+            loop {
+                match events.next().await {
+                    Some(Event::UntrustedCertificate) => return true,
+                    Some(Event::ServerDown | Event::Connected(_)) | None => return false,
+                    Some(_) => continue,
+                }
+            }
+        })
+        .await;
+        assert_eq!(reported, Ok(true));
+        host.server.shutdown().await;
+    }
+
+    #[test]
+    fn a_secure_session_is_joined_over_wss() {
+        let request = connect_request("127.0.0.1:8080", true, "edit", "", "notes");
+        assert_eq!(request.uri(), "wss://127.0.0.1:8080/edit/notes");
+    }
+
+    #[test]
+    fn an_empty_password_sends_no_authorization_header() {
+        let request = connect_request("127.0.0.1:8080", false, "read", "", "");
+        assert_eq!(request.uri(), "ws://127.0.0.1:8080/read");
+        assert!(request.headers().get("authorization").is_none());
+
+        let request = connect_request("127.0.0.1:8080", true, "edit", "secret", "notes");
+        assert_eq!(request.uri(), "wss://127.0.0.1:8080/edit/notes");
+        assert_eq!(request.headers()["authorization"], "secret");
+    }
+}
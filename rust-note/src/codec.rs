@@ -0,0 +1,102 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::fmt;
+
+// Everything but the characters URLs never need escaped
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Conversions that can be applied to the selected text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Base64Encode,
+    Base64Decode,
+    UrlEncode,
+    UrlDecode,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 4] = [
+        Codec::Base64Encode,
+        Codec::Base64Decode,
+        Codec::UrlEncode,
+        Codec::UrlDecode,
+    ];
+
+    /// Converts `text`, or says why it cannot be decoded
+    pub fn apply(self, text: &str) -> Result<String, String> {
+        match self {
+            Codec::Base64Encode => Ok(STANDARD.encode(text)),
+            Codec::Base64Decode => {
+                let bytes = STANDARD
+                    .decode(text.trim())
+                    .map_err(|_| "The selection is not valid base64.".to_string())?;
+                String::from_utf8(bytes)
+                    .map_err(|_| "The selection decodes to binary data, not text.".to_string())
+            }
+            Codec::UrlEncode => Ok(utf8_percent_encode(text, URL_COMPONENT).to_string()),
+            Codec::UrlDecode => percent_decode_str(text)
+                .decode_utf8()
+                .map(String::from)
+                .map_err(|_| "The selection does not decode to valid text.".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            Codec::Base64Encode => "Base64 encode",
+            Codec::Base64Decode => "Base64 decode",
+            Codec::UrlEncode => "URL encode",
+            Codec::UrlDecode => "URL decode",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: [&str; 5] = [
+        "",
+        "token",
+        "a b&c=d/e?f#g",
+        "héllo, 世界 🦀",
+        "line one\nline two\t+%",
+    ];
+
+    #[test]
+    fn encoded_text_decodes_back() {
+        for sample in SAMPLES {
+            let base64 = Codec::Base64Encode.apply(sample).unwrap();
+            assert_eq!(Codec::Base64Decode.apply(&base64).unwrap(), sample);
+
+            let url = Codec::UrlEncode.apply(sample).unwrap();
+            assert!(url
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.~%".contains(&b)));
+            assert_eq!(Codec::UrlDecode.apply(&url).unwrap(), sample);
+        }
+    }
+
+    #[test]
+    fn known_encodings() {
+        assert_eq!(Codec::Base64Encode.apply("hello").unwrap(), "aGVsbG8=");
+        assert_eq!(Codec::Base64Decode.apply(" aGVsbG8=\n").unwrap(), "hello");
+        assert_eq!(Codec::UrlEncode.apply("a b/é").unwrap(), "a%20b%2F%C3%A9");
+        assert_eq!(Codec::UrlDecode.apply("a+b%20c").unwrap(), "a+b c");
+    }
+
+    #[test]
+    fn undecodable_selections_are_errors() {
+        assert!(Codec::Base64Decode.apply("not base64!").is_err());
+        // Valid base64 of bytes that are not UTF-8
+        assert!(Codec::Base64Decode.apply("/w==").is_err());
+        assert!(Codec::UrlDecode.apply("%FF%FE").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{privacy::DisplayPath, settings::Settings};
+use std::path::{Path, PathBuf};
+
+// Stands in for anything left out of a report
+const REDACTED: &str = "<redacted>";
+
+/// Plain text summary of the app's state to attach to a bug report. Paths are left out, and
+/// any of `secrets` found in the operation log is blanked.
+pub fn report(settings: &Settings, connection: &str, op_log: &str, secrets: &[String]) -> String {
+    let mut settings = serde_json::to_value(settings).unwrap_or_default();
+    for field in ["last_file", "export_css"] {
+        if let Some(path) = settings.get_mut(field).filter(|path| !path.is_null()) {
+            *path = serde_json::Value::from(REDACTED);
+        }
+    }
+    let settings = serde_json::to_string_pretty(&settings).unwrap_or_default();
+
+    let mut op_log = op_log.to_string();
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        op_log = op_log.replace(secret.as_str(), REDACTED);
+    }
+    let op_log = if op_log.is_empty() {
+        String::from("(operation log disabled or empty)\n")
+    } else {
+        op_log
+    };
+
+    format!(
+        "rust-note {}\n\
+         OS: {} ({})\n\
+         Connection: {}\n\
+         \n\
+         Settings:\n{}\n\
+         \n\
+         Operation log:\n{}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        if connection.is_empty() {
+            "not in a session"
+        } else {
+            connection
+        },
+        settings,
+        op_log
+    )
+}
+
+pub async fn export(contents: String) -> Result<PathBuf, String> {
+    let path = rfd::AsyncFileDialog::new()
+        .set_file_name("rust-note-report.txt")
+        .save_file()
+        .await
+        .as_ref()
+        .map(rfd::FileHandle::path)
+        .map(Path::to_owned)
+        .ok_or_else(|| "Report dialog was closed without selection.".to_string())?;
+
+    tokio::fs::write(&path, contents)
+        .await
+        .map_err(|err| format!("Failed to write problem report: {}", err))?;
+
+    println!("Problem report saved to: {}", DisplayPath(&path));
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_report_leaves_out_paths_and_passwords() {
+        let settings = Settings {
+            last_file: Some(PathBuf::from("/home/someone/diary.md")),
+            export_css: Some(PathBuf::from("/home/someone/styles/print.css")),
+            ..Settings::default()
+        };
+        let op_log = "{\"text\":\"hunter2\"}\n";
+        let secrets = [String::new(), String::from("hunter2")];
+
+        let report = report(&settings, "Host: 127.0.0.1:8080", op_log, &secrets);
+        assert!(!report.contains("/home/someone"), "{report}");
+        assert!(!report.contains("hunter2"), "{report}");
+        assert!(report.contains("\"last_file\": \"<redacted>\""), "{report}");
+        assert!(report.contains("{\"text\":\"<redacted>\"}"), "{report}");
+        assert!(
+            report.contains("Connection: Host: 127.0.0.1:8080\n"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn a_report_notes_what_is_missing() {
+        let report = report(&Settings::default(), "", "", &[]);
+        assert!(
+            report.contains("Connection: not in a session\n"),
+            "{report}"
+        );
+        assert!(report.contains("\"last_file\": null"), "{report}");
+        assert!(
+            report.ends_with("(operation log disabled or empty)\n"),
+            "{report}"
+        );
+    }
+}
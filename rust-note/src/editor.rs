@@ -1,17 +1,31 @@
 use crate::{
     client,
-    server::{start_server, Document, Operation, UserId, Users},
+    codec::Codec,
+    diagnostics,
+    frontmatter::{self, Metadata},
+    html, math,
+    oplog::{self, OpLog},
+    plaintext,
+    privacy::DisplayPath,
+    protocol::ProtocolMessage,
+    recovery::Recovery,
+    server::{
+        start_server, Deletion, Document, Insertion, Operation, Participants, Room, ServerHandle,
+        SessionLimits, UserId, Users, DEFAULT_ROOM, HOST_ADDR, MAX_EDITS_PER_SECOND,
+    },
+    settings::Settings,
+    undo::{self, Replay, UndoHistory},
     widgets,
 };
 use futures::{channel::mpsc, SinkExt, Stream};
 use iced::{
-    highlighter, keyboard, mouse, stream,
+    keyboard, mouse, stream, task,
     widget::{
         button,
         canvas::{self, Frame, Path as icedPath},
-        center, column, container, horizontal_space, markdown, mouse_area, opaque, radio, row,
-        scrollable, stack, text, text_editor, text_input, toggler, Canvas, Container, Stack, Text,
-        TextEditor,
+        center, column, container, horizontal_space, markdown, mouse_area, opaque, pick_list,
+        progress_bar, radio, row, scrollable, stack, text, text_editor, text_input, toggler,
+        Canvas, Container, Stack, Text, TextEditor,
     },
     window, Alignment, Color, Element, Length, Pixels, Point, Rectangle, Renderer, Size,
     Subscription, Task, Theme,
@@ -19,34 +33,149 @@ use iced::{
 use iced_aw::{TabLabel, Tabs};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use std::{
+    collections::{HashMap, HashSet},
     ffi, fmt,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    hash::{DefaultHasher, Hash, Hasher},
+    ops::Range,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::sync::Mutex;
-use tokio::task::JoinHandle;
 use widgets::format_bar::{FormatBar, TextStyle, DEFAULT_FONT_SIZE};
-use widgets::menubar::{load_file, open_file, save_file, MenuBar, MenuMessage}; // For form parameters
+use widgets::menubar::{
+    default_highlight_theme, load_file, open_file, open_folder, open_url, recent_list, save_file,
+    MenuBar, MenuMessage, OPEN_CANCELLED, SAVE_CANCELLED,
+}; // For form parameters
+
+// Modifier names shown with the hotkeys. iced's `command()` is cmd on macOS and ctrl elsewhere.
+const COMMAND_KEY: &str = if cfg!(target_os = "macos") {
+    "cmd"
+} else {
+    "ctrl"
+};
+const ALT_KEY: &str = if cfg!(target_os = "macos") {
+    "option"
+} else {
+    "alt"
+};
 
 const BOLD_HOTKEY: &str = "b";
 const ITALIC_HOTKEY: &str = "i";
-const STRIKETHROUGH_HOTKEY: &str = "f";
+const STRIKETHROUGH_HOTKEY: &str = "x";
+const FIND_HOTKEY: &str = "f";
+const HIGHLIGHT_HOTKEY: &str = "h";
+const MATH_HOTKEY: &str = "m";
 const SHORTCUT_PALETTE_HOTKEY: &str = "p";
 const SESSION_MODAL_HOTKEY: &str = "n";
+const SWITCH_ACCESS_HOTKEY: &str = "r";
 const OPEN_FILE_HOTKEY: &str = "o";
 const SAVE_FILE_HOTKEY: &str = "s";
+const PREVIEW_FOCUS_HOTKEY: &str = "e";
+const SORT_LINES_HOTKEY: &str = "l";
+const UNIQUE_LINES_HOTKEY: &str = "u";
+const COMMENT_HOTKEY: &str = "/";
+const UNDO_HOTKEY: &str = "z";
+const MATCHING_BRACKET_HOTKEY: &str = "\\";
+// Brackets that go to matching bracket pairs up, opening one first
+const BRACKET_PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+// How far one arrow key press scrolls the focused preview
+const PREVIEW_SCROLL_STEP: f32 = 40.0;
+
+const LINE_HEIGHT: f32 = 21.0;
+
+// Shown in the editor while a new document is empty
+const EDITOR_PLACEHOLDER: &str = "Document is empty, start typing";
+
+// Shown when a session is started before the server worker is ready
+const NOT_READY_ERROR: &str = "Still getting ready to host, try again in a moment.";
+
+// How long the editing-too-fast notice stays up after the server throttles this client
+const THROTTLE_NOTICE: Duration = Duration::from_secs(5);
+
+// Choices offered for the editor padding and margin settings
+const SPACING_OPTIONS: [u16; 6] = [0, 5, 10, 20, 40, 80];
+
+// How often the shared document is compared with the editor, once edits have settled
+const CONSISTENCY_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+// How long edits have to settle before the preview and word count are worked out again
+const PREVIEW_DEBOUNCE: Duration = Duration::from_millis(100);
+
+// Opacity of the current line band in high contrast mode, where the usual tints are too faint
+const HIGH_CONTRAST_BAND_ALPHA: f32 = 0.35;
+
+// Contrast against the background below which a picked cursor color is flagged as hard to see
+const MIN_CURSOR_CONTRAST: f32 = 3.0;
+
+// Width of the strips marking the part of the document collaborators are looking at
+const VIEWPORT_MARKER_WIDTH: f32 = 4.0;
+
+// Width and thickness of the ticks marking collaborators' cursors along the right edge
+const ACTIVITY_TICK_WIDTH: f32 = 10.0;
+const ACTIVITY_TICK_HEIGHT: f32 = 3.0;
+
+// Choices for how many seconds cursor names stay up, 0 keeps them up
+const CURSOR_LABEL_FADE_OPTIONS: [u64; 5] = [0, 1, 3, 5, 10];
+
+// Choices for how many of this user's edits can be undone
+const UNDO_DEPTH_OPTIONS: [usize; 4] = [50, 100, 500, 1000];
+const UNDO_MEMORY_OPTIONS: [usize; 4] = [4, 16, 64, 256];
+
+// Choices for the limits a host sets on a session, in megabytes and edits per second
+const DOCUMENT_SIZE_OPTIONS: [usize; 4] = [1, 4, 16, 64];
+const EDIT_RATE_OPTIONS: [u32; 4] = [10, 30, 50, 100];
+
+// How long a cursor name takes to fade out once its delay is up
+const CURSOR_LABEL_FADE: Duration = Duration::from_millis(500);
+
+// Rough advance of one character in the editor's default font, for placing things over the text
+const APPROX_CHAR_WIDTH: f32 = 8.5;
+
+// The status row always has room for this much of the file name
+const STATUS_NAME_MIN_WIDTH: usize = 12;
+
+// Columns a tab advances to a multiple of
+const TAB_WIDTH: usize = 4;
+
+// Lines longer than this many bytes make wrapping and editing slow, so opening them prompts
+const LONG_LINE_WARNING: usize = 10_000;
+
+// Choices offered for the column long lines are wrapped at
+const HARD_WRAP_COLUMNS: [usize; 4] = [80, 100, 120, 160];
+
+// Choices offered for the interface scale, in percent
+const UI_SCALE_OPTIONS: [u16; 5] = [100, 125, 150, 175, 200];
+
+// Words ending in a period that do not end a sentence
+const ABBREVIATIONS: [&str; 12] = [
+    "mr.", "mrs.", "ms.", "dr.", "prof.", "st.", "vs.", "etc.", "e.g.", "i.e.", "approx.", "no.",
+];
 
 #[derive(Clone)]
 pub struct SessionModal {
     pub session_password_input: String,
     pub write_password_input: String,
     pub read_password_input: String,
+    pub show_passwords: bool,
+    pub room_input: String,
+    pub display_name_input: String,
+    pub host_input: String,
+    pub port_input: String,
+    pub secure: bool,
+    pub cert_path_input: String,
+    pub key_path_input: String,
     pub file_path_input: String,
+    // Result of the last `validate_file`, so rendering never touches the filesystem
+    pub file_valid: bool,
+    pub idle_lock_input: String,
+    pub enable_metrics: bool,
+    pub limits: SessionLimits,
     pub file_error: String,
     pub session_join_error: String,
+    pub session_start_error: String,
     pub session_selection: Option<SessionType>,
 }
 
@@ -56,21 +185,67 @@ impl Default for SessionModal {
             session_password_input: String::new(),
             write_password_input: String::new(),
             read_password_input: String::new(),
+            show_passwords: false,
+            room_input: String::new(),
+            display_name_input: String::new(),
+            host_input: String::new(),
+            port_input: String::new(),
+            secure: false,
+            cert_path_input: String::new(),
+            key_path_input: String::new(),
             file_path_input: String::new(),
+            file_valid: true,
+            idle_lock_input: String::new(),
+            enable_metrics: false,
+            limits: SessionLimits::default(),
             file_error: String::new(),
             session_join_error: String::new(),
+            session_start_error: String::new(),
             session_selection: Some(SessionType::Read),
         }
     }
 }
 
 impl SessionModal {
+    /// Address to host on or join, from the host and port fields
+    pub fn server_address(&self) -> Result<String, String> {
+        client::server_address(&self.host_input, &self.port_input)
+    }
+
+    /// Certificate and key files to host a secure session with, `None` when it is not secure
+    pub fn tls_files(&self) -> Result<Option<(PathBuf, PathBuf)>, String> {
+        if !self.secure {
+            return Ok(None);
+        }
+
+        let cert = self.cert_path_input.trim();
+        let key = self.key_path_input.trim();
+        if cert.is_empty() || key.is_empty() {
+            return Err("A secure session needs a certificate and a key file.".to_string());
+        }
+        Ok(Some((PathBuf::from(cert), PathBuf::from(key))))
+    }
+
+    pub fn validate_room(&self) -> bool {
+        let room = self.room_input.trim();
+        room.is_empty() || Room::is_valid_name(room)
+    }
+
     pub fn validate_password(&self) -> bool {
         !(self.read_password_input.is_empty() && self.write_password_input.is_empty())
     }
 
+    /// Idle period after which the host locks the session, `None` when disabled
+    pub fn idle_lock_timeout(&self) -> Option<Duration> {
+        match self.idle_lock_input.trim().parse::<u64>() {
+            Ok(minutes) if minutes > 0 => Some(Duration::from_secs(minutes * 60)),
+            _ => None,
+        }
+    }
+
+    /// Checks the file path against the filesystem, call it only when the path changes
     pub fn validate_file(&mut self) -> bool {
-        if !&self.file_path_input.is_empty() {
+        self.file_valid = if !&self.file_path_input.is_empty() {
             if self.file_path_input.ends_with(".md")
                 && std::path::Path::new(&self.file_path_input).exists()
             {
@@ -83,30 +258,118 @@ impl SessionModal {
         } else {
             self.file_error = "".to_string(); // File is optional
             true
-        }
+        };
+        self.file_valid
     }
 }
 
 pub struct Editor {
     content: text_editor::Content,
+    // Byte offset where each line starts, filled in as far as edits have needed it
+    line_offsets: Vec<usize>,
+    // Length of the longest line of a file just opened, while offering to wrap it
+    long_line_prompt: Option<usize>,
+    line_height: f32,
     document: Arc<Mutex<Document>>,
     is_dirty: Arc<Mutex<bool>>,
     cursor_marker: CursorMarker,
     is_moved: Arc<Mutex<bool>>,
+    is_locked: Arc<Mutex<bool>>,
+    session_locked: bool,
+    idle_lock_timeout: Option<Duration>,
+    last_host_activity: Instant,
+    // Last edit or remote update of the buffer, so a document still catching up with it is not
+    // taken as diverged
+    last_document_change: Instant,
     menubar: MenuBar,
     format_bar: FormatBar,
     file: Option<PathBuf>,
     theme: Theme,
     markdown_text: Vec<markdown::Item>,
+    metadata: Option<Metadata>,
     markdown_settings: markdown::Settings,
     modal_content: SessionModal,
     markdown_preview_open: bool,
+    // Set for files that are not markdown, which turns off formatting and the preview
+    plain_text: bool,
+    preview_focused: bool,
+    preview_link: Option<usize>,
+    settings: Settings,
+    // Where the settings are saved
+    config_dir: PathBuf,
+    window_width: f32,
+    window_height: f32,
+    op_log: Arc<std::sync::Mutex<OpLog>>,
+    undo: Arc<std::sync::Mutex<UndoHistory>>,
+    scroll_offset: usize,
     shortcut_palette_open: bool,
+    // Find panel, with the replace field shown only when asked for
+    find_open: bool,
+    replace_open: bool,
+    find_query: String,
+    replace_input: String,
+    find_ignore_case: bool,
+    find_whole_word: bool,
+    find_matches: Vec<FindMatch>,
+    // Match selected by the last `FindNext`
+    find_current: Option<usize>,
     session_modal_open: bool,
+    recovered_text: Option<String>,
+    recovery_pending: bool,
+    // Hashes of the text last written to the open file and to the recovery file, so unchanged
+    // text is not written again
+    saved_hash: Option<u64>,
+    recovery_hash: Option<u64>,
+    recovery: Recovery,
+    // Edits have been made since the preview was last parsed
+    preview_pending: bool,
+    word_count: usize,
+    unsaved_changes: bool,
+    join_confirm_open: bool,
+    leave_confirm_open: bool,
+    // Leaving waits on a copy of the document being saved
+    leave_after_save: bool,
+    access_prompt_open: bool,
+    access_password_input: String,
+    // Passwords that got this client into a session, by access level
+    known_access_passwords: HashMap<SessionType, String>,
+    // Access level and password to return to if switching access is refused
+    access_fallback: Option<(SessionType, String)>,
+    unsynced_edits: usize,
+    // Revision of the last copy the host sent, and of the last one put into the document
+    received_revision: u64,
+    applied_revision: u64,
+    host_open_pending: Option<(Option<PathBuf>, Arc<String>)>,
+    // Contents of the open file, read again while it has unsaved changes
+    reopen_pending: Option<Arc<String>>,
+    // Folder of notes shown in the sidebar, with the notes found in it
+    workspace_root: Option<PathBuf>,
+    workspace_files: Vec<PathBuf>,
+    // Note picked in the sidebar while the open file has unsaved changes
+    workspace_open_pending: Option<PathBuf>,
+    start_confirm_open: bool,
+    // Words in the document when it was opened, to tell how many were written since
+    opened_word_count: usize,
+    save_status: SaveStatus,
+    // The open or save dialog being shown, so closing the window can abandon it
+    file_dialog: Option<task::Handle>,
+    error_banner: Option<String>,
+    reconnecting: bool,
+    connection_controller: Option<client::Controller>,
+    throttled_until: Option<Instant>,
     active_tab: TabId,
-    server_thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+    server_thread: Arc<Mutex<Option<ServerHandle>>>,
     users: Arc<Mutex<Users>>,
-    user_cursors: Vec<CursorMarker>,
+    user_cursors: Vec<(UserId, CursorMarker)>,
+    // When each collaborator's cursor last moved, for fading out their name
+    cursor_moved: HashMap<UserId, Instant>,
+    participants: Vec<(UserId, SessionType)>,
+    // Bumped on leaving a session, so late events from its connection are told apart
+    session_generation: u64,
+    // Names the participants gave, shown by their cursors
+    participant_names: HashMap<UserId, String>,
+    host_participant: Option<UserId>,
+    participants_open: bool,
     read_password: Option<String>,
     edit_password: Option<String>,
     joined_session: bool,
@@ -115,6 +378,54 @@ pub struct Editor {
     client_state: State,
     id: Option<UserId>, // Id for collab sessions
     server_worker: Option<mpsc::Sender<Input>>,
+    // Documents open in tabs. The active one is `None`, as its document is the one held above
+    tabs: Vec<Option<DocumentTab>>,
+    current_tab: usize,
+    // Limits of the session hosted or joined, as the server advertised them
+    session_limits: Option<SessionLimits>,
+    // Tab asked to be closed while it has unsaved changes
+    close_tab_pending: Option<usize>,
+}
+
+/// A document open in a tab other than the active one
+struct DocumentTab {
+    content: text_editor::Content,
+    file: Option<PathBuf>,
+    plain_text: bool,
+    unsaved_changes: bool,
+    saved_hash: Option<u64>,
+    undo: Arc<std::sync::Mutex<UndoHistory>>,
+    scroll_offset: usize,
+    opened_word_count: usize,
+}
+
+impl DocumentTab {
+    /// An empty, untitled document
+    fn new(settings: &Settings) -> Self {
+        Self {
+            content: text_editor::Content::new(),
+            file: None,
+            plain_text: false,
+            unsaved_changes: false,
+            saved_hash: None,
+            undo: Arc::new(std::sync::Mutex::new(undo_history(settings))),
+            scroll_offset: 0,
+            opened_word_count: 0,
+        }
+    }
+
+    /// Name shown on the tab, starred while it has unsaved changes
+    fn label(file: Option<&PathBuf>, unsaved_changes: bool) -> String {
+        let name = file.and_then(|path| path.file_name()).map_or_else(
+            || String::from("Untitled"),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        if unsaved_changes {
+            format!("{}*", name)
+        } else {
+            name
+        }
+    }
 }
 
 enum State {
@@ -122,16 +433,55 @@ enum State {
     Connected(client::Connection),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Progress of the last save, cleared by the next edit
+enum SaveStatus {
+    Idle,
+    Saving,
+    Saved(SystemTime),
+    Failed,
+}
+
+impl fmt::Display for SaveStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStatus::Idle => Ok(()),
+            SaveStatus::Saving => f.write_str("Saving\u{2026}"),
+            SaveStatus::Saved(at) => {
+                // Without a time zone database the time is shown in UTC
+                let seconds = at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs());
+                let minutes = seconds / 60 % 60;
+                let hours = seconds / 3600 % 24;
+                write!(f, "Saved \u{2713} ({hours:02}:{minutes:02} UTC)")
+            }
+            SaveStatus::Failed => f.write_str("Save failed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub enum SessionType {
+    #[default]
     Read,
+    Comment,
     Edit,
 }
 
+impl SessionType {
+    fn other(self) -> Self {
+        match self {
+            SessionType::Read | SessionType::Comment => SessionType::Edit,
+            SessionType::Edit => SessionType::Read,
+        }
+    }
+}
+
 impl fmt::Display for SessionType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let value = match self {
             SessionType::Read => "read",
+            SessionType::Comment => "comment",
             SessionType::Edit => "edit",
         };
         write!(f, "{}", value)
@@ -145,27 +495,142 @@ pub enum Message {
     Format(TextStyle),
     LinkClicked(markdown::Url),
     ShowMarkdownPreview(bool),
+    PlainTextToggled(bool),
+    TogglePreviewFocus,
+    PreviewScroll(f32),
+    PreviewNextLink,
+    PreviewOpenLink,
+    AutoCapitalizeToggled(bool),
+    SmartQuotesToggled(bool),
+    LineNumbersToggled(bool),
+    OpLogToggled(bool),
+    ShareViewportToggled(bool),
+    ReopenLastFileToggled(bool),
+    ListPasteToggled(bool),
+    LinkPasteToggled(bool),
+    HighlightFollowsThemeToggled(bool),
+    PlainTextLinkUrlsToggled(bool),
+    RestoreCaret(usize, usize),
+    LineHighlightToggled(bool),
+    ShowWhitespaceToggled(bool),
+    LineHighlightColorSelected(LineHighlightColor),
+    CursorColorSelected(CursorColor),
+    EditorPaddingSelected(u16),
+    EditorMarginSelected(u16),
+    HighContrastToggled(bool),
+    UiScaleSelected(u16),
     NoOp,
     DeleteLine,
     DeleteWord,
+    // Sorts the selected lines, descending when set
+    SortLines(bool),
+    SortCaseSensitiveToggled(bool),
+    // Drops repeated selected lines, anywhere in the selection when set
+    UniqueLines(bool),
+    ToggleComment,
+    GoToMatchingBracket,
+    ConvertSelection(Codec),
+    // Reverses this user's most recent edit, leaving the others' edits since then in place
+    Undo,
+    Redo,
+    UndoStep(Replay, undo::Step),
+    UndoDepthSelected(usize),
+    UndoMemorySelected(usize),
+    UndoSpillToggled(bool),
     ShortcutPaletteToggle,
+    // Opens the find panel, with the replace field when true
+    OpenFind(bool),
+    CloseFind,
+    FindChanged(String),
+    ReplaceChanged(String),
+    FindIgnoreCaseToggled(bool),
+    FindWholeWordToggled(bool),
+    FindNext,
+    ReplaceAll,
+    // Matches still to replace, the last one first
+    ReplaceMatches(Vec<FindMatch>),
     SessionModalToggle,
     SessionPasswordChanged(String),
+    ShowPasswordsToggled,
+    RoomChanged(String),
+    DisplayNameChanged(String),
+    ServerHostChanged(String),
+    ServerPortChanged(String),
+    SwitchAccess,
+    AccessPasswordChanged(String),
+    ConfirmAccessSwitch,
+    CancelAccessSwitch,
     WritePasswordChanged(String),
     ReadPasswordChanged(String),
     FilePathChanged(String),
+    IdleLockChanged(String),
+    MetricsToggled(bool),
+    MaxDocumentSizeSelected(usize),
+    EditRateSelected(u32),
+    SecureSessionToggled(bool),
+    TlsCertPathChanged(String),
+    TlsKeyPathChanged(String),
+    CheckIdle,
+    CheckConsistency,
+    ResyncDocument,
     StartSessionPressed,
+    ConfirmStartSession,
+    CancelStartSession,
+    ConfirmSessionStartToggled(bool),
+    WordGoalChanged(String),
+    ExportStyleSelected(html::ExportStyle),
+    ExportCssChanged(String),
+    WordCountModeSelected(WordCountMode),
     UpdateHostDoc(Document),
-    UpdateHostCursors(Vec<CursorMarker>),
+    SessionStarted(UserId, Document),
+    SessionStartFailed(String),
+    UpdateHostCursors(Vec<(UserId, CursorMarker)>),
+    UpdateHostNames(HashMap<UserId, String>),
+    UpdateHostParticipants(Participants),
+    ParticipantsToggle,
+    CursorLabelFadeSelected(u64),
+    FadeCursorLabels,
     JoinSessionPressed,
     TabSelected(TabId),
-    Echo(client::Event),
+    // Tagged with the session generation the connection belongs to
+    Echo(u64, client::Event),
+    // The host's copy at this revision is now the document, tagged like `Echo`
+    RevisionApplied(u64, u64),
+    // An edit could not be sent as the connection closed under it, tagged like `Echo`
+    EditUnsent(u64),
     RequestClose,
     LeaveSession,
+    ConfirmLeaveSession,
+    SaveCopyAndLeave,
+    CancelLeaveSession,
     SessionClosed,
     SessionTypeRequested(SessionType),
     CloseWindow(iced::window::Id),
+    WindowResized(Size),
     WorkerReady(mpsc::Sender<Input>),
+    RecoveryFound(Option<String>),
+    RestoreRecovery,
+    DiscardRecovery,
+    WriteRecovery,
+    RefreshPreview,
+    DismissError,
+    RetryConnection,
+    ConfirmJoinSession,
+    CancelJoinSession,
+    ConfirmHostOpen,
+    CancelHostOpen,
+    ConfirmReopen,
+    CancelReopen,
+    WorkspaceFileSelected(PathBuf),
+    ConfirmWorkspaceOpen,
+    CancelWorkspaceOpen,
+    HardWrapColumnSelected(usize),
+    WrapLongLines,
+    KeepLongLines,
+    DocumentTabSelected(usize),
+    CloseTab(usize),
+    ConfirmCloseTab,
+    CancelCloseTab,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug, Default)]
@@ -177,12 +642,25 @@ pub enum TabId {
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct CursorMarker {
+    // Distance from the start of the line's text, older clients do not send it
+    #[serde(default)]
+    pub x: f32,
     pub y: f32,
     pub color: (f32, f32, f32),
+    // Line height the marker was measured with, older clients do not send it
+    #[serde(default = "default_line_height")]
+    pub height: f32,
+    /// First and last line on the user's screen, if they share it
+    #[serde(default)]
+    pub viewport: Option<(usize, usize)>,
+}
+
+fn default_line_height() -> f32 {
+    LINE_HEIGHT
 }
 
 impl CursorMarker {
-    pub fn new(y: f32) -> Self {
+    pub fn new(y: f32, height: f32) -> Self {
         let mut rng = rand::thread_rng();
 
         // Generate random RGB values
@@ -190,18 +668,264 @@ impl CursorMarker {
         let g = rng.gen_range(0.0..=1.0);
         let b = rng.gen_range(0.0..=1.0);
         Self {
+            x: 0.0,
             y,
             color: (r, g, b),
+            height,
+            viewport: None,
         }
     }
 
-    pub fn move_cursor(&mut self, y: f32) {
+    pub fn move_cursor(&mut self, (x, y): (f32, f32), height: f32) {
+        self.x = x;
         self.y = y;
+        self.height = height;
+    }
+
+    /// Raises the marker's color to full brightness, keeping its hue, so it stands out against
+    /// a black background
+    pub fn brightened(self) -> Self {
+        let (r, g, b) = self.color;
+        let max = r.max(g).max(b);
+        if max <= 0.0 {
+            return Self {
+                color: (1.0, 1.0, 1.0),
+                ..self
+            };
+        }
+
+        Self {
+            color: (r / max, g / max, b / max),
+            ..self
+        }
+    }
+
+    /// Line of the document the marker sits on
+    pub fn line(&self) -> usize {
+        if self.height > 0.0 {
+            (self.y / self.height).round().max(0.0) as usize
+        } else {
+            0
+        }
+    }
+
+    /// Re-measures a marker from another user's line height in terms of the local one
+    pub fn scaled_to(self, height: f32) -> Self {
+        let line = if self.height > 0.0 {
+            self.y / self.height
+        } else {
+            0.0
+        };
+
+        Self {
+            y: line * height,
+            height,
+            ..self
+        }
     }
 }
 
-impl<Message> canvas::Program<Message> for CursorMarker {
-    // No internal state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LineHighlightColor {
+    #[default]
+    Theme,
+    Accent,
+    Warm,
+}
+
+impl LineHighlightColor {
+    const ALL: [LineHighlightColor; 3] = [
+        LineHighlightColor::Theme,
+        LineHighlightColor::Accent,
+        LineHighlightColor::Warm,
+    ];
+
+    /// Translucent band color derived from the active theme
+    fn to_color(self, theme: &Theme) -> Color {
+        let palette = theme.palette();
+        match self {
+            LineHighlightColor::Theme => Color {
+                a: 0.08,
+                ..palette.text
+            },
+            LineHighlightColor::Accent => Color {
+                a: 0.15,
+                ..palette.primary
+            },
+            LineHighlightColor::Warm => Color::from_rgba(1.0, 0.85, 0.2, 0.15),
+        }
+    }
+}
+
+impl fmt::Display for LineHighlightColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            LineHighlightColor::Theme => "Theme",
+            LineHighlightColor::Accent => "Accent",
+            LineHighlightColor::Warm => "Warm",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// Cursor colors offered to pick from, or a random one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CursorColor {
+    #[default]
+    Random,
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Teal,
+    Blue,
+    Purple,
+    Pink,
+}
+
+impl CursorColor {
+    const ALL: [CursorColor; 9] = [
+        CursorColor::Random,
+        CursorColor::Red,
+        CursorColor::Orange,
+        CursorColor::Yellow,
+        CursorColor::Green,
+        CursorColor::Teal,
+        CursorColor::Blue,
+        CursorColor::Purple,
+        CursorColor::Pink,
+    ];
+
+    fn rgb(self) -> Option<(f32, f32, f32)> {
+        match self {
+            CursorColor::Random => None,
+            CursorColor::Red => Some((0.9, 0.2, 0.2)),
+            CursorColor::Orange => Some((1.0, 0.55, 0.0)),
+            CursorColor::Yellow => Some((0.95, 0.85, 0.1)),
+            CursorColor::Green => Some((0.2, 0.75, 0.3)),
+            CursorColor::Teal => Some((0.0, 0.65, 0.65)),
+            CursorColor::Blue => Some((0.2, 0.45, 0.95)),
+            CursorColor::Purple => Some((0.6, 0.3, 0.9)),
+            CursorColor::Pink => Some((0.95, 0.4, 0.7)),
+        }
+    }
+}
+
+impl fmt::Display for CursorColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            CursorColor::Random => "Random",
+            CursorColor::Red => "Red",
+            CursorColor::Orange => "Orange",
+            CursorColor::Yellow => "Yellow",
+            CursorColor::Green => "Green",
+            CursorColor::Teal => "Teal",
+            CursorColor::Blue => "Blue",
+            CursorColor::Purple => "Purple",
+            CursorColor::Pink => "Pink",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// WCAG contrast ratio between two colors, from 1 for the same color to 21 for black on white
+fn contrast_ratio(a: Color, b: Color) -> f32 {
+    fn luminance(color: Color) -> f32 {
+        let channel = |c: f32| {
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * channel(color.r) + 0.7152 * channel(color.g) + 0.0722 * channel(color.b)
+    }
+
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// How the status row counts words, since Chinese and Japanese text has no spaces to split on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WordCountMode {
+    /// Runs of text between whitespace
+    #[default]
+    Whitespace,
+    /// Every character other than whitespace
+    Characters,
+    /// Each Chinese or Japanese character, plus runs of other text between whitespace
+    Hybrid,
+}
+
+impl WordCountMode {
+    const ALL: [WordCountMode; 3] = [
+        WordCountMode::Whitespace,
+        WordCountMode::Characters,
+        WordCountMode::Hybrid,
+    ];
+
+    fn count(self, text: &str) -> usize {
+        match self {
+            WordCountMode::Whitespace => text.split_whitespace().count(),
+            WordCountMode::Characters => text.chars().filter(|c| !c.is_whitespace()).count(),
+            WordCountMode::Hybrid => {
+                let mut words = 0;
+                let mut in_word = false;
+                for c in text.chars() {
+                    if is_cjk(c) {
+                        words += 1;
+                        in_word = false;
+                    } else if c.is_whitespace() || is_cjk_punctuation(c) {
+                        in_word = false;
+                    } else if !in_word {
+                        words += 1;
+                        in_word = true;
+                    }
+                }
+                words
+            }
+        }
+    }
+}
+
+impl fmt::Display for WordCountMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            WordCountMode::Whitespace => "Words",
+            WordCountMode::Characters => "Characters",
+            WordCountMode::Hybrid => "Words + CJK",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+// Han ideographs and kana, which are written without spaces between words. Korean is left
+// out since it does use spaces.
+fn is_cjk(c: char) -> bool {
+    matches!(
+        c,
+        '\u{3040}'..='\u{30FF}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{20000}'..='\u{2FA1F}'
+    )
+}
+
+// Ideographic spaces, commas and full stops, and full-width forms of ASCII punctuation
+fn is_cjk_punctuation(c: char) -> bool {
+    matches!(c, '\u{3000}'..='\u{303F}' | '\u{FF01}'..='\u{FF0F}')
+}
+
+/// Translucent band drawn over the line that holds the caret
+#[derive(Debug, Clone, Copy)]
+pub struct LineHighlight {
+    pub y: f32,
+    pub height: f32,
+    pub color: Color,
+}
+
+impl<Message> canvas::Program<Message> for LineHighlight {
     type State = ();
 
     fn draw(
@@ -213,128 +937,588 @@ impl<Message> canvas::Program<Message> for CursorMarker {
         _cursor: mouse::Cursor,
     ) -> Vec<canvas::Geometry> {
         let mut frame = Frame::new(renderer, bounds.size());
-        // let offset_x = 2.0; // Offset for padding/margin adjustments
-        // let offset_y = 2.0; // Offset for padding/margin adjustments
 
-        let rectangle = icedPath::rectangle(Point::new(0.0, self.y), Size::new(5.5, 21.0));
-        frame.fill(
-            &rectangle,
-            Color::from_rgb(self.color.0, self.color.1, self.color.2),
+        let band = icedPath::rectangle(
+            Point::new(0.0, self.y),
+            Size::new(bounds.width, self.height),
         );
+        frame.fill(&band, self.color);
         vec![frame.into_geometry()]
     }
 }
 
-impl Editor {
-    pub fn new() -> (Self, Task<Message>) {
-        (
-            Self {
-                content: text_editor::Content::new(),
-                document: Arc::new(Mutex::new(Document::new(String::new()))),
-                is_dirty: Arc::new(Mutex::new(false)),
-                cursor_marker: CursorMarker::new(0.2),
-                is_moved: Arc::new(Mutex::new(false)),
-                menubar: MenuBar::new(),
-                format_bar: FormatBar::new(),
-                file: None,
-                theme: Theme::default(),
-                modal_content: SessionModal::default(),
-                markdown_text: markdown::parse("Write your **Markdown** text here.").collect(),
-                markdown_settings: markdown::Settings::with_text_size(DEFAULT_FONT_SIZE),
-                markdown_preview_open: false,
-                shortcut_palette_open: false,
-                session_modal_open: false,
-                active_tab: TabId::StartSession,
-                server_thread: Arc::new(Mutex::new(None)),
-                users: Arc::new(Mutex::new(Users::new())),
-                user_cursors: Vec::new(),
-                joined_session: false,
-                started_session: false,
-                leave_session: false,
-                read_password: None,
-                edit_password: None,
-                client_state: State::Disconnected,
-                id: None,
-                server_worker: None,
-            },
-            Task::none(),
-        )
-    }
-
-    pub fn title(&self) -> String {
-        String::from("rust-note")
-    }
+/// Marks drawn over the visible lines' whitespace: dots for spaces, arrows for tabs and a band
+/// behind whitespace that ends a line. Positions are estimated, so they drift on wrapped lines
+/// and with wide characters.
+#[derive(Debug, Clone)]
+pub struct WhitespaceMarks {
+    pub lines: Vec<String>,
+    pub top: f32,
+    pub left: f32,
+    pub line_height: f32,
+    pub color: Color,
+}
 
-    pub fn subscription(&self) -> Subscription<Message> {
-        let subscriptions = vec![
-            window::events().map(|(id, evt)| match evt {
-                iced::window::Event::CloseRequested => Message::CloseWindow(id),
-                _ => Message::NoOp,
-            }),
-            if self.joined_session {
-                let session_type_str = self.modal_content.session_selection.unwrap().to_string(); // Convert `SessionType` to `String` if `Some`
+impl<Message> canvas::Program<Message> for WhitespaceMarks {
+    type State = ();
 
-                Subscription::run_with_id(
-                    "id",
-                    client::connect(
-                        session_type_str, // Pass the resolved string
-                        self.modal_content.session_password_input.clone(),
-                    ),
-                )
-                .map(Message::Echo)
-            } else {
-                Subscription::none()
-            },
-            Subscription::run(server_worker),
-        ];
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let trailing_color = Color::from_rgba(1.0, 0.3, 0.3, 0.3);
 
-        Subscription::batch(subscriptions)
-    }
+        for (row, line) in self.lines.iter().enumerate() {
+            let y = self.top + row as f32 * self.line_height;
+            if y > bounds.height {
+                break;
+            }
+            let middle = y + self.line_height / 2.0;
+            let trailing_start = line.trim_end().len();
 
-    pub fn view(&self) -> Element<'_, Message> {
-        let status = row![
-            {
-                let button = if self.started_session {
-                    button("Stop Session")
-                        .on_press(Message::RequestClose)
-                        .style(button::primary)
-                } else if self.joined_session {
-                    button("Leave Session")
-                        .on_press(Message::LeaveSession)
-                        .style(button::primary)
+            let mut column = 0;
+            for (index, c) in line.char_indices() {
+                let x = self.left + column as f32 * APPROX_CHAR_WIDTH;
+                let advance = if c == '\t' {
+                    TAB_WIDTH - column % TAB_WIDTH
                 } else {
-                    button("Collaborate")
-                        .on_press(Message::SessionModalToggle)
-                        .style(button::primary)
+                    1
                 };
-                button
-            },
-            text(if let Some(path) = &self.file {
-                let path = path.display().to_string();
+                let width = advance as f32 * APPROX_CHAR_WIDTH;
 
-                if path.len() > 60 {
-                    format!("...{}", &path[path.len() - 40..])
-                } else {
-                    path
+                if index >= trailing_start {
+                    frame.fill(
+                        &icedPath::rectangle(Point::new(x, y), Size::new(width, self.line_height)),
+                        trailing_color,
+                    );
                 }
-            } else {
-                String::from("")
-            }),
-            horizontal_space(),
-            text({
-                let (line, column) = self.content.cursor_position();
-                let content = self.content.text();
-                let words = &content.split(" ").count();
-                let lines = &content.split("\n").count();
-
-                format!(
-                    "Words: {} | Lines: {} | Line {}, Columns {}",
-                    words - 1,
-                    lines - 1,
-                    line + 1,
-                    column + 1
-                )
-            })
+                match c {
+                    ' ' => frame.fill(
+                        &icedPath::circle(Point::new(x + width / 2.0, middle), 1.5),
+                        self.color,
+                    ),
+                    '\t' => {
+                        let end = Point::new(x + width - 2.0, middle);
+                        let arrow = icedPath::new(|builder| {
+                            builder.move_to(Point::new(x + 2.0, middle));
+                            builder.line_to(end);
+                            builder.move_to(Point::new(end.x - 3.0, middle - 3.0));
+                            builder.line_to(end);
+                            builder.line_to(Point::new(end.x - 3.0, middle + 3.0));
+                        });
+                        frame.stroke(
+                            &arrow,
+                            canvas::Stroke::default()
+                                .with_color(self.color)
+                                .with_width(1.0),
+                        );
+                    }
+                    _ => {}
+                }
+                column += advance;
+            }
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+/// A match of the find panel's query, which never spans lines
+#[derive(Debug, Clone)]
+pub struct FindMatch {
+    pub line: usize,
+    /// Characters of the line the match covers
+    pub columns: Range<usize>,
+}
+
+/// Bands drawn behind the find panel's matches, the selected one stronger. Like the whitespace
+/// marks, positions are estimated.
+#[derive(Debug, Clone)]
+pub struct FindHighlights {
+    pub bands: Vec<(Rectangle, bool)>,
+}
+
+impl<Message> canvas::Program<Message> for FindHighlights {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+
+        for &(band, current) in &self.bands {
+            let alpha = if current { 0.6 } else { 0.3 };
+            frame.fill(
+                &icedPath::rectangle(band.position(), band.size()),
+                Color::from_rgba(1.0, 0.8, 0.0, alpha),
+            );
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Strip along the editor's right edge showing which part of the document a collaborator has
+/// on screen, like a scrollbar thumb
+#[derive(Debug, Clone, Copy)]
+pub struct ViewportMarker {
+    pub viewport: (usize, usize),
+    pub line_count: usize,
+    pub color: (f32, f32, f32),
+}
+
+impl<Message> canvas::Program<Message> for ViewportMarker {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let line_count = self.line_count.max(1) as f32;
+        let (top, bottom) = self.viewport;
+        let top = (top as f32 / line_count).min(1.0) * bounds.height;
+        let bottom = (bottom as f32 / line_count).min(1.0) * bounds.height;
+
+        let strip = icedPath::rectangle(
+            Point::new(bounds.width - VIEWPORT_MARKER_WIDTH, top),
+            Size::new(VIEWPORT_MARKER_WIDTH, (bottom - top).max(2.0)),
+        );
+        frame.fill(
+            &strip,
+            Color::from_rgba(self.color.0, self.color.1, self.color.2, 0.35),
+        );
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Ticks along the editor's right edge marking the lines collaborators' cursors are on, so
+/// activity outside the screen can be seen at a glance. The edge is shaded while the host has
+/// locked the session.
+#[derive(Debug, Clone)]
+pub struct ActivityTicks {
+    pub ticks: Vec<(usize, (f32, f32, f32))>,
+    pub line_count: usize,
+    pub locked: bool,
+}
+
+impl<Message> canvas::Program<Message> for ActivityTicks {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        if self.locked {
+            let mut color = theme.extended_palette().danger.base.color;
+            color.a = 0.2;
+            frame.fill(
+                &icedPath::rectangle(
+                    Point::new(bounds.width - ACTIVITY_TICK_WIDTH, 0.0),
+                    Size::new(ACTIVITY_TICK_WIDTH, bounds.height),
+                ),
+                color,
+            );
+        }
+        for &(line, (r, g, b)) in &self.ticks {
+            let y = tick_position(line, self.line_count, bounds.height - ACTIVITY_TICK_HEIGHT);
+            frame.fill(
+                &icedPath::rectangle(
+                    Point::new(bounds.width - ACTIVITY_TICK_WIDTH, y),
+                    Size::new(ACTIVITY_TICK_WIDTH, ACTIVITY_TICK_HEIGHT),
+                ),
+                Color::from_rgb(r, g, b),
+            );
+        }
+        vec![frame.into_geometry()]
+    }
+}
+
+/// Where along a strip of `height` a mark for `line` goes, placing the document's lines evenly
+/// from the top of the strip to the bottom
+fn tick_position(line: usize, line_count: usize, height: f32) -> f32 {
+    let last_line = line_count.saturating_sub(1).max(1) as f32;
+    (line as f32 / last_line).min(1.0) * height.max(0.0)
+}
+
+/// A collaborator's name drawn beside their cursor
+#[derive(Debug, Clone)]
+pub struct CursorLabel {
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+    pub color: (f32, f32, f32),
+    pub opacity: f32,
+}
+
+impl<Message> canvas::Program<Message> for CursorLabel {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let (r, g, b) = self.color;
+
+        // Sits above the line, clear of the text the cursor is on
+        let top = (self.y - 14.0).max(0.0);
+        let width = self.name.chars().count() as f32 * 7.0 + 8.0;
+        let left = self.x.min(bounds.width - width).max(0.0);
+        frame.fill(
+            &icedPath::rectangle(Point::new(left, top), Size::new(width, 14.0)),
+            Color::from_rgba(r, g, b, self.opacity),
+        );
+        frame.fill_text(canvas::Text {
+            content: self.name.clone(),
+            position: Point::new(left + 4.0, top + 1.0),
+            color: Color::from_rgba(1.0, 1.0, 1.0, self.opacity),
+            size: Pixels(11.0),
+            ..canvas::Text::default()
+        });
+        vec![frame.into_geometry()]
+    }
+}
+
+impl<Message> canvas::Program<Message> for CursorMarker {
+    // No internal state
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        // let offset_x = 2.0; // Offset for padding/margin adjustments
+        // let offset_y = 2.0; // Offset for padding/margin adjustments
+
+        // The column is measured on the sender's screen, where the line may not have wrapped.
+        // Here it stays at the edge rather than disappearing past it.
+        let x = self.x.min(bounds.width - 5.5).max(0.0);
+        let rectangle = icedPath::rectangle(Point::new(x, self.y), Size::new(5.5, self.height));
+        frame.fill(
+            &rectangle,
+            Color::from_rgb(self.color.0, self.color.1, self.color.2),
+        );
+        vec![frame.into_geometry()]
+    }
+}
+
+impl Editor {
+    pub fn new() -> (Self, Task<Message>) {
+        let config_dir = Settings::config_dir();
+        Self::with_settings(Settings::load(&config_dir), config_dir)
+    }
+
+    /// An editor using `settings`, which are saved to `config_dir` as they are changed
+    fn with_settings(mut settings: Settings, config_dir: PathBuf) -> (Self, Task<Message>) {
+        if settings.highlight_theme_follows_app {
+            settings.highlight_theme = default_highlight_theme(&Theme::default());
+        }
+        let mut op_log = OpLog::default();
+        op_log.set_enabled(settings.op_log_enabled);
+        let undo = undo_history(&settings);
+        let mut cursor_marker = CursorMarker::new(0.2, LINE_HEIGHT);
+        if let Some(color) = settings.cursor_color.rgb() {
+            cursor_marker.color = color;
+        }
+
+        let recovery = Recovery::new(&config_dir);
+        let mut startup_tasks = vec![Task::perform(
+            recovery.clone().find(),
+            Message::RecoveryFound,
+        )];
+        if let Some((path, (line, col))) = settings.file_to_reopen() {
+            startup_tasks.push(
+                Task::perform(load_file(path), |result| {
+                    Message::Menu(MenuMessage::FileOpened(result))
+                })
+                .chain(Task::done(Message::RestoreCaret(line, col))),
+            );
+        }
+
+        (
+            Self {
+                content: text_editor::Content::new(),
+                line_offsets: vec![0],
+                long_line_prompt: None,
+                line_height: LINE_HEIGHT,
+                document: Arc::new(Mutex::new(Document::new(String::new()))),
+                is_dirty: Arc::new(Mutex::new(false)),
+                cursor_marker,
+                is_moved: Arc::new(Mutex::new(false)),
+                is_locked: Arc::new(Mutex::new(false)),
+                session_locked: false,
+                idle_lock_timeout: None,
+                last_host_activity: Instant::now(),
+                last_document_change: Instant::now(),
+                menubar: MenuBar::new(recent_list(&config_dir)),
+                format_bar: FormatBar::new(),
+                file: None,
+                theme: Theme::default(),
+                modal_content: SessionModal::default(),
+                markdown_text: Vec::new(),
+                metadata: None,
+                markdown_settings: markdown::Settings::with_text_size(DEFAULT_FONT_SIZE),
+                markdown_preview_open: false,
+                plain_text: false,
+                preview_focused: false,
+                preview_link: None,
+                settings,
+                config_dir,
+                window_width: f32::INFINITY,
+                window_height: 0.0,
+                op_log: Arc::new(std::sync::Mutex::new(op_log)),
+                undo: Arc::new(std::sync::Mutex::new(undo)),
+                scroll_offset: 0,
+                shortcut_palette_open: false,
+                find_open: false,
+                replace_open: false,
+                find_query: String::new(),
+                replace_input: String::new(),
+                find_ignore_case: true,
+                find_whole_word: false,
+                find_matches: Vec::new(),
+                find_current: None,
+                session_modal_open: false,
+                recovered_text: None,
+                recovery_pending: false,
+                saved_hash: None,
+                recovery_hash: None,
+                recovery,
+                preview_pending: false,
+                word_count: 0,
+                unsaved_changes: false,
+                join_confirm_open: false,
+                leave_confirm_open: false,
+                leave_after_save: false,
+                access_prompt_open: false,
+                access_password_input: String::new(),
+                known_access_passwords: HashMap::new(),
+                access_fallback: None,
+                unsynced_edits: 0,
+                received_revision: 0,
+                applied_revision: 0,
+                host_open_pending: None,
+                reopen_pending: None,
+                workspace_root: None,
+                workspace_files: Vec::new(),
+                workspace_open_pending: None,
+                start_confirm_open: false,
+                opened_word_count: 0,
+                save_status: SaveStatus::Idle,
+                file_dialog: None,
+                error_banner: None,
+                reconnecting: false,
+                connection_controller: None,
+                throttled_until: None,
+                active_tab: TabId::StartSession,
+                server_thread: Arc::new(Mutex::new(None)),
+                users: Arc::new(Mutex::new(Users::new())),
+                user_cursors: Vec::new(),
+                cursor_moved: HashMap::new(),
+                participants: Vec::new(),
+                session_generation: 0,
+                participant_names: HashMap::new(),
+                host_participant: None,
+                participants_open: true,
+                joined_session: false,
+                started_session: false,
+                leave_session: false,
+                read_password: None,
+                edit_password: None,
+                client_state: State::Disconnected,
+                id: None,
+                server_worker: None,
+                tabs: vec![None],
+                current_tab: 0,
+                session_limits: None,
+                close_tab_pending: None,
+            },
+            Task::batch(startup_tasks),
+        )
+    }
+
+    pub fn title(&self) -> String {
+        match self
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.title.as_ref())
+        {
+            Some(title) => format!("{} - rust-note", title),
+            None => String::from("rust-note"),
+        }
+    }
+
+    pub fn subscription(&self) -> Subscription<Message> {
+        let subscriptions = vec![
+            window::events().map(|(id, evt)| match evt {
+                iced::window::Event::CloseRequested => Message::CloseWindow(id),
+                iced::window::Event::Opened { size, .. } | iced::window::Event::Resized(size) => {
+                    Message::WindowResized(size)
+                }
+                _ => Message::NoOp,
+            }),
+            if self.joined_session {
+                let session_type_str = self.modal_content.session_selection.unwrap().to_string(); // Convert `SessionType` to `String` if `Some`
+                let room = self.modal_content.room_input.trim().to_string();
+                let address = self.modal_content.server_address().unwrap_or_default();
+                let secure = self.modal_content.secure;
+
+                // Switching access changes the id, which replaces the connection with a new one
+                Subscription::run_with_id(
+                    (
+                        "client",
+                        self.session_generation,
+                        address.clone(),
+                        secure,
+                        session_type_str.clone(),
+                        room.clone(),
+                    ),
+                    client::connect(
+                        address,
+                        secure,
+                        session_type_str, // Pass the resolved string
+                        self.modal_content.session_password_input.clone(),
+                        room,
+                        self.modal_content.display_name_input.clone(),
+                    ),
+                )
+                .with(self.session_generation)
+                .map(|(generation, event)| Message::Echo(generation, event))
+            } else {
+                Subscription::none()
+            },
+            Subscription::run(server_worker),
+            if self.started_session && self.idle_lock_timeout.is_some() {
+                iced::time::every(Duration::from_secs(1)).map(|_| Message::CheckIdle)
+            } else {
+                Subscription::none()
+            },
+            if self.in_session() {
+                iced::time::every(CONSISTENCY_CHECK_INTERVAL).map(|_| Message::CheckConsistency)
+            } else {
+                Subscription::none()
+            },
+            if self.settings.cursor_label_fade > 0
+                && self.cursor_moved.values().any(|moved| {
+                    moved.elapsed()
+                        < Duration::from_secs(self.settings.cursor_label_fade) + CURSOR_LABEL_FADE
+                })
+            {
+                iced::time::every(Duration::from_millis(50)).map(|_| Message::FadeCursorLabels)
+            } else {
+                Subscription::none()
+            },
+            if self.preview_pending {
+                iced::time::every(PREVIEW_DEBOUNCE).map(|_| Message::RefreshPreview)
+            } else {
+                Subscription::none()
+            },
+            if self.recovery_pending {
+                // Debounce recovery dumps so they are not written on every keystroke
+                iced::time::every(std::time::Duration::from_secs(5)).map(|_| Message::WriteRecovery)
+            } else {
+                Subscription::none()
+            },
+        ];
+
+        Subscription::batch(subscriptions)
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let status = row![
+            {
+                let button = if self.started_session {
+                    button("Stop Session")
+                        .on_press(Message::RequestClose)
+                        .style(button::primary)
+                } else if self.joined_session {
+                    button("Leave Session")
+                        .on_press(Message::LeaveSession)
+                        .style(button::primary)
+                } else {
+                    button("Collaborate")
+                        .on_press(Message::SessionModalToggle)
+                        .style(button::primary)
+                };
+                button
+            },
+            // Only the file name, so screen sharing a session does not reveal local directories
+            text(
+                self.file
+                    .as_ref()
+                    .and_then(|path| path.file_name())
+                    .map(|name| truncate_start(&name.to_string_lossy(), self.status_name_width()))
+                    .unwrap_or_default()
+            ),
+            text(self.save_status.to_string()),
+            text(self.sync_status()),
+            if self.in_session() {
+                Element::from(
+                    button(text(format!("People ({})", self.participants.len())))
+                        .on_press(Message::ParticipantsToggle)
+                        .style(button::secondary),
+                )
+            } else {
+                text("").into()
+            },
+            horizontal_space(),
+            text(if self.preview_focused {
+                let links = markdown_links(&self.content.text());
+                match self.preview_link.and_then(|index| links.get(index)) {
+                    Some(link) => format!("Preview | Link: {}", link),
+                    None => String::from("Preview"),
+                }
+            } else {
+                String::from("")
+            }),
+            text(match &self.metadata {
+                Some(metadata) if !metadata.tags.is_empty() => {
+                    format!("Tags: {}", metadata.tags.join(", "))
+                }
+                _ => String::new(),
+            }),
+            text(self.session_info()),
+            text({
+                let (line, column) = self.content.cursor_position();
+                let (words, lines) = self.text_statistics();
+
+                format!(
+                    "{}: {} | Lines: {} | Line {}, Columns {}",
+                    self.settings.word_count_mode,
+                    words,
+                    lines,
+                    line + 1,
+                    column + 1
+                )
+            }),
+            self.word_goal_progress()
         ]
         .spacing(10);
 
@@ -342,26 +1526,309 @@ impl Editor {
             column![
                 text("Shortcut Map").size(24),
                 column![
-                    Text::new(format!("cmd + {BOLD_HOTKEY}: Bold")),
-                    Text::new(format!("cmd + {ITALIC_HOTKEY}: Italic")),
-                    Text::new(format!("cmd + {STRIKETHROUGH_HOTKEY}: Strikethrough")),
-                    Text::new("cmd + option + backspace: Delete word"),
-                    Text::new("cmd + backspace: Delete line"),
+                    Text::new(format!("{COMMAND_KEY} + {BOLD_HOTKEY}: Bold")),
+                    Text::new(format!("{COMMAND_KEY} + {ITALIC_HOTKEY}: Italic")),
                     Text::new(format!(
-                        "cmd + {SHORTCUT_PALETTE_HOTKEY}: Toggle shortcut palette"
+                        "{COMMAND_KEY} + shift + {STRIKETHROUGH_HOTKEY}: Strikethrough \
+                         (moved from {COMMAND_KEY} + {FIND_HOTKEY}, which now finds)"
                     )),
+                    Text::new(format!("{COMMAND_KEY} + {HIGHLIGHT_HOTKEY}: Highlight")),
+                    Text::new(format!("{COMMAND_KEY} + {MATH_HOTKEY}: Inline math")),
+                    Text::new(format!("{COMMAND_KEY} + shift + {MATH_HOTKEY}: Math block")),
+                    Text::new(format!("{COMMAND_KEY} + {ALT_KEY} + backspace: Delete word")),
+                    Text::new(format!("{COMMAND_KEY} + backspace: Delete line")),
+                    Text::new(format!("{COMMAND_KEY} + {SORT_LINES_HOTKEY}: Sort selected lines")),
                     Text::new(format!(
-                        "cmd + {SESSION_MODAL_HOTKEY}: Toggle session modal"
+                        "{COMMAND_KEY} + shift + {SORT_LINES_HOTKEY}: Sort selected lines descending"
                     )),
-                    Text::new(format!("cmd + {OPEN_FILE_HOTKEY}: Open file")),
-                    Text::new(format!("cmd + {SAVE_FILE_HOTKEY}: Save file")),
-                ]
-                .spacing(10)
-            ]
-            .spacing(20),
-        )
-        .width(300)
-        .padding(10)
+                    Text::new(format!(
+                        "{COMMAND_KEY} + {UNIQUE_LINES_HOTKEY}: Remove consecutive duplicate lines"
+                    )),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + {COMMENT_HOTKEY}: Toggle comment on selected lines"
+                    )),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + shift + {MATCHING_BRACKET_HOTKEY}: Go to matching bracket"
+                    )),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + {UNDO_HOTKEY}: Undo your last edit"
+                    )),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + shift + {UNDO_HOTKEY}: Redo your last undone edit"
+                    )),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + shift + {UNIQUE_LINES_HOTKEY}: Remove all duplicate lines"
+                    )),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + {SHORTCUT_PALETTE_HOTKEY}: Toggle shortcut palette"
+                    )),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + {SESSION_MODAL_HOTKEY}: Toggle session modal"
+                    )),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + {SWITCH_ACCESS_HOTKEY}: Switch between read and write access"
+                    )),
+                    Text::new(format!("{COMMAND_KEY} + {OPEN_FILE_HOTKEY}: Open file")),
+                    Text::new(format!("{COMMAND_KEY} + {SAVE_FILE_HOTKEY}: Save file")),
+                    Text::new(format!("{COMMAND_KEY} + {FIND_HOTKEY}: Find")),
+                    Text::new(format!("{COMMAND_KEY} + shift + {FIND_HOTKEY}: Find and replace")),
+                    Text::new(format!(
+                        "{COMMAND_KEY} + {PREVIEW_FOCUS_HOTKEY}: Swap editor and preview focus"
+                    )),
+                ]
+                .spacing(10),
+                text("Convert selection").size(18),
+                Codec::ALL
+                    .chunks(2)
+                    .fold(column![].spacing(5), |buttons, codecs| {
+                        buttons.push(
+                            codecs
+                                .iter()
+                                .fold(row![].spacing(5), |row, &codec| {
+                                    row.push(
+                                        button(text(codec.to_string()))
+                                            .on_press(Message::ConvertSelection(codec))
+                                            .style(button::secondary),
+                                    )
+                                }),
+                        )
+                    }),
+            ]
+            .spacing(20),
+        )
+        .width(300)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let recovery_prompt: Container<Message> = container(
+            column![
+                text("Recover unsaved work?").size(24),
+                text("rust-note did not exit cleanly last time. Restore the unsaved document?"),
+                row![
+                    button("Restore")
+                        .on_press(Message::RestoreRecovery)
+                        .style(button::primary),
+                    button("Discard")
+                        .on_press(Message::DiscardRecovery)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(400)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let join_confirm: Container<Message> = container(
+            column![
+                text("Discard local changes?").size(24),
+                text("Joining a session replaces the current document with the host's."),
+                row![
+                    button("Join anyway")
+                        .on_press(Message::ConfirmJoinSession)
+                        .style(button::primary),
+                    button("Cancel")
+                        .on_press(Message::CancelJoinSession)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(400)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let leave_confirm: Container<Message> = container(
+            column![
+                text("Leave with unsynced edits?").size(24),
+                text(format!(
+                    "{} edit(s) made while disconnected never reached the host.",
+                    self.unsynced_edits
+                )),
+                row![
+                    button("Save a copy and leave")
+                        .on_press(Message::SaveCopyAndLeave)
+                        .style(button::primary),
+                    button("Leave anyway")
+                        .on_press(Message::ConfirmLeaveSession)
+                        .style(button::danger),
+                    button("Cancel")
+                        .on_press(Message::CancelLeaveSession)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(500)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let access_prompt: Container<Message> = container(
+            column![
+                text(format!(
+                    "Switch to {} access",
+                    self.modal_content
+                        .session_selection
+                        .map_or(SessionType::Edit, SessionType::other)
+                ))
+                .size(24),
+                text_input("Session password", &self.access_password_input)
+                    .on_input(Message::AccessPasswordChanged)
+                    .on_submit(Message::ConfirmAccessSwitch)
+                    .secure(true)
+                    .padding(5),
+                row![
+                    button("Switch")
+                        .on_press(Message::ConfirmAccessSwitch)
+                        .style(button::primary),
+                    button("Cancel")
+                        .on_press(Message::CancelAccessSwitch)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(500)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let host_open_confirm: Container<Message> = container(
+            column![
+                text("Replace the shared document?").size(24),
+                text("Everyone in the session will see the newly opened document."),
+                row![
+                    button("Replace")
+                        .on_press(Message::ConfirmHostOpen)
+                        .style(button::primary),
+                    button("Cancel")
+                        .on_press(Message::CancelHostOpen)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(400)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let start_confirm: Container<Message> = container(
+            column![
+                text("Start sharing?").size(24),
+                text(self.session_share_summary()),
+                row![
+                    button("Start Session")
+                        .on_press(Message::ConfirmStartSession)
+                        .style(button::primary),
+                    button("Cancel")
+                        .on_press(Message::CancelStartSession)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(400)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let long_line_prompt: Container<Message> = container(
+            column![
+                text("Wrap long lines?").size(24),
+                text(format!(
+                    "This file has a line of {} characters, which makes editing it slow. \
+                     Breaking long lines up changes the file once it is saved.",
+                    self.long_line_prompt.unwrap_or_default()
+                )),
+                row![
+                    text("Wrap at column"),
+                    pick_list(
+                        HARD_WRAP_COLUMNS,
+                        Some(self.settings.hard_wrap_column),
+                        Message::HardWrapColumnSelected
+                    )
+                    .padding(5),
+                ]
+                .align_y(Alignment::Center)
+                .spacing(10),
+                row![
+                    button("Wrap lines")
+                        .on_press(Message::WrapLongLines)
+                        .style(button::primary),
+                    button("Keep as is")
+                        .on_press(Message::KeepLongLines)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(400)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let workspace_open_confirm: Container<Message> = container(
+            column![
+                text("Discard unsaved changes?").size(24),
+                text("Opening another note loses the edits made since this file was last saved."),
+                row![
+                    button("Open")
+                        .on_press(Message::ConfirmWorkspaceOpen)
+                        .style(button::primary),
+                    button("Cancel")
+                        .on_press(Message::CancelWorkspaceOpen)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(400)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let reopen_confirm: Container<Message> = container(
+            column![
+                text("Discard unsaved changes?").size(24),
+                text("This file is already open. Reloading it loses the edits made since it was last saved."),
+                row![
+                    button("Reload")
+                        .on_press(Message::ConfirmReopen)
+                        .style(button::primary),
+                    button("Cancel")
+                        .on_press(Message::CancelReopen)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(400)
+        .padding(10)
+        .style(container::rounded_box);
+
+        let close_tab_confirm: Container<Message> = container(
+            column![
+                text("Discard unsaved changes?").size(24),
+                text("Closing this tab loses the edits made since its document was last saved."),
+                row![
+                    button("Close Tab")
+                        .on_press(Message::ConfirmCloseTab)
+                        .style(button::danger),
+                    button("Cancel")
+                        .on_press(Message::CancelCloseTab)
+                        .style(button::secondary),
+                ]
+                .spacing(10)
+            ]
+            .spacing(20),
+        )
+        .width(400)
+        .padding(10)
         .style(container::rounded_box);
 
         let session_modal: Container<Message> = container(
@@ -378,7 +1845,7 @@ impl Editor {
                             )
                             .on_input(Message::FilePathChanged)
                             .padding(5),
-                            if !self.modal_content.clone().validate_file() {
+                            if !self.modal_content.file_valid {
                                 text("Invalid Markdown file path")
                                     .size(14)
                                     .color([1.0, 0.0, 0.0])
@@ -391,27 +1858,62 @@ impl Editor {
                                     &self.modal_content.read_password_input
                                 )
                                 .on_input(Message::ReadPasswordChanged)
+                                .secure(!self.modal_content.show_passwords)
                                 .padding(5),
                                 text_input(
                                     "Enter write session password",
                                     &self.modal_content.write_password_input
                                 )
                                 .on_input(Message::WritePasswordChanged)
+                                .secure(!self.modal_content.show_passwords)
                                 .padding(5),
-                            ],
+                                self.password_visibility_button(),
+                            ]
+                            .spacing(5),
+                            self.display_name_input(),
+                            self.server_address_inputs(),
+                            self.tls_file_inputs(),
+                            text_input(
+                                "Lock to read-only after idle minutes (0 = never)",
+                                &self.modal_content.idle_lock_input
+                            )
+                            .on_input(Message::IdleLockChanged)
+                            .padding(5),
+                            self.session_limit_inputs(),
+                            toggler(self.modal_content.enable_metrics)
+                                .label("Expose /metrics endpoint")
+                                .on_toggle(Message::MetricsToggled),
+                            toggler(self.settings.confirm_session_start)
+                                .label("Confirm what is shared before starting")
+                                .on_toggle(Message::ConfirmSessionStartToggled),
                             {
-                                let mut button = button("Start Session").style(button::secondary);
-                                if self.modal_content.validate_password()
-                                    && ((!self.modal_content.file_path_input.clone().is_empty()
-                                        && self.modal_content.clone().validate_file())
-                                        || self.modal_content.file_path_input.clone().is_empty())
+                                // The server can only start once its worker has reported in
+                                let label = if self.server_worker.is_some() {
+                                    "Start Session"
+                                } else {
+                                    "Initializing\u{2026}"
+                                };
+                                let mut button = button(label).style(button::secondary);
+                                if self.server_worker.is_some()
+                                    && !self.in_session()
+                                    && self.modal_content.validate_password()
+                                    && self.modal_content.file_valid
+                                    && self.modal_content.server_address().is_ok()
+                                    && self.modal_content.tls_files().is_ok()
                                 {
                                     button = button
                                         .on_press(Message::StartSessionPressed)
                                         .style(button::primary);
                                 }
                                 button
-                            }
+                            },
+                            if !self.modal_content.session_start_error.is_empty() {
+                                text(&self.modal_content.session_start_error)
+                                    .size(14)
+                                    .color([1.0, 0.0, 0.0])
+                            } else {
+                                text("").size(14)
+                            },
                         ]
                         .spacing(10)
                         .padding(10)
@@ -420,12 +1922,32 @@ impl Editor {
                         TabId::JoinSession,
                         TabLabel::Text(String::from("Join Session")),
                         column![
+                            row![
+                                text_input(
+                                    "Enter session password",
+                                    &self.modal_content.session_password_input
+                                )
+                                .on_input(Message::SessionPasswordChanged)
+                                .secure(!self.modal_content.show_passwords)
+                                .padding(5),
+                                self.password_visibility_button(),
+                            ]
+                            .spacing(5),
                             text_input(
-                                "Enter session password",
-                                &self.modal_content.session_password_input
+                                "Room (leave empty for the host's document)",
+                                &self.modal_content.room_input
                             )
-                            .on_input(Message::SessionPasswordChanged)
+                            .on_input(Message::RoomChanged)
                             .padding(5),
+                            self.display_name_input(),
+                            self.server_address_inputs(),
+                            if self.modal_content.validate_room() {
+                                text("").size(14)
+                            } else {
+                                text("Room names use letters, digits, - and _")
+                                    .size(14)
+                                    .color([1.0, 0.0, 0.0])
+                            },
                             row![
                                 radio(
                                     "Read Session",
@@ -433,6 +1955,12 @@ impl Editor {
                                     self.modal_content.session_selection,
                                     Message::SessionTypeRequested
                                 ),
+                                radio(
+                                    "Comment Session",
+                                    SessionType::Comment,
+                                    self.modal_content.session_selection,
+                                    Message::SessionTypeRequested
+                                ),
                                 radio(
                                     "Write Session",
                                     SessionType::Edit,
@@ -443,7 +1971,11 @@ impl Editor {
                             .spacing(10),
                             {
                                 let mut button = button("Join Session").style(button::secondary);
-                                if !self.modal_content.session_password_input.is_empty() {
+                                // Sessions without a password are joined with it left empty
+                                if !self.in_session()
+                                    && self.modal_content.validate_room()
+                                    && self.modal_content.server_address().is_ok()
+                                {
                                     button = button
                                         .on_press(Message::JoinSessionPressed)
                                         .style(button::primary);
@@ -472,126 +2004,541 @@ impl Editor {
         .padding(10)
         .style(container::rounded_box);
 
+        let preview_focused = self.preview_focused;
         let editor = TextEditor::new(&self.content)
-            .line_height(text::LineHeight::Absolute(Pixels(21.0)))
+            .placeholder(self.placeholder().unwrap_or_default())
+            .line_height(text::LineHeight::Absolute(Pixels(self.line_height)))
             .highlight(
                 self.file
                     .as_deref()
                     .and_then(Path::extension)
                     .and_then(ffi::OsStr::to_str)
                     .unwrap_or("md"),
-                highlighter::Theme::SolarizedDark,
+                self.settings.highlight_theme,
             )
-            .wrapping(text::Wrapping::WordOrGlyph)
-            .width(300)
+            // The gutter numbers logical lines, so they only line up when lines are not wrapped
+            .wrapping(if self.settings.show_line_numbers {
+                text::Wrapping::None
+            } else {
+                text::Wrapping::WordOrGlyph
+            })
+            // The editor fills its portion of the row by default
             .height(Length::FillPortion(1))
+            .padding(self.settings.editor_padding)
             .on_action(Message::Action)
-            .key_binding(|key_press| match key_press.key.as_ref() {
-                keyboard::Key::Character(BOLD_HOTKEY) if key_press.modifiers.command() => Some(
-                    text_editor::Binding::Custom(Message::Format(TextStyle::Bold)),
-                ),
-                keyboard::Key::Character(ITALIC_HOTKEY) if key_press.modifiers.command() => Some(
-                    text_editor::Binding::Custom(Message::Format(TextStyle::Italic)),
-                ),
-                keyboard::Key::Character(STRIKETHROUGH_HOTKEY) if key_press.modifiers.command() => {
-                    Some(text_editor::Binding::Custom(Message::Format(
-                        TextStyle::Strikethrough,
-                    )))
+            .key_binding(move |key_press| {
+                if preview_focused {
+                    return preview_key_binding(key_press);
                 }
-                keyboard::Key::Named(keyboard::key::Named::Backspace)
-                    if key_press.modifiers.command() =>
-                {
-                    if key_press.modifiers.alt() {
-                        Some(text_editor::Binding::Custom(Message::DeleteWord))
-                    } else {
-                        Some(text_editor::Binding::Custom(Message::DeleteLine))
+
+                match key_press.key.as_ref() {
+                    keyboard::Key::Character(BOLD_HOTKEY) if key_press.modifiers.command() => Some(
+                        text_editor::Binding::Custom(Message::Format(TextStyle::Bold)),
+                    ),
+                    keyboard::Key::Character(ITALIC_HOTKEY) if key_press.modifiers.command() => {
+                        Some(text_editor::Binding::Custom(Message::Format(
+                            TextStyle::Italic,
+                        )))
                     }
+                    // Shift keeps command + x free for cutting
+                    keyboard::Key::Character(STRIKETHROUGH_HOTKEY)
+                        if key_press.modifiers.command() && key_press.modifiers.shift() =>
+                    {
+                        Some(text_editor::Binding::Custom(Message::Format(
+                            TextStyle::Strikethrough,
+                        )))
+                    }
+                    keyboard::Key::Character(HIGHLIGHT_HOTKEY) if key_press.modifiers.command() => {
+                        Some(text_editor::Binding::Custom(Message::Format(
+                            TextStyle::Highlight,
+                        )))
+                    }
+                    keyboard::Key::Character(MATH_HOTKEY) if key_press.modifiers.command() => {
+                        let style = if key_press.modifiers.shift() {
+                            TextStyle::MathBlock
+                        } else {
+                            TextStyle::Math
+                        };
+                        Some(text_editor::Binding::Custom(Message::Format(style)))
+                    }
+                    keyboard::Key::Character(SORT_LINES_HOTKEY)
+                        if key_press.modifiers.command() =>
+                    {
+                        Some(text_editor::Binding::Custom(Message::SortLines(
+                            key_press.modifiers.shift(),
+                        )))
+                    }
+                    keyboard::Key::Character(COMMENT_HOTKEY) if key_press.modifiers.command() => {
+                        Some(text_editor::Binding::Custom(Message::ToggleComment))
+                    }
+                    // Shift turns the backslash into a bar on most layouts
+                    keyboard::Key::Character(MATCHING_BRACKET_HOTKEY | "|")
+                        if key_press.modifiers.command() && key_press.modifiers.shift() =>
+                    {
+                        Some(text_editor::Binding::Custom(Message::GoToMatchingBracket))
+                    }
+                    keyboard::Key::Character(UNDO_HOTKEY) if key_press.modifiers.command() => Some(
+                        text_editor::Binding::Custom(if key_press.modifiers.shift() {
+                            Message::Redo
+                        } else {
+                            Message::Undo
+                        }),
+                    ),
+                    keyboard::Key::Character(UNIQUE_LINES_HOTKEY)
+                        if key_press.modifiers.command() =>
+                    {
+                        Some(text_editor::Binding::Custom(Message::UniqueLines(
+                            key_press.modifiers.shift(),
+                        )))
+                    }
+                    keyboard::Key::Named(keyboard::key::Named::Backspace)
+                        if key_press.modifiers.command() =>
+                    {
+                        if key_press.modifiers.alt() {
+                            Some(text_editor::Binding::Custom(Message::DeleteWord))
+                        } else {
+                            Some(text_editor::Binding::Custom(Message::DeleteLine))
+                        }
+                    }
+                    keyboard::Key::Character(SHORTCUT_PALETTE_HOTKEY)
+                        if key_press.modifiers.command() =>
+                    {
+                        Some(text_editor::Binding::Custom(Message::ShortcutPaletteToggle))
+                    }
+                    keyboard::Key::Character(SESSION_MODAL_HOTKEY)
+                        if key_press.modifiers.command() =>
+                    {
+                        Some(text_editor::Binding::Custom(Message::SessionModalToggle))
+                    }
+                    keyboard::Key::Character(SWITCH_ACCESS_HOTKEY)
+                        if key_press.modifiers.command() =>
+                    {
+                        Some(text_editor::Binding::Custom(Message::SwitchAccess))
+                    }
+                    keyboard::Key::Character(OPEN_FILE_HOTKEY) if key_press.modifiers.command() => {
+                        Some(text_editor::Binding::Custom(Message::Menu(
+                            MenuMessage::OpenFile,
+                        )))
+                    }
+                    keyboard::Key::Character(FIND_HOTKEY) if key_press.modifiers.command() => {
+                        Some(text_editor::Binding::Custom(Message::OpenFind(
+                            key_press.modifiers.shift(),
+                        )))
+                    }
+                    keyboard::Key::Character(SAVE_FILE_HOTKEY) if key_press.modifiers.command() => {
+                        Some(text_editor::Binding::Custom(Message::Menu(
+                            MenuMessage::SaveFile,
+                        )))
+                    }
+                    keyboard::Key::Character(PREVIEW_FOCUS_HOTKEY)
+                        if key_press.modifiers.command() =>
+                    {
+                        Some(text_editor::Binding::Custom(Message::TogglePreviewFocus))
+                    }
+                    _ => text_editor::Binding::from_key_press(key_press),
                 }
-                keyboard::Key::Character(SHORTCUT_PALETTE_HOTKEY)
-                    if key_press.modifiers.command() =>
-                {
-                    Some(text_editor::Binding::Custom(Message::ShortcutPaletteToggle))
-                }
-                keyboard::Key::Character(SESSION_MODAL_HOTKEY) if key_press.modifiers.command() => {
-                    Some(text_editor::Binding::Custom(Message::SessionModalToggle))
-                }
-                keyboard::Key::Character(OPEN_FILE_HOTKEY) if key_press.modifiers.command() => {
-                    Some(text_editor::Binding::Custom(Message::Menu(
-                        MenuMessage::OpenFile,
-                    )))
-                }
-                keyboard::Key::Character(SAVE_FILE_HOTKEY) if key_press.modifiers.command() => {
-                    Some(text_editor::Binding::Custom(Message::Menu(
-                        MenuMessage::SaveFile,
-                    )))
-                }
-                _ => text_editor::Binding::from_key_press(key_press),
             });
 
-        let mut marker_elements: Vec<Element<Message>> = self
-            .user_cursors
-            .clone()
-            .into_iter()
-            .map(|marker| {
-                // Create a Canvas for each marker and convert it to an Element
+        let mut marker_elements: Vec<Element<Message>> = Vec::new();
+        for &(id, marker) in &self.user_cursors {
+            let marker = if self.settings.high_contrast {
+                marker.brightened()
+            } else {
+                marker
+            };
+            let marker = CursorMarker {
+                x: marker.x + f32::from(self.settings.editor_padding),
+                ..marker.scaled_to(self.line_height)
+            };
+            // Create a Canvas for each marker and convert it to an Element
+            marker_elements.push(
                 Canvas::<CursorMarker, Message>::new(marker)
                     .width(Length::FillPortion(1))
                     .height(Length::FillPortion(1))
-                    .into() // Convert the Canvas into an Element<Message>
-            })
-            .collect();
+                    .into(), // Convert the Canvas into an Element<Message>
+            );
+
+            let opacity = self.cursor_label_opacity(id);
+            if opacity > 0.0 {
+                let label = CursorLabel {
+                    name: self
+                        .participant_names
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or_else(|| format!("User {id}")),
+                    x: marker.x,
+                    y: marker.y,
+                    color: marker.color,
+                    opacity,
+                };
+                marker_elements.push(
+                    Canvas::<CursorLabel, Message>::new(label)
+                        .width(Length::FillPortion(1))
+                        .height(Length::FillPortion(1))
+                        .into(),
+                );
+            }
+        }
 
         let mut stack_elements = Vec::new();
         stack_elements.push(editor.into());
+        if self.settings.highlight_current_line {
+            let (line, _) = self.content.cursor_position();
+            // Lines scrolled out of view above the caret shift the band up
+            let visible_line = line.saturating_sub(self.scroll_offset);
+            let mut color = self.settings.line_highlight_color.to_color(&self.theme());
+            if self.settings.high_contrast {
+                color.a = HIGH_CONTRAST_BAND_ALPHA;
+            }
+            let highlight = LineHighlight {
+                y: f32::from(self.settings.editor_padding) + visible_line as f32 * self.line_height,
+                height: self.line_height,
+                color,
+            };
+            stack_elements.push(
+                Canvas::<LineHighlight, Message>::new(highlight)
+                    .width(Length::FillPortion(1))
+                    .height(Length::FillPortion(1))
+                    .into(),
+            );
+        }
+        if self.settings.show_whitespace {
+            let visible_lines = (self.window_height / self.line_height).ceil() as usize;
+            let mut color = self.theme().palette().text;
+            color.a = 0.4;
+            let marks = WhitespaceMarks {
+                lines: self
+                    .content
+                    .lines()
+                    .skip(self.scroll_offset)
+                    .take(visible_lines)
+                    .map(|line| line.to_string())
+                    .collect(),
+                top: f32::from(self.settings.editor_padding),
+                left: f32::from(self.settings.editor_padding),
+                line_height: self.line_height,
+                color,
+            };
+            stack_elements.push(
+                Canvas::<WhitespaceMarks, Message>::new(marks)
+                    .width(Length::FillPortion(1))
+                    .height(Length::FillPortion(1))
+                    .into(),
+            );
+        }
+        if let Some(highlights) = self.find_highlights() {
+            stack_elements.push(highlights);
+        }
         stack_elements.append(&mut marker_elements);
+        let line_count = self.content.line_count();
+        stack_elements.extend(self.user_cursors.iter().filter_map(|(_, marker)| {
+            let viewport = ViewportMarker {
+                viewport: marker.viewport?,
+                line_count,
+                color: marker.color,
+            };
+            Some(
+                Canvas::<ViewportMarker, Message>::new(viewport)
+                    .width(Length::FillPortion(1))
+                    .height(Length::FillPortion(1))
+                    .into(),
+            )
+        }));
+        if !self.user_cursors.is_empty() || self.session_locked {
+            let ticks = ActivityTicks {
+                ticks: self
+                    .user_cursors
+                    .iter()
+                    .map(|(_, marker)| (marker.line(), marker.color))
+                    .collect(),
+                line_count,
+                locked: self.session_locked,
+            };
+            stack_elements.push(
+                Canvas::<ActivityTicks, Message>::new(ticks)
+                    .width(Length::FillPortion(1))
+                    .height(Length::FillPortion(1))
+                    .into(),
+            );
+        }
+
+        if self.find_open {
+            stack_elements.push(
+                container(self.find_panel())
+                    .align_right(Length::Fill)
+                    .padding(10)
+                    .into(),
+            );
+        }
+
+        let error_banner = if let Some(error) = &self.error_banner {
+            row![
+                text(error).color([1.0, 0.0, 0.0]),
+                horizontal_space(),
+                button("Dismiss")
+                    .on_press(Message::DismissError)
+                    .style(button::secondary)
+                    .padding(5)
+            ]
+            .align_y(Alignment::Center)
+        } else {
+            row![]
+        };
+
+        let reconnect_banner = if self.joined_session && self.reconnecting {
+            row![
+                text("Connection lost, reconnecting..."),
+                horizontal_space(),
+                button("Retry now")
+                    .on_press(Message::RetryConnection)
+                    .style(button::primary)
+                    .padding(5),
+                button("Stop")
+                    .on_press(Message::LeaveSession)
+                    .style(button::secondary)
+                    .padding(5)
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center)
+        } else {
+            row![]
+        };
 
         let content = column![
+            error_banner,
+            reconnect_banner,
             row![
                 self.menubar
                     .view(
                         self.theme.clone(),
-                        if let State::Connected(_) = self.client_state {
-                            // Use `connection` here
-                            true
-                        } else {
-                            false
-                        },
-                        if let None = self.file { true } else { false }
+                        self.settings.highlight_theme,
+                        self.joined_session,
+                        self.file.is_none()
                     )
                     .map(Message::Menu),
                 toggler(self.markdown_preview_open)
                     .label("Show Markdown preview")
-                    .on_toggle(Message::ShowMarkdownPreview)
+                    .on_toggle_maybe((!self.plain_text).then_some(Message::ShowMarkdownPreview)),
+                toggler(self.plain_text)
+                    .label("Plain text")
+                    .on_toggle(Message::PlainTextToggled),
+                toggler(self.settings.auto_capitalize)
+                    .label("Auto-capitalize")
+                    .on_toggle(Message::AutoCapitalizeToggled),
+                toggler(self.settings.smart_quotes)
+                    .label("Smart quotes")
+                    .on_toggle(Message::SmartQuotesToggled),
+                toggler(self.settings.show_line_numbers)
+                    .label("Line numbers (turns off line wrapping)")
+                    .on_toggle(Message::LineNumbersToggled),
+                toggler(self.settings.op_log_enabled)
+                    .label("Log operations")
+                    .on_toggle(Message::OpLogToggled),
+                toggler(self.settings.share_viewport)
+                    .label("Share what I'm viewing")
+                    .on_toggle(Message::ShareViewportToggled),
+                toggler(self.settings.highlight_theme_follows_app)
+                    .label("Match highlighting to theme")
+                    .on_toggle(Message::HighlightFollowsThemeToggled),
+                toggler(self.settings.plain_text_link_urls)
+                    .label("Link URLs in text export")
+                    .on_toggle(Message::PlainTextLinkUrlsToggled),
+                toggler(self.settings.sort_case_sensitive)
+                    .label("Case-sensitive sort")
+                    .on_toggle(Message::SortCaseSensitiveToggled),
+                toggler(self.settings.list_paste)
+                    .label("Paste lines as list items")
+                    .on_toggle(Message::ListPasteToggled),
+                toggler(self.settings.link_paste)
+                    .label("Paste URLs over text as links")
+                    .on_toggle(Message::LinkPasteToggled),
+                toggler(self.settings.reopen_last_file)
+                    .label("Reopen last file")
+                    .on_toggle(Message::ReopenLastFileToggled),
+                toggler(self.settings.highlight_current_line)
+                    .label("Highlight line")
+                    .on_toggle(Message::LineHighlightToggled),
+                toggler(self.settings.show_whitespace)
+                    .label("Show whitespace")
+                    .on_toggle(Message::ShowWhitespaceToggled),
+                pick_list(
+                    LineHighlightColor::ALL,
+                    Some(self.settings.line_highlight_color),
+                    Message::LineHighlightColorSelected
+                )
+                .padding(5),
+                text("Cursor"),
+                pick_list(
+                    CursorColor::ALL,
+                    Some(self.settings.cursor_color),
+                    Message::CursorColorSelected
+                )
+                .padding(5),
+                text("Names fade (s)"),
+                pick_list(
+                    CURSOR_LABEL_FADE_OPTIONS,
+                    Some(self.settings.cursor_label_fade),
+                    Message::CursorLabelFadeSelected
+                )
+                .padding(5),
+                text("Undo steps"),
+                pick_list(
+                    UNDO_DEPTH_OPTIONS,
+                    Some(self.settings.undo_depth),
+                    Message::UndoDepthSelected
+                )
+                .padding(5),
+                text("Undo memory (MB)"),
+                pick_list(
+                    UNDO_MEMORY_OPTIONS,
+                    Some(self.settings.undo_memory_mb),
+                    Message::UndoMemorySelected
+                )
+                .padding(5),
+                toggler(self.settings.undo_spill)
+                    .label("Keep old undo steps on disk")
+                    .on_toggle(Message::UndoSpillToggled),
+                self.cursor_contrast_warning(),
+                text("Padding"),
+                pick_list(
+                    SPACING_OPTIONS,
+                    Some(self.settings.editor_padding),
+                    Message::EditorPaddingSelected
+                )
+                .padding(5),
+                text("Margin"),
+                pick_list(
+                    SPACING_OPTIONS,
+                    Some(self.settings.editor_margin),
+                    Message::EditorMarginSelected
+                )
+                .padding(5),
+                pick_list(
+                    WordCountMode::ALL,
+                    Some(self.settings.word_count_mode),
+                    Message::WordCountModeSelected
+                )
+                .padding(5),
+                text_input(
+                    "Word goal",
+                    &match self.settings.word_goal {
+                        0 => String::new(),
+                        goal => goal.to_string(),
+                    }
+                )
+                .on_input(Message::WordGoalChanged)
+                .width(100)
+                .padding(5),
+                text("Publish style"),
+                pick_list(
+                    html::ExportStyle::ALL,
+                    Some(self.settings.export_style),
+                    Message::ExportStyleSelected
+                )
+                .padding(5),
+                text_input(
+                    "Custom CSS file",
+                    &self
+                        .settings
+                        .export_css
+                        .as_ref()
+                        .map_or_else(String::new, |path| path.display().to_string())
+                )
+                .on_input(Message::ExportCssChanged)
+                .width(150)
+                .padding(5),
+                toggler(self.settings.high_contrast)
+                    .label("High contrast")
+                    .on_toggle(Message::HighContrastToggled),
+                text("UI scale %"),
+                pick_list(
+                    UI_SCALE_OPTIONS,
+                    Some(self.settings.ui_scale),
+                    Message::UiScaleSelected
+                )
+                .padding(5)
             ]
+            .align_y(Alignment::Center)
             .spacing(15),
-            self.format_bar.view().map(Message::Format),
-            row![
-                Stack::with_children(stack_elements)
-                    .width(Length::FillPortion(1))
-                    .height(Length::FillPortion(1)),
-                if self.markdown_preview_open {
-                    scrollable(
-                        markdown::view(
-                            &self.markdown_text,
-                            self.markdown_settings,
-                            markdown::Style::from_palette(self.theme.clone().palette()),
-                        )
-                        .map(Message::LinkClicked),
-                    )
-                    .width(Length::FillPortion(1))
-                    .height(Length::FillPortion(1))
-                } else {
-                    scrollable(column![]).width(Length::Shrink)
-                },
-            ]
-            .spacing(20)
-            .align_y(Alignment::Start),
-            status, // Add the status widget here
+            if self.plain_text {
+                row![].into()
+            } else {
+                self.format_bar.view(self.window_width).map(Message::Format)
+            },
+            self.document_tabs(
+                container(
+                    row![
+                        self.workspace_sidebar(),
+                        self.line_number_gutter(),
+                        Stack::with_children(stack_elements)
+                            .width(Length::FillPortion(1))
+                            .height(Length::FillPortion(1)),
+                        if self.markdown_preview_open {
+                            let preview_focused = self.preview_focused;
+                            container(
+                                scrollable(
+                                    markdown::view(
+                                        &self.markdown_text,
+                                        self.markdown_settings,
+                                        markdown::Style::from_palette(self.theme.clone().palette()),
+                                    )
+                                    .map(Message::LinkClicked),
+                                )
+                                .id(preview_scrollable_id())
+                                .width(Length::Fill)
+                                .height(Length::Fill),
+                            )
+                            // Outline the preview while it has keyboard focus
+                            .style(move |theme| {
+                                if preview_focused {
+                                    container::bordered_box(theme)
+                                } else {
+                                    container::transparent(theme)
+                                }
+                            })
+                            .width(Length::FillPortion(1))
+                            .height(Length::FillPortion(1))
+                        } else {
+                            container(scrollable(column![]).width(Length::Shrink))
+                        },
+                        self.participants_sidebar(),
+                    ]
+                    .spacing(20)
+                    .align_y(Alignment::Start)
+                )
+                .padding(self.settings.editor_margin)
+                .into()
+            ),
+            status, // Add the status widget here
         ]
         .align_x(Alignment::Center)
         .spacing(10);
 
-        if self.shortcut_palette_open {
+        if self.recovered_text.is_some() {
+            modal(content, recovery_prompt, Message::NoOp)
+        } else if self.join_confirm_open {
+            modal(content, join_confirm, Message::CancelJoinSession)
+        } else if self.leave_confirm_open {
+            modal(content, leave_confirm, Message::CancelLeaveSession)
+        } else if self.access_prompt_open {
+            modal(content, access_prompt, Message::CancelAccessSwitch)
+        } else if self.host_open_pending.is_some() {
+            modal(content, host_open_confirm, Message::CancelHostOpen)
+        } else if self.start_confirm_open {
+            modal(content, start_confirm, Message::CancelStartSession)
+        } else if self.long_line_prompt.is_some() {
+            modal(content, long_line_prompt, Message::KeepLongLines)
+        } else if self.reopen_pending.is_some() {
+            modal(content, reopen_confirm, Message::CancelReopen)
+        } else if self.close_tab_pending.is_some() {
+            modal(content, close_tab_confirm, Message::CancelCloseTab)
+        } else if self.workspace_open_pending.is_some() {
+            modal(
+                content,
+                workspace_open_confirm,
+                Message::CancelWorkspaceOpen,
+            )
+        } else if self.shortcut_palette_open {
             modal(content, shortcut_palette, Message::ShortcutPaletteToggle)
         } else if self.session_modal_open {
             modal(content, session_modal, Message::SessionModalToggle)
@@ -600,721 +2547,5089 @@ impl Editor {
         }
     }
 
-    pub fn update(&mut self, message: Message) -> Task<Message> {
-        match message {
-            Message::Action(action) => {
-                let (x, y) = self.content.cursor_position();
-                let mut is_blank_line = false;
-                let mut running_sum = 0;
-                let mut running_sum_vec = vec![0];
-                let lines = self.content.lines();
-                lines.enumerate().for_each(|(idx, line)| {
-                    if idx == x {
-                        is_blank_line = line.is_empty();
-                    }
-                    running_sum += line.len() + 1;
-                    running_sum_vec.push(running_sum);
-                });
+    pub fn update(&mut self, message: Message) -> Task<Message> {
+        match message {
+            Message::Action(action) => {
+                let mut tasks = Vec::new();
+                if action.is_edit() {
+                    self.last_document_change = Instant::now();
+                    self.save_status = SaveStatus::Idle;
+                }
+                if self.started_session {
+                    // Any host activity resets the idle timer and lifts an idle lock
+                    self.last_host_activity = Instant::now();
+                    if self.session_locked {
+                        tasks.push(self.set_session_lock(false));
+                    }
+                }
+
+                let (x, y) = self.content.cursor_position();
+                let line_start = self.line_offset(x);
+
+                let mut connection = if let State::Connected(ref mut connection) = self.client_state
+                {
+                    Some(connection.clone())
+                } else {
+                    None
+                };
+
+                // Only edits need the text, copying it for every caret move makes scrolling slow
+                let content_text = if action.is_edit() {
+                    self.buffer_text()
+                } else {
+                    String::new()
+                };
+                let mut index = line_start + y;
+
+                let doc_lock = self.document.clone();
+                let is_dirty_lock = self.is_dirty.clone();
+                let op_log = self.op_log.clone();
+                let undo = self.undo.clone();
+                let selection = self.content.selection().clone();
+                let id = self.id;
+                let hosting = self.started_session;
+                let generation = self.session_generation;
+
+                // An edit moves the starts of the lines after the first one it touches, which a
+                // selection can reach back from the cursor, and a backspace one line further
+                if action.is_edit() {
+                    let selected_lines = selection
+                        .as_ref()
+                        .map_or(0, |selection| selection.matches('\n').count());
+                    let first_changed = x.saturating_sub(selected_lines + 1);
+                    self.line_offsets.truncate(first_changed + 1);
+                }
+
+                // A URL pasted over text links it rather than replacing it
+                let action = match (&action, selection.as_deref()) {
+                    (
+                        text_editor::Action::Edit(text_editor::Edit::Paste(text)),
+                        Some(selection),
+                    ) if self.settings.link_paste && !self.plain_text => {
+                        match link_paste(selection, text) {
+                            Some(link) => {
+                                text_editor::Action::Edit(text_editor::Edit::Paste(Arc::new(link)))
+                            }
+                            None => action,
+                        }
+                    }
+                    _ => action,
+                };
+
+                // Apply prose substitutions before the edit reaches the editor or the document
+                let action = match action {
+                    text_editor::Action::Edit(text_editor::Edit::Insert(ch))
+                        if selection.is_none() =>
+                    {
+                        let text_before = content_text.get(..index).unwrap_or("");
+                        text_editor::Action::Edit(text_editor::Edit::Insert(
+                            self.prose_substitution(text_before, ch),
+                        ))
+                    }
+                    text_editor::Action::Edit(text_editor::Edit::Paste(text))
+                        if self.settings.list_paste && !self.plain_text =>
+                    {
+                        let current_line = self.content.line(x).map(|line| line.to_string());
+                        let text = current_line
+                            .and_then(|line| continue_list(&line, &text))
+                            .map_or(text, Arc::new);
+                        text_editor::Action::Edit(text_editor::Edit::Paste(text))
+                    }
+                    action => action,
+                };
+
+                // A refused edit stays out of the document as well as the buffer, or it would be
+                // sent on and leave this copy out of step with the host's
+                if self.read_only() && action.is_edit() {
+                    return Task::batch(tasks);
+                }
+                self.content.perform(action.clone());
+
+                // Keep the gutter from scrolling past the cursor
+                let (cursor_line, _) = self.content.cursor_position();
+                self.scroll_offset = self.scroll_offset.min(cursor_line);
+
+                // Parsing a large document on every keystroke stalls typing, so the preview waits
+                // for edits to settle
+                if action.is_edit() {
+                    self.preview_pending = true;
+                    self.refresh_find();
+                }
+
+                match action {
+                    text_editor::Action::Edit(edit) => {
+                        self.recovery_pending = true;
+                        self.unsaved_changes = true;
+                        if self.joined_session && connection.is_none() {
+                            // Nothing carries this edit to the host
+                            self.unsynced_edits += 1;
+                        }
+
+                        // Translate local user edit action to document operations. Edits reach
+                        // the document exactly as typed, with no newline added on blank lines.
+                        tasks.push(Task::future(async move {
+                            let mut operations = Vec::new();
+                            let mut undo_steps = Vec::new();
+                            let mut doc = doc_lock.lock().await;
+                            // Outside a session there is nobody else to tell edits apart from
+                            let author = id.unwrap_or(doc.last_edit);
+                            let mut apply = |doc: &mut Document, operation: Operation| {
+                                // A client's copy stays at the revision the host last sent, which
+                                // tells the host which of its edits this one has not seen
+                                let operation = operation.with_base(doc.revision);
+                                // Undoing a deletion needs the text it removed
+                                let deleted = match &operation {
+                                    Operation::Delete(deletion) => {
+                                        doc.text_in(deletion.range.clone())
+                                    }
+                                    Operation::Insert(_) => None,
+                                };
+                                let applied: Vec<Operation> = if hosting {
+                                    doc.apply_concurrent(author, operation)
+                                } else {
+                                    doc.apply(author, operation).into_iter().collect()
+                                };
+                                for operation in &applied {
+                                    undo_steps.push((operation.clone(), deleted.clone()));
+                                    operations.push(operation.clone());
+                                }
+                                !applied.is_empty()
+                            };
+
+                            let num_deleted = if let Some(s) = selection {
+                                // The selection ends at the cursor or starts there, depending on
+                                // which way it was made
+                                let before = index.checked_sub(s.len());
+                                let start = before
+                                    .filter(|&start| content_text.get(start..index) == Some(&*s))
+                                    .or_else(|| {
+                                        let after = content_text.get(index..index + s.len());
+                                        (after == Some(&*s)).then_some(index)
+                                    });
+                                if let Some(start) = start {
+                                    index = start;
+                                    let deletion = Deletion::new(author, index..(index + s.len()));
+                                    if apply(&mut doc, Operation::Delete(deletion)) {
+                                        s.len()
+                                    } else {
+                                        0
+                                    }
+                                } else {
+                                    // Selection not found
+                                    0
+                                }
+                            } else {
+                                0
+                            };
+
+                            match edit {
+                                text_editor::Edit::Insert(ch) => {
+                                    let insertion = Insertion::new(author, index, ch.to_string());
+                                    apply(&mut doc, Operation::Insert(insertion));
+                                }
+                                text_editor::Edit::Paste(text) => {
+                                    let insertion = Insertion::new(author, index, text.to_string());
+                                    apply(&mut doc, Operation::Insert(insertion));
+                                }
+                                text_editor::Edit::Enter => {
+                                    let insertion = Insertion::new(author, index, "\n".to_string());
+                                    apply(&mut doc, Operation::Insert(insertion));
+                                }
+                                text_editor::Edit::Delete => {
+                                    let char_len = doc.char_at(index).map_or(1, char::len_utf8);
+                                    if num_deleted == 0 {
+                                        let deletion =
+                                            Deletion::new(author, index..(index + char_len));
+                                        apply(&mut doc, Operation::Delete(deletion));
+                                    }
+                                }
+                                text_editor::Edit::Backspace => {
+                                    let char_len = doc.char_before(index).map_or(1, char::len_utf8);
+                                    if num_deleted == 0 && index > 0 {
+                                        let deletion = Deletion::new(
+                                            author,
+                                            index.saturating_sub(char_len)..index,
+                                        );
+                                        apply(&mut doc, Operation::Delete(deletion));
+                                    }
+                                }
+                            }
+
+                            if let Ok(mut history) = undo.lock() {
+                                history.record_action(undo_steps);
+                            }
+
+                            let mut unsent = false;
+                            if let Some(conn) = connection.as_mut() {
+                                for op in operations {
+                                    let message = ProtocolMessage::from(op).to_ws_text();
+                                    if let Ok(mut log) = op_log.lock() {
+                                        log.record(oplog::Direction::Sent, message.clone());
+                                    }
+                                    if let Err(error) = conn.send(client::Message::User(message)) {
+                                        println!("Failed to send edit: {}", error);
+                                        unsent = true;
+                                        break;
+                                    }
+                                }
+                            }
+                            *is_dirty_lock.lock().await = true;
+
+                            if unsent {
+                                Message::EditUnsent(generation)
+                            } else {
+                                Message::NoOp
+                            }
+                        }));
+                    }
+                    text_editor::Action::Scroll { lines } => {
+                        let max_offset = self.content.line_count().saturating_sub(1);
+                        self.scroll_offset = self
+                            .scroll_offset
+                            .saturating_add_signed(lines as isize)
+                            .min(max_offset);
+                        // Only a shared viewport makes scrolling worth telling the others about
+                        if !self.settings.share_viewport {
+                            return Task::batch(tasks);
+                        }
+                    }
+                    _ => tasks.push(Task::done(Message::NoOp)),
+                }
+
+                tasks.push(self.broadcast_cursor());
+                return Task::batch(tasks);
+            }
+            Message::Menu(menu_msg) => match menu_msg {
+                // The session document takes precedence, so nothing else may be loaded over it
+                MenuMessage::OpenFile
+                | MenuMessage::OpenRecent(_)
+                | MenuMessage::OpenUrl
+                | MenuMessage::FileOpened(_)
+                | MenuMessage::UrlOpened(_)
+                    if self.joined_session =>
+                {
+                    self.error_banner =
+                        Some("Leave the session before opening another document.".to_string());
+                }
+                // Opening the current file again only reloads it, which is only worth confirming
+                // when that throws away edits
+                MenuMessage::FileOpened(Ok((path, contents)))
+                    if self.file.as_ref() == Some(&path) && !self.started_session =>
+                {
+                    if self.unsaved_changes {
+                        self.reopen_pending = Some(contents);
+                    }
+                }
+                // A host replaces the shared document for everyone, so confirm first
+                MenuMessage::FileOpened(Ok((path, contents))) if self.started_session => {
+                    self.host_open_pending = Some((Some(path), contents));
+                }
+                MenuMessage::UrlOpened(Ok((_, contents))) if self.started_session => {
+                    self.host_open_pending = Some((None, contents));
+                }
+                MenuMessage::ThemeSelected(theme) => {
+                    self.theme = theme;
+                    if self.settings.highlight_theme_follows_app {
+                        self.settings.highlight_theme = default_highlight_theme(&self.theme);
+                        return self.save_settings();
+                    }
+                }
+                MenuMessage::ExportOpLog => {
+                    let contents = self
+                        .op_log
+                        .lock()
+                        .map(|log| log.to_jsonl())
+                        .unwrap_or_default();
+                    return Task::perform(oplog::export(contents), MenuMessage::OpLogExported)
+                        .map(Message::Menu);
+                }
+                MenuMessage::ExportPlainText => {
+                    let text = self.content.text();
+                    let (_, body) = frontmatter::parse(&text);
+                    let items: Vec<markdown::Item> =
+                        markdown::parse(&math::preserve(body)).collect();
+                    let plain =
+                        plaintext::from_markdown(&items, self.settings.plain_text_link_urls);
+
+                    return self.track_file_dialog(
+                        Task::perform(save_file(None, plain), MenuMessage::PlainTextExported)
+                            .map(Message::Menu),
+                    );
+                }
+                MenuMessage::PublishSnapshot => {
+                    let text = self.content.text();
+                    let (metadata, body) = frontmatter::parse(&text);
+                    let pages: Vec<Vec<markdown::Item>> = html::split_pages(body)
+                        .iter()
+                        .map(|page| markdown::parse(&math::preserve(page)).collect())
+                        .collect();
+                    // Named after the note, falling back to the file it was opened from
+                    let title = metadata
+                        .and_then(|metadata| metadata.title)
+                        .or_else(|| {
+                            self.file
+                                .as_ref()
+                                .and_then(|path| path.file_stem())
+                                .map(|name| name.to_string_lossy().into_owned())
+                        })
+                        .unwrap_or_else(|| String::from("Untitled"));
+
+                    let custom_css = self.settings.export_css.clone();
+                    if let Some(path) = custom_css.as_ref().filter(|path| !path.is_file()) {
+                        self.error_banner =
+                            Some(format!("Stylesheet {} does not exist.", DisplayPath(path)));
+                        return Task::none();
+                    }
+                    let style = self.settings.export_style;
+
+                    return self.track_file_dialog(
+                        Task::perform(
+                            async move {
+                                let css = html::stylesheet(custom_css, style).await?;
+                                save_file(None, html::from_markdown(&pages, &title, &css)).await
+                            },
+                            MenuMessage::SnapshotPublished,
+                        )
+                        .map(Message::Menu),
+                    );
+                }
+                MenuMessage::SnapshotPublished(result) => match result {
+                    Ok(path) => println!("Snapshot published at: {}", DisplayPath(&path)),
+                    Err(error) => println!("Failed to publish snapshot: {:?}", error),
+                },
+                MenuMessage::PlainTextExported(result) => {
+                    if let Err(error) = result {
+                        println!("Failed to export plain text: {:?}", error);
+                    }
+                }
+                MenuMessage::OpLogExported(result) => {
+                    if let Err(error) = result {
+                        println!("Failed to export operation log: {:?}", error);
+                    }
+                }
+                MenuMessage::ReportProblem => {
+                    let op_log = self
+                        .op_log
+                        .lock()
+                        .map(|log| log.to_jsonl())
+                        .unwrap_or_default();
+                    let secrets: Vec<String> = [
+                        &self.read_password,
+                        &self.edit_password,
+                        &Some(self.modal_content.session_password_input.clone()),
+                        &Some(self.modal_content.read_password_input.clone()),
+                        &Some(self.modal_content.write_password_input.clone()),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .chain(self.known_access_passwords.values().cloned())
+                    .collect();
+                    let report = diagnostics::report(
+                        &self.settings,
+                        &self.session_info(),
+                        &op_log,
+                        &secrets,
+                    );
+                    return self.track_file_dialog(
+                        Task::perform(diagnostics::export(report), MenuMessage::ReportSaved)
+                            .map(Message::Menu),
+                    );
+                }
+                MenuMessage::ReportSaved(result) => {
+                    if let Err(error) = result {
+                        println!("Failed to save problem report: {:?}", error);
+                    }
+                }
+                MenuMessage::HighlightThemeSelected(highlight_theme) => {
+                    self.settings.highlight_theme = highlight_theme;
+                    self.settings.highlight_theme_follows_app = false;
+                    return self.save_settings();
+                }
+                MenuMessage::NewTab => {
+                    return self.new_tab();
+                }
+                MenuMessage::CloseFile => {
+                    self.file = None;
+                    self.set_plain_text(false);
+                    self.set_content(text_editor::Content::new());
+                    self.scroll_offset = 0;
+                    self.unsaved_changes = false;
+                    self.update_preview();
+
+                    let document = self.document.clone();
+                    return Task::future(async move {
+                        let mut doc_lock = document.lock().await;
+                        doc_lock.replace("");
+                        Message::NoOp
+                    });
+                }
+                MenuMessage::FileOpened(result) => match result {
+                    Ok((path, contents)) => {
+                        let remember = self.add_recent(path.clone());
+                        return Task::batch([remember, self.load_file(path, &contents)]);
+                    }
+                    // Closing the dialog is not a failure, the open document just stays
+                    Err(error) if error == OPEN_CANCELLED => {}
+                    Err(error) => {
+                        println!("Failed to open file: {:?}", error);
+                        self.error_banner = Some(error);
+                    }
+                },
+                MenuMessage::OpenFolder => {
+                    return self.track_file_dialog(
+                        Task::perform(open_folder(), MenuMessage::FolderOpened).map(Message::Menu),
+                    );
+                }
+                MenuMessage::FolderOpened(result) => match result {
+                    Ok((root, notes)) => {
+                        self.workspace_root = Some(root);
+                        self.workspace_files = notes;
+                    }
+                    Err(error) if error == OPEN_CANCELLED => {}
+                    Err(error) => {
+                        println!("Failed to open folder: {:?}", error);
+                        self.error_banner = Some(error);
+                    }
+                },
+                MenuMessage::OpenFile => {
+                    return self.track_file_dialog(
+                        Task::perform(open_file(), MenuMessage::FileOpened).map(Message::Menu),
+                    );
+                }
+                MenuMessage::OpenRecent(path) => {
+                    return Task::perform(load_file(path), MenuMessage::FileOpened)
+                        .map(Message::Menu);
+                }
+                MenuMessage::UrlChanged(url) => {
+                    self.menubar.set_url(url);
+                }
+                MenuMessage::OpenUrl => {
+                    return Task::perform(open_url(self.menubar.url().to_string()), |result| {
+                        Message::Menu(MenuMessage::UrlOpened(result))
+                    });
+                }
+                MenuMessage::UrlOpened(result) => match result {
+                    Ok((url, contents)) => {
+                        // Remote documents have no local path, so saving prompts for one
+                        self.file = None;
+                        self.set_plain_text(false);
+                        self.error_banner = None;
+                        self.set_content(text_editor::Content::with_text(&contents));
+                        self.scroll_offset = 0;
+                        self.update_preview();
+                        println!("URL loaded: {}", url);
+
+                        let document = self.document.clone();
+                        let content = self.buffer_text();
+                        return Task::future(async move {
+                            let mut doc_lock = document.lock().await;
+                            doc_lock.replace(content);
+                            Message::NoOp
+                        });
+                    }
+                    Err(error) => {
+                        println!("Failed to open URL: {:?}", error);
+                        self.error_banner = Some(error);
+                    }
+                },
+                MenuMessage::FileSaved(result) => match result {
+                    Ok(path) => {
+                        println!("File saved at: {}", DisplayPath(&path));
+                        let remember = self.add_recent(path.clone());
+                        self.save_status = SaveStatus::Saved(SystemTime::now());
+                        self.recovery_pending = false;
+                        self.recovery_hash = None;
+                        self.unsaved_changes = false;
+                        let leave = if std::mem::take(&mut self.leave_after_save) {
+                            Task::done(Message::ConfirmLeaveSession)
+                        } else {
+                            Task::none()
+                        };
+                        return Task::batch([
+                            remember,
+                            Task::future(self.recovery.clone().remove()).map(|_| Message::NoOp),
+                            leave,
+                        ]);
+                    }
+                    Err(error) => {
+                        println!("Failed to save file: {:?}", error);
+                        self.saved_hash = None;
+                        self.save_status = if error == SAVE_CANCELLED {
+                            SaveStatus::Idle
+                        } else {
+                            SaveStatus::Failed
+                        };
+                        // Without the copy the unsynced edits would be lost, so ask again
+                        if std::mem::take(&mut self.leave_after_save) {
+                            self.leave_confirm_open = true;
+                        }
+                        return Task::done(Message::NoOp);
+                    }
+                },
+                MenuMessage::SaveFile => {
+                    let text = self.content.text();
+                    let hash = content_hash(&text);
+                    // Without a file the save asks where to go, so it is never skipped
+                    if self.file.is_some() && self.saved_hash == Some(hash) {
+                        println!("Nothing changed since the last save, skipping it");
+                        self.unsaved_changes = false;
+                        return Task::none();
+                    }
+                    self.saved_hash = self.file.is_some().then_some(hash);
+
+                    self.save_status = SaveStatus::Saving;
+                    return self.track_file_dialog(
+                        Task::perform(save_file(self.file.clone(), text), MenuMessage::FileSaved)
+                            .map(Message::Menu),
+                    );
+                }
+            },
+            // Markdown syntax has no meaning in plain text, so formatting hotkeys do nothing
+            Message::Format(_) if self.plain_text => {}
+            Message::Format(text_style) => {
+                let _ = self.format_bar.update(text_style.clone()); // Update the format bar UI
+
+                return match text_style {
+                    TextStyle::Bold => self.toggle_formatting(TextStyle::Bold),
+                    TextStyle::Italic => self.toggle_formatting(TextStyle::Italic),
+                    TextStyle::Strikethrough => self.toggle_formatting(TextStyle::Strikethrough),
+                    TextStyle::Highlight => self.toggle_formatting(TextStyle::Highlight),
+                    TextStyle::Math => self.toggle_formatting(TextStyle::Math),
+                    TextStyle::MathBlock => self.toggle_formatting(TextStyle::MathBlock),
+                    TextStyle::HorizontalRule => self.insert_block("---"),
+                    TextStyle::PageBreak => self.insert_block(html::PAGE_BREAK),
+                    TextStyle::Wrap(open, close) => self.wrap_selection(&open, &close),
+                    TextStyle::TextSize(size) => {
+                        // Update the text size
+                        let text_size = if let Ok(size) = size.parse::<f32>() {
+                            iced::Pixels::from(size)
+                        } else {
+                            iced::Pixels::from(DEFAULT_FONT_SIZE)
+                        };
+
+                        self.markdown_settings = markdown::Settings::with_text_size(text_size);
+                        Task::done(Message::NoOp)
+                    }
+                    TextStyle::WrapOpenChanged(_)
+                    | TextStyle::WrapCloseChanged(_)
+                    | TextStyle::ToggleOverflow => Task::none(),
+                };
+            }
+            Message::LinkClicked(url) => {
+                let _ = open::that(url.to_string());
+            }
+            Message::NoOp => {}
+            Message::RevisionApplied(generation, revision) => {
+                if generation == self.session_generation {
+                    self.applied_revision = revision;
+                }
+            }
+            Message::EditUnsent(generation) => {
+                // Counted like an edit made while disconnected, so leaving still asks first
+                if generation == self.session_generation && self.joined_session {
+                    self.unsynced_edits += 1;
+                }
+            }
+            Message::DeleteLine => {
+                let tasks = vec![
+                    Task::done(Message::Action(text_editor::Action::SelectLine)),
+                    Task::done(Message::Action(text_editor::Action::Edit(
+                        text_editor::Edit::Delete,
+                    ))),
+                ];
+
+                return Task::batch(tasks);
+            }
+            Message::DeleteWord => {
+                let tasks = vec![
+                    Task::done(Message::Action(text_editor::Action::SelectWord)),
+                    Task::done(Message::Action(text_editor::Action::Edit(
+                        text_editor::Edit::Delete,
+                    ))),
+                ];
+
+                return Task::batch(tasks);
+            }
+            Message::SortLines(descending) => {
+                let sorted = self.content.selection().map(|selection| {
+                    sort_lines(&selection, descending, self.settings.sort_case_sensitive)
+                });
+                return self.replace_lines(sorted);
+            }
+            Message::UniqueLines(everywhere) => {
+                let unique = self
+                    .content
+                    .selection()
+                    .map(|selection| unique_lines(&selection, everywhere));
+                return self.replace_lines(unique);
+            }
+            Message::GoToMatchingBracket => {
+                let text = self.buffer_text();
+                let (line, col) = self.content.cursor_position();
+                let caret = text
+                    .split('\n')
+                    .take(line)
+                    .map(|line| line.len() + 1)
+                    .sum::<usize>()
+                    + col;
+                if let Some(bracket) = matching_bracket(&text, caret) {
+                    let (line, col) = position_at(&text, bracket);
+                    self.move_caret_to(line, col);
+                }
+            }
+            Message::ToggleComment => {
+                let (open, close) = self.comment_markers();
+                if let Some(selection) = self.content.selection() {
+                    return self.replace_lines(Some(toggle_comment(&selection, open, close)));
+                }
+
+                // Without a selection the caret's line is toggled
+                let (line, _) = self.content.cursor_position();
+                let Some(text) = self.content.line(line).map(|text| text.to_string()) else {
+                    return Task::none();
+                };
+                let toggled = toggle_comment(&text, open, close);
+                if toggled == text {
+                    return Task::none();
+                }
+                return Task::done(Message::Action(text_editor::Action::SelectLine))
+                    .chain(self.replace_lines(Some(toggled)));
+            }
+            Message::ConvertSelection(codec) => {
+                self.shortcut_palette_open = false;
+                let Some(selection) = self.content.selection() else {
+                    self.error_banner = Some("Select the text to convert first.".to_string());
+                    return Task::none();
+                };
+                // A failed decode leaves the text as it was
+                match codec.apply(&selection) {
+                    Ok(converted) => return self.replace_lines(Some(converted)),
+                    Err(error) => self.error_banner = Some(error),
+                }
+            }
+            Message::Undo | Message::Redo => {
+                let replay = if matches!(message, Message::Undo) {
+                    Replay::Undo
+                } else {
+                    Replay::Redo
+                };
+                let step = self
+                    .undo
+                    .lock()
+                    .ok()
+                    .and_then(|mut history| history.pop(replay));
+                if let Some(step) = step {
+                    return Task::done(Message::UndoStep(replay, step));
+                }
+            }
+            Message::UndoDepthSelected(depth) => {
+                self.settings.undo_depth = depth;
+                if let Ok(mut history) = self.undo.lock() {
+                    history.set_depth(depth);
+                }
+                return self.save_settings();
+            }
+            Message::UndoMemorySelected(megabytes) => {
+                self.settings.undo_memory_mb = megabytes;
+                if let Ok(mut history) = self.undo.lock() {
+                    history.set_memory_budget(megabytes * undo::MEGABYTE);
+                }
+                return self.save_settings();
+            }
+            Message::UndoSpillToggled(toggled) => {
+                self.settings.undo_spill = toggled;
+                if let Ok(mut history) = self.undo.lock() {
+                    history.set_spill(toggled);
+                }
+                return self.save_settings();
+            }
+            Message::UndoStep(replay, step) => {
+                // The inverse goes through the editor like any other edit, so it reaches the
+                // document and the others as a new operation
+                let text = self.buffer_text();
+                return match step {
+                    undo::Step::Deleted { at, text: deleted } => {
+                        let (line, col) = position_at(&text, at);
+                        self.move_caret_to(line, col);
+                        self.undo_edit(replay, text_editor::Edit::Paste(Arc::new(deleted)))
+                    }
+                    undo::Step::Inserted(mut ranges) => {
+                        // The last range first, so the ones before it stay where they are
+                        let Some(range) = ranges.pop() else {
+                            return Task::none();
+                        };
+                        let Some(inserted) = text.get(range.clone()) else {
+                            return Task::none();
+                        };
+                        let (line, col) = position_at(&text, range.start);
+                        self.move_caret_to(line, col);
+                        (0..inserted.chars().count()).for_each(|_| {
+                            self.content
+                                .perform(text_editor::Action::Select(text_editor::Motion::Right));
+                        });
+
+                        let edit = self.undo_edit(replay, text_editor::Edit::Delete);
+                        if ranges.is_empty() {
+                            edit
+                        } else {
+                            let rest = undo::Step::Inserted(ranges);
+                            edit.chain(Task::done(Message::UndoStep(replay, rest)))
+                        }
+                    }
+                };
+            }
+            Message::SortCaseSensitiveToggled(toggled) => {
+                self.settings.sort_case_sensitive = toggled;
+                return self.save_settings();
+            }
+            Message::ShortcutPaletteToggle => {
+                self.shortcut_palette_open = !self.shortcut_palette_open;
+            }
+            Message::OpenFind(replace) => {
+                self.find_open = true;
+                self.replace_open = replace;
+                // A selection within one line is what to look for
+                if let Some(selection) = self.content.selection().filter(|s| !s.contains('\n')) {
+                    self.find_query = selection;
+                }
+                self.refresh_find();
+                return text_input::focus(find_input_id());
+            }
+            Message::CloseFind => {
+                self.find_open = false;
+                self.find_matches.clear();
+                self.find_current = None;
+            }
+            Message::FindChanged(query) => {
+                self.find_query = query;
+                self.refresh_find();
+            }
+            Message::ReplaceChanged(replacement) => {
+                self.replace_input = replacement;
+            }
+            Message::FindIgnoreCaseToggled(toggled) => {
+                self.find_ignore_case = toggled;
+                self.refresh_find();
+            }
+            Message::FindWholeWordToggled(toggled) => {
+                self.find_whole_word = toggled;
+                self.refresh_find();
+            }
+            Message::FindNext => {
+                // The first match after the caret, wrapping around to the top
+                let caret = self.caret_position();
+                let next = self
+                    .find_matches
+                    .iter()
+                    .position(|found| (found.line, found.columns.start) >= caret)
+                    .or((!self.find_matches.is_empty()).then_some(0));
+                if let Some(index) = next {
+                    let found = self.find_matches[index].clone();
+                    self.select_match(&found);
+                    self.find_current = Some(index);
+                }
+            }
+            Message::ReplaceAll => {
+                if !self.read_only() {
+                    return Task::done(Message::ReplaceMatches(self.find_matches.clone()));
+                }
+            }
+            Message::ReplaceMatches(mut matches) => {
+                let Some(found) = matches.pop() else {
+                    return Task::none();
+                };
+                // Each replacement goes through the editor like any other edit, so it reaches
+                // the document and the others as operations
+                self.select_match(&found);
+                let edit = if self.replace_input.is_empty() {
+                    text_editor::Edit::Delete
+                } else {
+                    text_editor::Edit::Paste(Arc::new(self.replace_input.clone()))
+                };
+                return Task::done(Message::Action(text_editor::Action::Edit(edit)))
+                    .chain(Task::done(Message::ReplaceMatches(matches)));
+            }
+            Message::PlainTextToggled(toggled) => {
+                self.set_plain_text(toggled);
+                self.update_preview();
+            }
+            Message::ShowMarkdownPreview(toggled) => {
+                self.markdown_preview_open = toggled;
+                if !toggled {
+                    self.preview_focused = false;
+                }
+                self.update_preview();
+            }
+            Message::TogglePreviewFocus => {
+                if self.markdown_preview_open {
+                    self.preview_focused = !self.preview_focused;
+                    self.preview_link = None;
+                }
+            }
+            Message::PreviewScroll(y) => {
+                return scrollable::scroll_by(
+                    preview_scrollable_id(),
+                    scrollable::AbsoluteOffset { x: 0.0, y },
+                );
+            }
+            Message::PreviewNextLink => {
+                let links = markdown_links(&self.content.text());
+                self.preview_link = if links.is_empty() {
+                    None
+                } else {
+                    Some(
+                        self.preview_link
+                            .map_or(0, |index| (index + 1) % links.len()),
+                    )
+                };
+            }
+            Message::PreviewOpenLink => {
+                let links = markdown_links(&self.content.text());
+                if let Some(link) = self.preview_link.and_then(|index| links.get(index)) {
+                    let _ = open::that(link);
+                }
+            }
+            Message::AutoCapitalizeToggled(toggled) => {
+                self.settings.auto_capitalize = toggled;
+                return self.save_settings();
+            }
+            Message::SmartQuotesToggled(toggled) => {
+                self.settings.smart_quotes = toggled;
+                return self.save_settings();
+            }
+            Message::LineNumbersToggled(toggled) => {
+                self.settings.show_line_numbers = toggled;
+                return self.save_settings();
+            }
+            Message::ShareViewportToggled(toggled) => {
+                self.settings.share_viewport = toggled;
+                return self.save_settings();
+            }
+            Message::OpLogToggled(toggled) => {
+                self.settings.op_log_enabled = toggled;
+                if let Ok(mut log) = self.op_log.lock() {
+                    log.set_enabled(toggled);
+                }
+                return self.save_settings();
+            }
+            Message::HighlightFollowsThemeToggled(toggled) => {
+                self.settings.highlight_theme_follows_app = toggled;
+                if toggled {
+                    self.settings.highlight_theme = default_highlight_theme(&self.theme);
+                }
+                return self.save_settings();
+            }
+            Message::PlainTextLinkUrlsToggled(toggled) => {
+                self.settings.plain_text_link_urls = toggled;
+                return self.save_settings();
+            }
+            Message::ListPasteToggled(toggled) => {
+                self.settings.list_paste = toggled;
+                return self.save_settings();
+            }
+            Message::LinkPasteToggled(toggled) => {
+                self.settings.link_paste = toggled;
+                return self.save_settings();
+            }
+            Message::ReopenLastFileToggled(toggled) => {
+                self.settings.reopen_last_file = toggled;
+                return self.save_settings();
+            }
+            Message::RestoreCaret(line, col) => {
+                self.move_caret_to(line, col);
+            }
+            Message::LineHighlightToggled(toggled) => {
+                self.settings.highlight_current_line = toggled;
+                return self.save_settings();
+            }
+            Message::ShowWhitespaceToggled(toggled) => {
+                self.settings.show_whitespace = toggled;
+                return self.save_settings();
+            }
+            Message::EditorPaddingSelected(padding) => {
+                self.settings.editor_padding = padding;
+                return self.save_settings();
+            }
+            Message::EditorMarginSelected(margin) => {
+                self.settings.editor_margin = margin;
+                return self.save_settings();
+            }
+            Message::HighContrastToggled(toggled) => {
+                self.settings.high_contrast = toggled;
+                if self.settings.highlight_theme_follows_app {
+                    self.settings.highlight_theme = default_highlight_theme(&self.theme());
+                }
+                return self.save_settings();
+            }
+            Message::UiScaleSelected(scale) => {
+                self.settings.ui_scale = scale;
+                return self.save_settings();
+            }
+            Message::CursorColorSelected(color) => {
+                self.settings.cursor_color = color;
+                self.cursor_marker.color = color
+                    .rgb()
+                    .unwrap_or_else(|| CursorMarker::new(0.0, LINE_HEIGHT).color);
+                return Task::batch([self.broadcast_cursor(), self.save_settings()]);
+            }
+            Message::LineHighlightColorSelected(color) => {
+                self.settings.line_highlight_color = color;
+                return self.save_settings();
+            }
+            // Starting or joining again would bind the port twice or register this user twice
+            Message::StartSessionPressed
+            | Message::ConfirmStartSession
+            | Message::JoinSessionPressed
+            | Message::ConfirmJoinSession
+                if self.in_session() =>
+            {
+                println!("Ignoring {:?}, already in a session", message);
+                self.start_confirm_open = false;
+                self.join_confirm_open = false;
+            }
+            Message::StartSessionPressed => {
+                // Verify that a server worker has been created
+                if self.server_worker.is_none() {
+                    self.modal_content.session_start_error = String::from(NOT_READY_ERROR);
+                    return Task::none();
+                }
+
+                if self.settings.confirm_session_start {
+                    self.start_confirm_open = true;
+                } else {
+                    return Task::done(Message::ConfirmStartSession);
+                }
+            }
+            Message::CancelStartSession => {
+                self.start_confirm_open = false;
+            }
+            Message::WordCountModeSelected(mode) => {
+                self.settings.word_count_mode = mode;
+                self.update_preview();
+                return self.save_settings();
+            }
+            Message::ExportStyleSelected(style) => {
+                self.settings.export_style = style;
+                return self.save_settings();
+            }
+            Message::ExportCssChanged(path) => {
+                self.settings.export_css = Some(path)
+                    .filter(|path| !path.trim().is_empty())
+                    .map(PathBuf::from);
+                return self.save_settings();
+            }
+            Message::WordGoalChanged(goal) => {
+                let goal = goal.trim();
+                if goal.is_empty() {
+                    self.settings.word_goal = 0;
+                } else if let Ok(goal) = goal.parse() {
+                    self.settings.word_goal = goal;
+                } else {
+                    return Task::none();
+                }
+                return self.save_settings();
+            }
+            Message::ConfirmSessionStartToggled(toggled) => {
+                self.settings.confirm_session_start = toggled;
+                return self.save_settings();
+            }
+            Message::ConfirmStartSession => {
+                self.start_confirm_open = false;
+                let Some(server_worker) = self.server_worker.clone() else {
+                    self.modal_content.session_start_error = String::from(NOT_READY_ERROR);
+                    return Task::none();
+                };
+                let (address, tls_files) = match self
+                    .modal_content
+                    .server_address()
+                    .and_then(|address| Ok((address, self.modal_content.tls_files()?)))
+                {
+                    Ok(server) => server,
+                    Err(error) => {
+                        self.modal_content.session_start_error = error;
+                        return Task::none();
+                    }
+                };
+                self.session_modal_open = !self.session_modal_open;
+                self.started_session = true;
+                self.modal_content.session_start_error.clear();
+
+                let doc = self.document.clone();
+                // If a file path is provided, load the file
+                let file_path = self.modal_content.file_path_input.clone();
+                let load_file_task = if !file_path.is_empty() {
+                    Some(load_file(file_path.clone()))
+                } else {
+                    None
+                };
+                let is_dirty_lock = self.is_dirty.clone();
+                let read_password = if self.modal_content.read_password_input.is_empty() {
+                    self.read_password.clone()
+                } else {
+                    Some(self.modal_content.read_password_input.clone())
+                };
+                let edit_password = if self.modal_content.write_password_input.is_empty() {
+                    self.edit_password.clone()
+                } else {
+                    Some(self.modal_content.write_password_input.clone())
+                };
+                self.read_password = read_password.clone();
+                self.edit_password = edit_password.clone();
+                let users_lock = self.users.clone();
+                let is_moved_lock = self.is_moved.clone();
+                let is_locked_lock = self.is_locked.clone();
+                let enable_metrics = self.modal_content.enable_metrics;
+                let limits = self.modal_content.limits;
+                self.session_limits = Some(limits);
+                let display_name = self.modal_content.display_name_input.clone();
+                let server_thread_lock = self.server_thread.clone();
+                self.idle_lock_timeout = self.modal_content.idle_lock_timeout();
+                self.last_host_activity = Instant::now();
+                self.session_locked = false;
+                return Task::future(async move {
+                    if let Some(load_task) = load_file_task {
+                        match load_task.await {
+                            Ok((_, contents)) => {
+                                // Update the document with loaded file contents
+                                let mut doc_lock = doc.lock().await;
+                                doc_lock.replace(contents.as_str());
+                                // return Message::UpdateHostDoc(doc_lock.clone());
+                            }
+                            Err(err) => {
+                                // Handle file load error (log or return an error message)
+                                eprintln!("Failed to load file: {:?}", err);
+                                return Message::NoOp;
+                            }
+                        }
+                    }
+                    let mut server_thread = server_thread_lock.lock().await;
+                    // The host registers like any other user and learns its id from that
+                    let host_id = {
+                        let mut users = users_lock.lock().await;
+                        let host_id = users.join(HOST_ADDR, SessionType::Edit);
+                        users.set_name(HOST_ADDR, &display_name);
+                        host_id
+                    };
+                    match start_server(
+                        address,
+                        tls_files,
+                        read_password,
+                        edit_password,
+                        doc.clone(),
+                        is_dirty_lock,
+                        users_lock.clone(),
+                        is_moved_lock,
+                        is_locked_lock,
+                        limits,
+                        enable_metrics,
+                        host_id,
+                        server_worker,
+                    )
+                    .await
+                    {
+                        Ok(handle) => *server_thread = Some(handle),
+                        Err(err) => {
+                            eprintln!("Failed to start server: {:?}", err);
+                            users_lock.lock().await.delete_all_users();
+
+                            let reason = if err.kind() == std::io::ErrorKind::AddrInUse {
+                                "Port already in use, choose another.".to_string()
+                            } else {
+                                format!("Could not start session: {}", err)
+                            };
+                            return Message::SessionStartFailed(reason);
+                        }
+                    }
+                    let dock_to_update = doc.lock().await.clone(); // Clone document for the update message
+                    Message::SessionStarted(host_id, dock_to_update)
+                });
+            }
+            Message::UpdateHostDoc(document) => {
+                // Update text editor content with the document content
+                self.replace_content(&document);
+            }
+            Message::SessionStarted(host_id, document) => {
+                self.id = Some(host_id);
+                self.replace_content(&document);
+            }
+            Message::UpdateHostCursors(cursors) => {
+                self.set_user_cursors(cursors);
+            }
+            Message::UpdateHostNames(names) => {
+                self.participant_names = names;
+            }
+            Message::UpdateHostParticipants(participants) => {
+                self.set_participants(participants);
+            }
+            Message::ParticipantsToggle => {
+                self.participants_open = !self.participants_open;
+            }
+            Message::CursorLabelFadeSelected(seconds) => {
+                self.settings.cursor_label_fade = seconds;
+                return self.save_settings();
+            }
+            // Only here to redraw the fading names
+            Message::FadeCursorLabels => {}
+            Message::SessionStartFailed(reason) => {
+                self.started_session = false;
+                self.id = None;
+                self.modal_content.session_start_error = reason;
+                self.session_modal_open = true;
+                self.active_tab = TabId::StartSession;
+            }
+            // Whatever a connection that was left still had in flight
+            Message::Echo(generation, _) if generation != self.session_generation => {}
+            Message::Echo(_, event) => match event {
+                client::Event::Ready(controller) => {
+                    self.connection_controller = Some(controller);
+                }
+                client::Event::ServerDown => {
+                    self.joined_session = false;
+                    self.modal_content.session_join_error =
+                        "Server is down, please contact host.".to_string();
+                }
+                client::Event::UntrustedCertificate => {
+                    self.joined_session = false;
+                    self.modal_content.session_join_error =
+                        "The host's certificate could not be verified.".to_string();
+                }
+                client::Event::IncorrectPassword => {
+                    if let Some((access, password)) = self.access_fallback.take() {
+                        // A refused switch falls back to the access that already worked
+                        if let Some(refused) = self.modal_content.session_selection {
+                            self.known_access_passwords.remove(&refused);
+                            self.error_banner = Some(format!(
+                                "Incorrect {} password, staying with {} access",
+                                refused, access
+                            ));
+                        }
+                        self.modal_content.session_selection = Some(access);
+                        self.modal_content.session_password_input = password;
+                        return Task::none();
+                    }
+
+                    self.joined_session = false;
+                    self.modal_content.session_join_error =
+                        "Incorrect password, please try again.".to_string();
+                }
+                client::Event::Connected(connection) => {
+                    self.client_state = State::Connected(connection.clone());
+                    self.access_fallback = None;
+                    if let Some(access) = self.modal_content.session_selection {
+                        self.known_access_passwords
+                            .insert(access, self.modal_content.session_password_input.clone());
+                    }
+                    self.reconnecting = false;
+                    self.joined_session = true;
+                    self.session_modal_open = false;
+
+                    let position = self.cursor_position_in_pixels();
+                    self.cursor_marker.move_cursor(position, self.line_height);
+
+                    let message = ProtocolMessage::Cursor(self.cursor_marker).to_ws_text();
+                    if self.leave_session {
+                        connection.clone().close();
+                    }
+
+                    // Send the message
+                    if let Err(error) = connection.clone().send(client::Message::User(message)) {
+                        println!("Failed to send cursor: {}", error);
+                    }
+                }
+                client::Event::Disconnected => {
+                    self.client_state = State::Disconnected;
+                    self.reconnecting = true;
+                    println!("DISCONNECTED");
+                    self.user_cursors.clear();
+                    self.participants.clear();
+                    self.participant_names.clear();
+                    self.host_participant = None;
+                    self.session_locked = false;
+                }
+                client::Event::MessageReceived(message) => {
+                    // Extract the message as a string
+                    let message_text = message.as_str();
+                    if let Ok(mut log) = self.op_log.lock() {
+                        log.record(oplog::Direction::Received, message_text);
+                    }
+
+                    let message = match ProtocolMessage::from_ws_text(message_text) {
+                        Ok(message) => message,
+                        Err(error) => {
+                            println!("Failed to parse message from the host: {}", error);
+                            return Task::none();
+                        }
+                    };
+                    match message {
+                        ProtocolMessage::Users(participants) => {
+                            self.set_participants(participants);
+                        }
+                        ProtocolMessage::Document(server_doc) => {
+                            // The host's copy wins, so anything unsynced is gone now
+                            self.unsynced_edits = 0;
+                            self.received_revision = server_doc.revision;
+                            self.replace_content(&server_doc);
+
+                            let doc_lock = self.document.clone();
+                            let generation = self.session_generation;
+                            return Task::future(async move {
+                                let mut doc = doc_lock.lock().await;
+                                *doc = server_doc;
+
+                                Message::RevisionApplied(generation, doc.revision)
+                            });
+                        }
+                        ProtocolMessage::Locked(locked) => {
+                            self.session_locked = locked;
+                        }
+                        // The server names the client it wants to slow down
+                        ProtocolMessage::Throttled(throttled_id)
+                            if Some(throttled_id) == self.id =>
+                        {
+                            self.throttled_until = Some(Instant::now() + THROTTLE_NOTICE);
+                            if let Some(controller) = &mut self.connection_controller {
+                                let edits_per_second = self
+                                    .session_limits
+                                    .map_or(MAX_EDITS_PER_SECOND, |limits| limits.edits_per_second)
+                                    .max(1);
+                                controller.pace(Duration::from_millis(
+                                    1000 / u64::from(edits_per_second),
+                                ));
+                            }
+                        }
+                        // The host refused an edit of ours, so fetch its copy again
+                        ProtocolMessage::Rejected(rejected_id) if Some(rejected_id) == self.id => {
+                            self.error_banner = Some(
+                                "The host refused an edit that would make the document larger than the session allows."
+                                    .to_string(),
+                            );
+                            if let Some(controller) = &mut self.connection_controller {
+                                controller.resync();
+                            }
+                        }
+                        ProtocolMessage::Limits(limits) => {
+                            self.session_limits = Some(limits);
+                        }
+                        ProtocolMessage::Id(id) => {
+                            self.id = Some(id);
+                            // The participants can arrive before this client knows which it is
+                            self.user_cursors.retain(|(user, _)| *user != id);
+                        }
+                        _ => {}
+                    }
+                }
+            },
+            Message::ReadPasswordChanged(password) => {
+                self.modal_content.read_password_input = password;
+            }
+            Message::WritePasswordChanged(password) => {
+                self.modal_content.write_password_input = password;
+            }
+            Message::SessionPasswordChanged(password) => {
+                self.modal_content.session_password_input = password;
+            }
+            Message::SwitchAccess => {
+                let Some(current) = self.modal_content.session_selection else {
+                    return Task::none();
+                };
+                if !self.joined_session {
+                    return Task::none();
+                }
+
+                match self.known_access_passwords.get(&current.other()).cloned() {
+                    Some(password) => return self.switch_access(current.other(), password),
+                    None => {
+                        self.access_password_input.clear();
+                        self.access_prompt_open = true;
+                    }
+                }
+            }
+            Message::AccessPasswordChanged(password) => {
+                self.access_password_input = password;
+            }
+            Message::ConfirmAccessSwitch => {
+                self.access_prompt_open = false;
+                if let Some(current) = self.modal_content.session_selection {
+                    let password = std::mem::take(&mut self.access_password_input);
+                    return self.switch_access(current.other(), password);
+                }
+            }
+            Message::CancelAccessSwitch => {
+                self.access_prompt_open = false;
+                self.access_password_input.clear();
+            }
+            Message::DisplayNameChanged(name) => {
+                self.modal_content.display_name_input = name;
+            }
+            Message::ServerHostChanged(host) => {
+                self.modal_content.host_input = host;
+            }
+            Message::ServerPortChanged(port) => {
+                self.modal_content.port_input = port;
+            }
+            Message::RoomChanged(room) => {
+                self.modal_content.room_input = room;
+            }
+            Message::ShowPasswordsToggled => {
+                self.modal_content.show_passwords = !self.modal_content.show_passwords;
+            }
+            Message::SessionTypeRequested(choice) => {
+                self.modal_content.session_selection = Some(choice);
+            }
+            Message::JoinSessionPressed => {
+                if self.unsaved_changes {
+                    self.join_confirm_open = true;
+                } else {
+                    return Task::done(Message::ConfirmJoinSession);
+                }
+            }
+            Message::ConfirmJoinSession => {
+                // The joined session's document replaces the local buffer, which no longer
+                // belongs to the previously opened file
+                self.join_confirm_open = false;
+                self.file = None;
+                self.unsaved_changes = false;
+                self.joined_session = true;
+            }
+            Message::CancelJoinSession => {
+                self.join_confirm_open = false;
+            }
+            Message::ConfirmHostOpen => {
+                if let Some((path, contents)) = self.host_open_pending.take() {
+                    self.set_plain_text(
+                        path.as_deref().is_some_and(|path| !is_markdown_file(path)),
+                    );
+                    self.file = path;
+                    self.set_content(text_editor::Content::with_text(&contents));
+                    self.scroll_offset = 0;
+                    self.unsaved_changes = false;
+                    self.update_preview();
+
+                    // Marking the document dirty makes the server resync every client
+                    let document = self.document.clone();
+                    let is_dirty_lock = self.is_dirty.clone();
+                    let content = self.buffer_text();
+                    let id = self.id;
+                    return Task::future(async move {
+                        let mut doc_lock = document.lock().await;
+                        doc_lock.replace(content);
+                        if let Some(id) = id {
+                            doc_lock.last_edit = id;
+                        }
+                        *is_dirty_lock.lock().await = true;
+                        Message::NoOp
+                    });
+                }
+            }
+            Message::ConfirmReopen => {
+                if let (Some(contents), Some(path)) =
+                    (self.reopen_pending.take(), self.file.clone())
+                {
+                    return self.load_file(path, &contents);
+                }
+            }
+            Message::CancelReopen => {
+                self.reopen_pending = None;
+            }
+            Message::DocumentTabSelected(index) => {
+                return self.select_tab(index);
+            }
+            Message::CloseTab(index) => {
+                let unsaved_changes = if index == self.current_tab {
+                    self.unsaved_changes
+                } else {
+                    self.tabs
+                        .get(index)
+                        .and_then(Option::as_ref)
+                        .is_some_and(|tab| tab.unsaved_changes)
+                };
+                if unsaved_changes {
+                    self.close_tab_pending = Some(index);
+                } else {
+                    return self.close_tab(index);
+                }
+            }
+            Message::ConfirmCloseTab => {
+                if let Some(index) = self.close_tab_pending.take() {
+                    return self.close_tab(index);
+                }
+            }
+            Message::CancelCloseTab => {
+                self.close_tab_pending = None;
+            }
+            Message::WorkspaceFileSelected(path) => {
+                // Reopening the current file has its own confirmation once it is read
+                if self.unsaved_changes && self.file.as_ref() != Some(&path) {
+                    self.workspace_open_pending = Some(path);
+                } else {
+                    return Task::perform(load_file(path), MenuMessage::FileOpened)
+                        .map(Message::Menu);
+                }
+            }
+            Message::ConfirmWorkspaceOpen => {
+                if let Some(path) = self.workspace_open_pending.take() {
+                    return Task::perform(load_file(path), MenuMessage::FileOpened)
+                        .map(Message::Menu);
+                }
+            }
+            Message::CancelWorkspaceOpen => {
+                self.workspace_open_pending = None;
+            }
+            Message::HardWrapColumnSelected(column) => {
+                self.settings.hard_wrap_column = column;
+                return self.save_settings();
+            }
+            Message::WrapLongLines => {
+                self.long_line_prompt = None;
+                let wrapped = hard_wrap(&self.buffer_text(), self.settings.hard_wrap_column);
+                self.set_content(text_editor::Content::with_text(&wrapped));
+                self.unsaved_changes = true;
+                self.update_preview();
+
+                let document = self.document.clone();
+                return Task::future(async move {
+                    document.lock().await.replace(wrapped);
+                    Message::NoOp
+                });
+            }
+            Message::KeepLongLines => {
+                self.long_line_prompt = None;
+            }
+            Message::CancelHostOpen => {
+                self.host_open_pending = None;
+            }
+            Message::SessionModalToggle => {
+                self.session_modal_open = !self.session_modal_open;
+            }
+            Message::TabSelected(selected) => {
+                self.active_tab = selected;
+            }
+            Message::IdleLockChanged(minutes) => {
+                self.modal_content.idle_lock_input = minutes;
+            }
+            Message::SecureSessionToggled(toggled) => {
+                self.modal_content.secure = toggled;
+            }
+            Message::TlsCertPathChanged(path) => {
+                self.modal_content.cert_path_input = path;
+            }
+            Message::TlsKeyPathChanged(path) => {
+                self.modal_content.key_path_input = path;
+            }
+            Message::MetricsToggled(toggled) => {
+                self.modal_content.enable_metrics = toggled;
+            }
+            Message::MaxDocumentSizeSelected(megabytes) => {
+                self.modal_content.limits.max_document_bytes = megabytes * 1024 * 1024;
+            }
+            Message::EditRateSelected(edits) => {
+                self.modal_content.limits.edits_per_second = edits;
+            }
+            Message::CheckIdle => {
+                if let Some(timeout) = self.idle_lock_timeout {
+                    if !self.session_locked && self.last_host_activity.elapsed() >= timeout {
+                        println!("Host idle, locking session to read-only");
+                        return self.set_session_lock(true);
+                    }
+                }
+            }
+            Message::CheckConsistency => {
+                if self.last_document_change.elapsed() < CONSISTENCY_CHECK_INTERVAL {
+                    return Task::none();
+                }
+
+                // The editor's text is what the user sees, so it wins over the shared copy
+                let document = self.document.clone();
+                let is_dirty_lock = self.is_dirty.clone();
+                let content = self.buffer_text();
+                let id = self.id;
+                let is_host = self.started_session;
+                return Task::future(async move {
+                    let mut doc_lock = document.lock().await;
+                    // A host's copy last edited by someone else is waiting to be shown, not broken
+                    let is_pending_remote_edit = is_host && id != Some(doc_lock.last_edit);
+                    if doc_lock.has_text(&content) || is_pending_remote_edit {
+                        return Message::NoOp;
+                    }
+
+                    println!(
+                        "Document out of sync with the editor ({} characters, editor has {}), repairing",
+                        doc_lock.char_len(),
+                        content.chars().count()
+                    );
+                    doc_lock.replace(content);
+                    if !is_host {
+                        return Message::ResyncDocument;
+                    }
+
+                    *is_dirty_lock.lock().await = true;
+                    Message::NoOp
+                });
+            }
+            Message::ResyncDocument => {
+                if let Some(controller) = self.connection_controller.as_mut() {
+                    controller.resync();
+                }
+            }
+            Message::FilePathChanged(file_path) => {
+                self.modal_content.file_path_input = file_path;
+                self.modal_content.validate_file();
+            }
+            Message::RequestClose => {
+                println!("Closing server...");
+                let server_thread_lock = self.server_thread.clone();
+                let users_lock = self.users.clone();
+
+                return Task::future(async move {
+                    // Let the server finish what it is doing before the session ends
+                    let server = server_thread_lock.lock().await.take();
+                    if let Some(server) = server {
+                        server.shutdown().await;
+                    }
+
+                    // Clear all users
+                    let mut users = users_lock.lock().await;
+                    users.delete_all_users();
+
+                    // Send the close window message
+                    Message::SessionClosed
+                });
+            }
+            Message::LeaveSession => {
+                if self.unsynced_edits > 0 {
+                    self.leave_confirm_open = true;
+                } else {
+                    return Task::done(Message::ConfirmLeaveSession);
+                }
+            }
+            Message::SaveCopyAndLeave => {
+                self.leave_confirm_open = false;
+                self.leave_after_save = true;
+                self.save_status = SaveStatus::Saving;
+                return self.track_file_dialog(
+                    Task::perform(save_file(None, self.content.text()), MenuMessage::FileSaved)
+                        .map(Message::Menu),
+                );
+            }
+            Message::CancelLeaveSession => {
+                self.leave_confirm_open = false;
+            }
+            Message::ConfirmLeaveSession => {
+                if let State::Connected(ref mut connection) = self.client_state {
+                    connection.close();
+                }
+                if let Some(controller) = &mut self.connection_controller {
+                    controller.stop();
+                }
+                self.session_generation += 1;
+                self.leave_confirm_open = false;
+                self.leave_after_save = false;
+                self.unsynced_edits = 0;
+                self.received_revision = 0;
+                self.applied_revision = 0;
+                self.joined_session = false;
+                self.reconnecting = false;
+                self.connection_controller = None;
+                self.access_fallback = None;
+                self.known_access_passwords.clear();
+                self.client_state = State::Disconnected;
+                self.user_cursors.clear();
+                self.participants.clear();
+                self.participant_names.clear();
+                self.host_participant = None;
+                self.session_locked = false;
+                self.session_limits = None;
+                self.id = None;
+            }
+            Message::SessionClosed => {
+                println!("Server closed");
+                self.started_session = false;
+                self.session_limits = None;
+                self.idle_lock_timeout = None;
+                self.id = None;
+                return self.set_session_lock(false);
+            }
+            Message::CloseWindow(id) => {
+                println!("Window with id {:?} closed", id);
+                if let Some(file_dialog) = self.file_dialog.take() {
+                    file_dialog.abort();
+                }
+                let save_last_file = if self.settings.reopen_last_file {
+                    self.settings.last_file = self.file.clone();
+                    self.settings.last_caret = self.caret_position();
+                    self.save_settings()
+                } else {
+                    Task::none()
+                };
+
+                // A clean exit leaves nothing to recover
+                return save_last_file.chain(
+                    Task::future(self.recovery.clone().remove())
+                        .then(move |_| window::close::<iced::window::Id>(id))
+                        .map(|_| Message::NoOp),
+                );
+            }
+            Message::WindowResized(size) => {
+                self.window_width = size.width;
+                self.window_height = size.height;
+            }
+            Message::WorkerReady(sender) => {
+                self.server_worker = Some(sender);
+                if self.modal_content.session_start_error == NOT_READY_ERROR {
+                    self.modal_content.session_start_error.clear();
+                }
+            }
+            Message::RecoveryFound(recovered_text) => {
+                self.recovered_text = recovered_text;
+            }
+            Message::RestoreRecovery => {
+                if let Some(recovered_text) = self.recovered_text.take() {
+                    self.set_content(text_editor::Content::with_text(&recovered_text));
+                    self.scroll_offset = 0;
+                    self.update_preview();
+                    self.unsaved_changes = true;
+
+                    // The restored text is kept in this editor's dump before the old one goes
+                    let dumped = self.content.text();
+                    self.recovery_hash = Some(content_hash(&dumped));
+                    let recovery = self.recovery.clone();
+                    let adopt = Task::future(async move {
+                        if let Err(error) = recovery.clone().write(dumped).await {
+                            println!("{}", error);
+                            return;
+                        }
+                        recovery.discard_found().await;
+                    });
+
+                    let document = self.document.clone();
+                    let content = self.buffer_text();
+                    let share = Task::future(async move {
+                        document.lock().await.replace(content);
+                    });
+                    return Task::batch([adopt, share]).map(|_| Message::NoOp);
+                }
+            }
+            Message::DiscardRecovery => {
+                self.recovered_text = None;
+                return Task::future(self.recovery.clone().discard_found()).map(|_| Message::NoOp);
+            }
+            Message::RetryConnection => {
+                if let Some(controller) = &mut self.connection_controller {
+                    controller.retry_now();
+                }
+            }
+            Message::DismissError => {
+                self.error_banner = None;
+            }
+            Message::RefreshPreview => {
+                if self.last_document_change.elapsed() >= PREVIEW_DEBOUNCE {
+                    self.update_preview();
+                }
+            }
+            Message::WriteRecovery => {
+                self.recovery_pending = false;
+                let text = self.content.text();
+                let hash = content_hash(&text);
+                if self.recovery_hash == Some(hash) {
+                    return Task::none();
+                }
+                self.recovery_hash = Some(hash);
+
+                return Task::perform(self.recovery.clone().write(text), |result| {
+                    if let Err(error) = result {
+                        println!("{}", error);
+                    }
+                    Message::NoOp
+                });
+            }
+        }
+        Task::none()
+    }
+
+    pub fn theme(&self) -> Theme {
+        if self.settings.high_contrast {
+            return high_contrast_theme();
+        }
+        self.theme.clone()
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        f64::from(self.settings.ui_scale) / 100.0
+    }
+
+    /// Reconnects the joined session with another access level. The document stays as it is
+    /// until the server sends its copy on connect.
+    fn switch_access(&mut self, access: SessionType, password: String) -> Task<Message> {
+        if !self.joined_session {
+            return Task::none();
+        }
+
+        if let Some(current) = self.modal_content.session_selection {
+            let current_password =
+                std::mem::replace(&mut self.modal_content.session_password_input, password);
+            self.access_fallback = Some((current, current_password));
+        }
+        self.modal_content.session_selection = Some(access);
+
+        // The old connection goes away with its subscription, so nothing may be sent on it
+        self.client_state = State::Disconnected;
+        self.connection_controller = None;
+        self.reconnecting = false;
+        Task::none()
+    }
+
+    /// Host and port to serve or join the session on, with what is wrong with them
+    fn server_address_inputs(&self) -> Element<'_, Message> {
+        column![
+            row![
+                text_input(
+                    &format!("Host ({})", client::DEFAULT_HOST),
+                    &self.modal_content.host_input
+                )
+                .on_input(Message::ServerHostChanged)
+                .padding(5),
+                text_input(
+                    &format!("Port ({})", client::DEFAULT_PORT),
+                    &self.modal_content.port_input
+                )
+                .on_input(Message::ServerPortChanged)
+                .width(100)
+                .padding(5),
+            ]
+            .spacing(5),
+            toggler(self.modal_content.secure)
+                .label("Secure session (TLS)")
+                .on_toggle(Message::SecureSessionToggled),
+            match self.modal_content.server_address() {
+                Ok(_) => text("").size(14),
+                Err(error) => text(error).size(14).color([1.0, 0.0, 0.0]),
+            },
+        ]
+        .spacing(5)
+        .into()
+    }
+
+    /// Certificate and key the host serves a secure session with, hidden otherwise
+    fn tls_file_inputs(&self) -> Element<'_, Message> {
+        if !self.modal_content.secure {
+            return column![].into();
+        }
+
+        column![
+            text_input(
+                "Certificate file (PEM)",
+                &self.modal_content.cert_path_input
+            )
+            .on_input(Message::TlsCertPathChanged)
+            .padding(5),
+            text_input("Private key file (PEM)", &self.modal_content.key_path_input)
+                .on_input(Message::TlsKeyPathChanged)
+                .padding(5),
+            match self.modal_content.tls_files() {
+                Ok(_) => text("").size(14),
+                Err(error) => text(error).size(14).color([1.0, 0.0, 0.0]),
+            },
+        ]
+        .spacing(5)
+        .into()
+    }
+
+    /// Largest document and fastest edit rate the host accepts from clients
+    fn session_limit_inputs(&self) -> Element<'_, Message> {
+        let limits = self.modal_content.limits;
+        row![
+            text("Max document (MB)"),
+            pick_list(
+                DOCUMENT_SIZE_OPTIONS,
+                Some(limits.max_document_bytes / (1024 * 1024)),
+                Message::MaxDocumentSizeSelected
+            )
+            .padding(5),
+            text("Edits per second"),
+            pick_list(
+                EDIT_RATE_OPTIONS,
+                Some(limits.edits_per_second),
+                Message::EditRateSelected
+            )
+            .padding(5),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    /// Name the others see by this user's cursor, asked for when starting or joining
+    fn display_name_input(&self) -> Element<'_, Message> {
+        text_input(
+            "Your name (shown by your cursor)",
+            &self.modal_content.display_name_input,
+        )
+        .on_input(Message::DisplayNameChanged)
+        .padding(5)
+        .into()
+    }
+
+    fn password_visibility_button(&self) -> Element<'_, Message> {
+        button(if self.modal_content.show_passwords {
+            "Hide"
+        } else {
+            "Show"
+        })
+        .on_press(Message::ShowPasswordsToggled)
+        .style(button::secondary)
+        .padding(5)
+        .into()
+    }
+
+    /// Lists `path` first among the recent files, saving the list in the background
+    fn add_recent(&mut self, path: PathBuf) -> Task<Message> {
+        Task::perform(self.menubar.add_recent(path), |result| {
+            if let Err(error) = result {
+                println!("{}", error);
+            }
+            Message::NoOp
+        })
+    }
+
+    fn save_settings(&self) -> Task<Message> {
+        Task::perform(
+            self.settings.clone().save(self.config_dir.clone()),
+            |result| {
+                if let Err(error) = result {
+                    println!("{}", error);
+                }
+                Message::NoOp
+            },
+        )
+    }
+
+    fn set_session_lock(&mut self, locked: bool) -> Task<Message> {
+        self.session_locked = locked;
+
+        let is_locked_lock = self.is_locked.clone();
+        Task::future(async move {
+            *is_locked_lock.lock().await = locked;
+            Message::NoOp
+        })
+    }
+
+    /// Summary of the joined session: host, access level and participant count
+    fn session_info(&self) -> String {
+        if self.started_session && self.session_locked {
+            return String::from("Session locked while idle");
+        }
+
+        if self.started_session {
+            // The server only checks passwords it was given, so a missing one leaves access open
+            let access = |password: &Option<String>| match password {
+                Some(_) => "password",
+                None => "open",
+            };
+            return format!(
+                "Hosting | Read access: {} | Write access: {}",
+                access(&self.read_password),
+                access(&self.edit_password)
+            );
+        }
+
+        if !self.joined_session {
+            return String::new();
+        }
+
+        if self
+            .throttled_until
+            .is_some_and(|until| Instant::now() < until)
+        {
+            return String::from("You're editing faster than the server accepts");
+        }
+
+        let State::Connected(_) = self.client_state else {
+            return String::from("Connecting...");
+        };
+
+        let access = match self.modal_content.session_selection {
+            Some(SessionType::Edit) => "Write access",
+            Some(SessionType::Comment) => "Comment access",
+            _ => "Read access",
+        };
+
+        format!(
+            "Host: {} | Room: {} | {}{} | Participants: {}",
+            self.modal_content.server_address().unwrap_or_default(),
+            match self.modal_content.room_input.trim() {
+                "" => DEFAULT_ROOM,
+                room => room,
+            },
+            access,
+            if self.session_locked {
+                " (locked by host)"
+            } else {
+                ""
+            },
+            self.participant_list()
+        )
+    }
+
+    /// Participant ids for the status line, marking the host and this client. Readers get an
+    /// eye and editors a pencil, since only an editor's cursor moves with their own typing.
+    fn participant_list(&self) -> String {
+        self.participants
+            .iter()
+            .map(|&(id, access)| {
+                let icon = match access {
+                    SessionType::Read => "👁",
+                    SessionType::Comment => "💬",
+                    SessionType::Edit => "✏",
+                };
+                if Some(id) == self.host_participant {
+                    format!("{icon} {id} (host)")
+                } else if Some(id) == self.id {
+                    format!("{icon} {id} (you)")
+                } else {
+                    format!("{icon} {id}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn prose_substitution(&self, text_before: &str, ch: char) -> char {
+        if (!self.settings.auto_capitalize && !self.settings.smart_quotes) || in_code(text_before) {
+            return ch;
+        }
+
+        match ch {
+            '"' if self.settings.smart_quotes => {
+                if opens_quote(text_before) {
+                    '\u{201C}'
+                } else {
+                    '\u{201D}'
+                }
+            }
+            '\'' if self.settings.smart_quotes => {
+                if opens_quote(text_before) {
+                    '\u{2018}'
+                } else {
+                    '\u{2019}'
+                }
+            }
+            ch if self.settings.auto_capitalize
+                && ch.is_lowercase()
+                && is_sentence_start(text_before) =>
+            {
+                ch.to_uppercase().next().unwrap_or(ch)
+            }
+            ch => ch,
+        }
+    }
+
+    /// Flags a cursor color that blends into the background of the current theme
+    fn cursor_contrast_warning(&self) -> Element<'_, Message> {
+        let (r, g, b) = self.cursor_marker.color;
+        let background = self.theme().palette().background;
+        if contrast_ratio(Color::from_rgb(r, g, b), background) >= MIN_CURSOR_CONTRAST {
+            return row![].into();
+        }
+
+        text("Hard to see on this theme")
+            .size(14)
+            .color(self.theme().palette().danger)
+            .into()
+    }
+
+    /// Tells the others in the session where the caret is, and in what color
+    fn broadcast_cursor(&mut self) -> Task<Message> {
+        let position = self.cursor_position_in_pixels();
+        self.cursor_marker.move_cursor(position, self.line_height);
+        self.cursor_marker.viewport = self.shared_viewport();
+        let cursor_marker = self.cursor_marker;
+
+        // Check if the user is connected to a session
+        if let State::Connected(ref mut connection) = self.client_state {
+            if self.joined_session {
+                let message = ProtocolMessage::Cursor(cursor_marker).to_ws_text();
+
+                // Send the message
+                if let Err(error) = connection.send(client::Message::User(message)) {
+                    println!("Failed to send cursor: {}", error);
+                }
+            } else {
+                println!("Cannot send message; not joined in a session.");
+            }
+            Task::none()
+        } else {
+            // If not, user is the host so update cursor position in the users map and user cursors list
+            let users_lock = self.users.clone();
+            let is_moved_lock = self.is_moved.clone();
+            Task::future(async move {
+                let mut users = users_lock.lock().await;
+                users.add_user(HOST_ADDR, Some(cursor_marker));
+                *is_moved_lock.lock().await = true;
+
+                let cursors = users.get_all_cursors();
+                Message::UpdateHostCursors(cursors)
+            })
+        }
+    }
+
+    /// Lines on screen, when the user shares them. The window height stands in for the
+    /// editor's, which iced does not report, so the last line is an overestimate.
+    fn shared_viewport(&self) -> Option<(usize, usize)> {
+        if !self.settings.share_viewport {
+            return None;
+        }
+
+        let visible_lines = (self.window_height / self.line_height).ceil() as usize;
+        let last_line = self.content.line_count().saturating_sub(1);
+        Some((
+            self.scroll_offset,
+            (self.scroll_offset + visible_lines).min(last_line),
+        ))
+    }
+
+    /// Characters of the file name the status row has room for, about a quarter of the window
+    /// How far a collaborator's document trails the latest copy the host sent, empty when
+    /// not in someone else's session
+    fn sync_status(&self) -> String {
+        if !self.joined_session || self.started_session {
+            return String::new();
+        }
+
+        match self.received_revision.saturating_sub(self.applied_revision) {
+            0 => String::from("Synced"),
+            1 => String::from("1 edit behind"),
+            behind => format!("{} edits behind", behind),
+        }
+    }
+
+    fn status_name_width(&self) -> usize {
+        ((self.window_width / 4.0 / APPROX_CHAR_WIDTH) as usize).max(STATUS_NAME_MIN_WIDTH)
+    }
+
+    /// Where the caret is from the top left of the text, with columns estimated from an
+    /// average glyph width
+    fn cursor_position_in_pixels(&self) -> (f32, f32) {
+        let (line, col) = self.caret_position();
+        let column = self
+            .content
+            .line(line)
+            .map_or(0, |text| visual_column(&text, col));
+
+        (
+            column as f32 * APPROX_CHAR_WIDTH,
+            line as f32 * self.line_height,
+        )
+    }
+
+    /// Notes in the open folder, grouped under the subfolders they are in
+    fn workspace_sidebar(&self) -> Element<'_, Message> {
+        let Some(root) = &self.workspace_root else {
+            return column![].into();
+        };
+
+        let root_name = root.file_name().map_or_else(
+            || root.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let mut entries = column![text(root_name).size(16)].spacing(2);
+        let mut current_folder = Path::new("");
+        for path in &self.workspace_files {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            let folder = relative.parent().unwrap_or(Path::new(""));
+            let depth = folder.components().count() as f32;
+            if folder != current_folder && !folder.as_os_str().is_empty() {
+                entries = entries.push(
+                    container(text(folder.display().to_string()).size(14))
+                        .padding(iced::Padding::ZERO.left(10.0)),
+                );
+            }
+            current_folder = folder;
+
+            let name = relative
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+            let style = if self.file.as_ref() == Some(path) {
+                button::primary
+            } else {
+                button::text
+            };
+            entries = entries.push(
+                container(
+                    button(text(name).size(14))
+                        .on_press(Message::WorkspaceFileSelected(path.clone()))
+                        .style(style)
+                        .padding(2),
+                )
+                .padding(iced::Padding::ZERO.left(10.0 + depth * 10.0)),
+            );
+        }
+
+        container(scrollable(entries).height(Length::Fill))
+            .width(Length::Fixed(200.0))
+            .height(Length::FillPortion(1))
+            .into()
+    }
+
+    /// Who is in the session, each by a dot in their cursor's color, while the list is open
+    fn participants_sidebar(&self) -> Element<'_, Message> {
+        if !self.in_session() || !self.participants_open {
+            return column![].into();
+        }
+
+        let limits = match self.session_limits {
+            Some(limits) => text(limits.to_string()).size(12),
+            None => text(""),
+        };
+        let entries = self.participants.iter().fold(
+            column![text("Participants").size(16), limits].spacing(5),
+            |entries, &(id, access)| {
+                let (r, g, b) = if Some(id) == self.id {
+                    self.cursor_marker.color
+                } else {
+                    self.user_cursors
+                        .iter()
+                        .find(|(user, _)| *user == id)
+                        .map_or((0.5, 0.5, 0.5), |(_, marker)| marker.color)
+                };
+                let name = self
+                    .participant_names
+                    .get(&id)
+                    .cloned()
+                    .unwrap_or_else(|| format!("User {id}"));
+                let role = if Some(id) == self.host_participant {
+                    " (host)"
+                } else if Some(id) == self.id {
+                    " (you)"
+                } else {
+                    ""
+                };
+                entries.push(
+                    row![
+                        text("\u{25CF}").color(Color::from_rgb(r, g, b)),
+                        text(format!("{name}{role}")).size(14),
+                        horizontal_space(),
+                        text(access.to_string()).size(12),
+                    ]
+                    .align_y(Alignment::Center)
+                    .spacing(5),
+                )
+            },
+        );
+
+        container(scrollable(entries).height(Length::Fill))
+            .width(Length::Fixed(200.0))
+            .height(Length::FillPortion(1))
+            .into()
+    }
+
+    /// Lines numbered in the gutter: only those in view, plus one partly scrolled in
+    fn gutter_lines(&self) -> std::ops::Range<usize> {
+        let line_count = self.content.line_count().max(1);
+        let visible_lines = (self.window_height / self.line_height).ceil() as usize;
+        let first = self.scroll_offset.min(line_count);
+        first..(first + visible_lines + 1).min(line_count)
+    }
+
+    /// Column of logical line numbers that follows the editor's scroll offset
+    fn line_number_gutter(&self) -> Element<'_, Message> {
+        if !self.settings.show_line_numbers {
+            return column![].into();
+        }
+
+        let numbers = self.gutter_lines().map(|line| {
+            text(format!("{}", line + 1))
+                .line_height(text::LineHeight::Absolute(Pixels(self.line_height)))
+                .color(if self.settings.high_contrast {
+                    Color::WHITE
+                } else {
+                    Color::from_rgb(0.5, 0.5, 0.5)
+                })
+                .into()
+        });
+
+        // Match the text editor's padding so the numbers line up with its rows
+        container(column(numbers).align_x(Alignment::End))
+            .padding([self.settings.editor_padding, 0])
+            .width(Length::Shrink)
+            .height(Length::FillPortion(1))
+            .clip(true)
+            .into()
+    }
+
+    fn replace_content(&mut self, doc: &Document) {
+        if self.id == Some(doc.last_edit) {
+            return;
+        }
+        self.last_document_change = Instant::now();
+
+        let text = doc.text();
+        if let Ok(mut history) = self.undo.lock() {
+            history.rebase(&self.buffer_text(), &text);
+        }
+
+        let (line, col) = self.caret_position();
+        self.set_content(text_editor::Content::with_text(&text));
+        self.move_caret_to(line, col);
+        self.preview_pending = true;
+    }
+
+    /// Applies an edit that reverses one from `replay`'s history, so that it is recorded in the
+    /// other one
+    fn undo_edit(&mut self, replay: Replay, edit: text_editor::Edit) -> Task<Message> {
+        if let Ok(mut history) = self.undo.lock() {
+            history.replaying(replay);
+        }
+        Task::done(Message::Action(text_editor::Action::Edit(edit)))
+    }
+
+    /// Takes in who is in the session, with their cursors, names and access
+    fn set_participants(&mut self, participants: Participants) {
+        self.set_user_cursors(
+            participants
+                .users
+                .iter()
+                .filter_map(|user| Some((user.id, user.cursor?)))
+                .collect(),
+        );
+        self.participants = participants
+            .users
+            .iter()
+            .map(|user| (user.id, user.access))
+            .collect();
+        self.participant_names = participants
+            .users
+            .iter()
+            .filter_map(|user| Some((user.id, user.name.clone()?)))
+            .collect();
+        self.host_participant = participants.host;
+    }
+
+    /// Replaces the collaborators' cursors, noting which of them moved. This user's own cursor
+    /// is left out, since the caret already shows it.
+    fn set_user_cursors(&mut self, mut cursors: Vec<(UserId, CursorMarker)>) {
+        cursors.retain(|(id, _)| Some(*id) != self.id);
+        let now = Instant::now();
+        for (id, marker) in &cursors {
+            let moved = !self
+                .user_cursors
+                .iter()
+                .any(|(known, old)| known == id && old.y == marker.y && old.x == marker.x);
+            if moved {
+                self.cursor_moved.insert(*id, now);
+            }
+        }
+        self.cursor_moved
+            .retain(|id, _| cursors.iter().any(|(present, _)| present == id));
+        self.user_cursors = cursors;
+    }
+
+    fn cursor_label_opacity(&self, id: UserId) -> f32 {
+        let delay = Duration::from_secs(self.settings.cursor_label_fade);
+        match self.cursor_moved.get(&id) {
+            Some(moved) => label_opacity(moved.elapsed(), delay),
+            None => label_opacity(Duration::MAX, delay),
+        }
+    }
+
+    /// Whether this editor is hosting a session or has joined one
+    fn in_session(&self) -> bool {
+        self.started_session || self.joined_session
+    }
+
+    /// What starting a session will expose, for the confirmation before it starts
+    fn session_share_summary(&self) -> String {
+        let source = match self.modal_content.file_path_input.trim() {
+            "" => String::from("the current document"),
+            path => format!("the file {}", DisplayPath(Path::new(path))),
+        };
+        let protection = |input: &str, saved: &Option<String>| {
+            if input.is_empty() && saved.is_none() {
+                "anyone who can reach this machine"
+            } else {
+                "password protected"
+            }
+        };
+
+        format!(
+            "You're about to share {} at {}.\nRead access: {}\nWrite access: {}",
+            source,
+            self.modal_content.server_address().unwrap_or_default(),
+            protection(&self.modal_content.read_password_input, &self.read_password),
+            protection(
+                &self.modal_content.write_password_input,
+                &self.edit_password
+            ),
+        )
+    }
+
+    /// Makes a task that shows a file dialog abortable, keeping its handle for when the window
+    /// closes. Aborting a dialog that already finished does nothing.
+    fn track_file_dialog(&mut self, task: Task<Message>) -> Task<Message> {
+        let (task, handle) = task.abortable();
+        self.file_dialog = Some(handle);
+        task
+    }
+
+    /// Switches between markdown and plain text, closing the preview for plain text
+    fn set_plain_text(&mut self, plain_text: bool) {
+        self.plain_text = plain_text;
+        if plain_text {
+            self.markdown_preview_open = false;
+            self.preview_focused = false;
+        }
+    }
+
+    /// Swaps in new editor content, forgetting the line offsets measured in the old one
+    fn set_content(&mut self, content: text_editor::Content) {
+        self.content = content;
+        self.line_offsets = vec![0];
+        self.refresh_find();
+    }
+
+    /// Whether edits are kept out of the editor, in a session joined without edit access or
+    /// locked by the host
+    fn read_only(&self) -> bool {
+        matches!(self.client_state, State::Connected(_))
+            && self
+                .modal_content
+                .session_selection
+                .is_some_and(|selection| selection != SessionType::Edit || self.session_locked)
+    }
+
+    /// Looks for the find panel's query again, after it or the text changed
+    fn refresh_find(&mut self) {
+        self.find_current = None;
+        if !self.find_open || self.find_query.is_empty() {
+            self.find_matches.clear();
+            return;
+        }
+
+        self.find_matches = self
+            .content
+            .lines()
+            .enumerate()
+            .flat_map(|(line, text)| {
+                find_in_line(
+                    &text,
+                    &self.find_query,
+                    self.find_ignore_case,
+                    self.find_whole_word,
+                )
+                .into_iter()
+                .map(move |columns| FindMatch { line, columns })
+            })
+            .collect();
+    }
+
+    /// Moves the caret to a match and selects it
+    fn select_match(&mut self, found: &FindMatch) {
+        self.move_caret_to(found.line, found.columns.start);
+        for _ in found.columns.clone() {
+            self.content
+                .perform(text_editor::Action::Select(text_editor::Motion::Right));
+        }
+    }
+
+    fn find_panel(&self) -> Element<'_, Message> {
+        let count = match (self.find_current, self.find_matches.len()) {
+            (_, 0) if !self.find_query.is_empty() => String::from("No matches"),
+            (_, 0) => String::new(),
+            (Some(current), total) => format!("{} of {}", current + 1, total),
+            (None, total) => format!("{} matches", total),
+        };
+
+        let mut panel = column![
+            row![
+                text_input("Find", &self.find_query)
+                    .id(find_input_id())
+                    .on_input(Message::FindChanged)
+                    .on_submit(Message::FindNext)
+                    .width(200)
+                    .padding(5),
+                button("Next")
+                    .on_press(Message::FindNext)
+                    .style(button::secondary),
+                button("Close")
+                    .on_press(Message::CloseFind)
+                    .style(button::secondary),
+            ]
+            .align_y(Alignment::Center)
+            .spacing(5),
+            row![
+                toggler(self.find_ignore_case)
+                    .label("Ignore case")
+                    .on_toggle(Message::FindIgnoreCaseToggled),
+                toggler(self.find_whole_word)
+                    .label("Whole word")
+                    .on_toggle(Message::FindWholeWordToggled),
+                text(count).size(14),
+            ]
+            .align_y(Alignment::Center)
+            .spacing(10),
+        ]
+        .spacing(5);
+
+        if self.replace_open {
+            let mut replace_all = button("Replace all").style(button::secondary);
+            if !self.find_matches.is_empty() && !self.read_only() {
+                replace_all = replace_all.on_press(Message::ReplaceAll);
+            }
+            panel = panel.push(
+                row![
+                    text_input("Replace with", &self.replace_input)
+                        .on_input(Message::ReplaceChanged)
+                        .on_submit(Message::ReplaceAll)
+                        .width(200)
+                        .padding(5),
+                    replace_all,
+                ]
+                .align_y(Alignment::Center)
+                .spacing(5),
+            );
+        }
+
+        container(panel)
+            .padding(10)
+            .style(container::rounded_box)
+            .into()
+    }
+
+    /// Bands behind the matches of the find panel's query on the visible lines
+    fn find_highlights(&self) -> Option<Element<'_, Message>> {
+        if self.find_matches.is_empty() {
+            return None;
+        }
+
+        let visible_lines = (self.window_height / self.line_height).ceil() as usize;
+        let visible = self.scroll_offset..self.scroll_offset + visible_lines;
+        let padding = f32::from(self.settings.editor_padding);
+        let bands = self
+            .find_matches
+            .iter()
+            .enumerate()
+            .filter(|(_, found)| visible.contains(&found.line))
+            .filter_map(|(index, found)| {
+                let line = self.content.line(found.line)?;
+                let start = visual_column(&line, found.columns.start);
+                let end = visual_column(&line, found.columns.end);
+                let band = Rectangle::new(
+                    Point::new(
+                        padding + start as f32 * APPROX_CHAR_WIDTH,
+                        padding + (found.line - self.scroll_offset) as f32 * self.line_height,
+                    ),
+                    Size::new((end - start) as f32 * APPROX_CHAR_WIDTH, self.line_height),
+                );
+                Some((band, Some(index) == self.find_current))
+            })
+            .collect();
+
+        Some(
+            Canvas::<FindHighlights, Message>::new(FindHighlights { bands })
+                .width(Length::FillPortion(1))
+                .height(Length::FillPortion(1))
+                .into(),
+        )
+    }
+
+    /// Byte offset where `line` starts, measuring the lines since the last known start
+    fn line_offset(&mut self, line: usize) -> usize {
+        while self.line_offsets.len() <= line {
+            let last = self.line_offsets.len() - 1;
+            let Some(text) = self.content.line(last) else {
+                break;
+            };
+            let next = self.line_offsets[last] + text.len() + 1;
+            self.line_offsets.push(next);
+        }
+
+        self.line_offsets
+            .get(line)
+            .copied()
+            .unwrap_or_else(|| self.line_offsets[self.line_offsets.len() - 1])
+    }
+
+    /// Sessions share the active document, so it stays put while one is running
+    fn tabs_locked(&mut self) -> bool {
+        let locked = self.started_session || self.joined_session;
+        if locked {
+            self.error_banner =
+                Some("Leave the session before switching to another document.".to_string());
+        }
+        locked
+    }
+
+    /// Opens an empty document in a new tab
+    fn new_tab(&mut self) -> Task<Message> {
+        if self.tabs_locked() {
+            return Task::none();
+        }
+        let parked = self.park_document();
+        self.tabs[self.current_tab] = Some(parked);
+        self.tabs.push(None);
+        self.current_tab = self.tabs.len() - 1;
+        self.restore_document(DocumentTab::new(&self.settings))
+    }
+
+    fn select_tab(&mut self, index: usize) -> Task<Message> {
+        if index == self.current_tab || index >= self.tabs.len() || self.tabs_locked() {
+            return Task::none();
+        }
+        let Some(tab) = self.tabs[index].take() else {
+            return Task::none();
+        };
+        self.tabs[self.current_tab] = Some(self.park_document());
+        self.current_tab = index;
+        self.restore_document(tab)
+    }
+
+    /// Drops a tab's document, moving to a neighbouring tab if it was the active one. Closing
+    /// the last tab leaves an empty one.
+    fn close_tab(&mut self, index: usize) -> Task<Message> {
+        if index >= self.tabs.len() {
+            return Task::none();
+        }
+        if index != self.current_tab {
+            self.tabs.remove(index);
+            if index < self.current_tab {
+                self.current_tab -= 1;
+            }
+            return Task::none();
+        }
+        if self.tabs_locked() {
+            return Task::none();
+        }
+
+        self.tabs.remove(index);
+        if self.tabs.is_empty() {
+            self.tabs.push(None);
+            self.current_tab = 0;
+            return self.restore_document(DocumentTab::new(&self.settings));
+        }
+        self.current_tab = index.min(self.tabs.len() - 1);
+        let tab = self.tabs[self.current_tab]
+            .take()
+            .unwrap_or_else(|| DocumentTab::new(&self.settings));
+        self.restore_document(tab)
+    }
+
+    /// Takes the document being edited out of the editor, to keep in its tab
+    fn park_document(&mut self) -> DocumentTab {
+        DocumentTab {
+            content: std::mem::replace(&mut self.content, text_editor::Content::new()),
+            file: self.file.take(),
+            plain_text: self.plain_text,
+            unsaved_changes: self.unsaved_changes,
+            saved_hash: self.saved_hash,
+            undo: self.undo.clone(),
+            scroll_offset: self.scroll_offset,
+            opened_word_count: self.opened_word_count,
+        }
+    }
+
+    /// Makes a tab's document the one being edited
+    fn restore_document(&mut self, tab: DocumentTab) -> Task<Message> {
+        // Settings may have changed while the tab was in the background
+        if let Ok(mut history) = tab.undo.lock() {
+            history.set_depth(self.settings.undo_depth);
+            history.set_memory_budget(self.settings.undo_memory_mb * undo::MEGABYTE);
+            history.set_spill(self.settings.undo_spill);
+        }
+        self.undo = tab.undo;
+        self.set_plain_text(tab.plain_text);
+        self.file = tab.file;
+        self.set_content(tab.content);
+        self.unsaved_changes = tab.unsaved_changes;
+        self.saved_hash = tab.saved_hash;
+        self.scroll_offset = tab.scroll_offset;
+        self.opened_word_count = tab.opened_word_count;
+        self.long_line_prompt = None;
+        self.reopen_pending = None;
+        self.workspace_open_pending = None;
+        self.preview_link = None;
+        self.update_preview();
+
+        let document = self.document.clone();
+        let content = self.buffer_text();
+        Task::future(async move {
+            document.lock().await.replace(content);
+            Message::NoOp
+        })
+    }
+
+    /// Strip of the open documents, holding the editor under the active one
+    fn document_tabs<'a>(&self, editor: Element<'a, Message>) -> Element<'a, Message> {
+        let mut editor = Some(editor);
+        let mut tabs = Tabs::new(Message::DocumentTabSelected)
+            .on_close(Message::CloseTab)
+            .tab_label_padding(5)
+            .height(Length::Fill);
+        for (index, tab) in self.tabs.iter().enumerate() {
+            let (label, content) = match tab {
+                Some(tab) => (
+                    DocumentTab::label(tab.file.as_ref(), tab.unsaved_changes),
+                    Element::from(column![]),
+                ),
+                None => (
+                    DocumentTab::label(self.file.as_ref(), self.unsaved_changes),
+                    editor.take().unwrap_or_else(|| column![].into()),
+                ),
+            };
+            tabs = tabs.push(index, TabLabel::Text(label), content);
+        }
+        tabs.set_active_tab(&self.current_tab).into()
+    }
+
+    /// Replaces the buffer with a file read from disk
+    fn load_file(&mut self, path: PathBuf, contents: &str) -> Task<Message> {
+        self.set_plain_text(!is_markdown_file(&path));
+        self.file = Some(path.clone());
+        self.set_content(text_editor::Content::with_text(contents));
+        self.scroll_offset = 0;
+        self.unsaved_changes = false;
+        self.saved_hash = Some(content_hash(&self.content.text()));
+        self.update_preview();
+        self.opened_word_count = self.text_statistics().0;
+        self.long_line_prompt = contents
+            .lines()
+            .map(str::len)
+            .max()
+            .filter(|&longest| longest > LONG_LINE_WARNING);
+        println!("File loaded: {}", DisplayPath(&path));
+
+        let document = self.document.clone();
+        let content = self.buffer_text();
+        Task::future(async move {
+            let mut doc_lock = document.lock().await;
+            doc_lock.replace(content);
+            Message::NoOp
+        })
+    }
+
+    /// Line and character column of the caret
+    fn caret_position(&self) -> (usize, usize) {
+        let (line, col) = self.content.cursor_position();
+        // The cursor column is a byte index, but the editor moves right one character at a time
+        let col = self.content.line(line).map_or(col, |text| {
+            text.get(..col).map_or(col, |text| text.chars().count())
+        });
+        (line, col)
+    }
+
+    fn move_caret_to(&mut self, line: usize, col: usize) {
+        // The position may be past the end of the document, e.g. after a remote delete
+        let line = line.min(self.content.line_count().saturating_sub(1));
+        let col = col.min(
+            self.content
+                .line(line)
+                .map_or(0, |text| text.chars().count()),
+        );
+
+        // Start at the beginning
+        self.content.perform(text_editor::Action::Move(
+            text_editor::Motion::DocumentStart,
+        ));
+        // Move to the right row
+        (0..line).for_each(|_| {
+            self.content
+                .perform(text_editor::Action::Move(text_editor::Motion::Down));
+        });
+
+        // Scroll to the right col
+        (0..col).for_each(|_| {
+            self.content
+                .perform(text_editor::Action::Move(text_editor::Motion::Right));
+        });
+    }
+
+    /// The content as the shared document stores it: its lines joined by newlines, without the
+    /// trailing newline `Content::text` always appends
+    fn buffer_text(&self) -> String {
+        self.content
+            .lines()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Text shown in place of a new document while it is still empty. An opened file that
+    /// happens to be empty is not a new document.
+    fn placeholder(&self) -> Option<&'static str> {
+        let is_empty = self.content.line_count() == 1
+            && self.content.line(0).is_some_and(|line| line.is_empty());
+        (is_empty && self.file.is_none()).then_some(EDITOR_PLACEHOLDER)
+    }
+
+    /// Word and line counts of the current content. Words are counted along with the preview, so
+    /// they follow remote updates as well as local edits once those settle.
+    fn text_statistics(&self) -> (usize, usize) {
+        (self.word_count, self.content.line_count())
+    }
+
+    /// Progress towards the word goal, with the words written since the document was opened
+    fn word_goal_progress(&self) -> Element<'_, Message> {
+        let goal = self.settings.word_goal;
+        if goal == 0 {
+            return row![].into();
+        }
+
+        let (words, _) = self.text_statistics();
+        let progress = goal_progress(words, goal);
+        row![
+            text(format!(
+                "{} / {} words (+{} since open)",
+                words,
+                goal,
+                words.saturating_sub(self.opened_word_count)
+            )),
+            progress_bar(0.0..=1.0, progress)
+                .width(100)
+                .height(10)
+                .style(if progress >= 1.0 {
+                    progress_bar::success
+                } else {
+                    progress_bar::primary
+                }),
+        ]
+        .align_y(Alignment::Center)
+        .spacing(5)
+        .into()
+    }
+
+    /// Re-reads the front-matter and re-renders the preview from the body below it. The preview
+    /// is only parsed while it is open, so large documents cost nothing with it closed.
+    fn update_preview(&mut self) {
+        self.preview_pending = false;
+        let text = self.content.text();
+        self.word_count = self.settings.word_count_mode.count(&text);
+        let (metadata, body) = frontmatter::parse(&text);
+        self.metadata = metadata;
+
+        if !self.markdown_preview_open || body.trim().is_empty() {
+            self.markdown_text = Vec::new();
+        } else {
+            self.markdown_text = markdown::parse(&math::preserve(body)).collect();
+        }
+    }
+
+    fn toggle_formatting(&mut self, format: TextStyle) -> Task<Message> {
+        match format {
+            TextStyle::Bold => self.wrap_selection("**", "**"),
+            TextStyle::Italic => {
+                // Bold text is also wrapped in `*`, so italicise it rather than stripping a star
+                if let Some(selection) = self.content.selection() {
+                    if selection.starts_with("**")
+                        && selection.ends_with("**")
+                        && !(selection.starts_with("***") && selection.ends_with("***"))
+                    {
+                        return self.replace_selection(Some(format!("*{}*", selection)));
+                    }
+                }
+                self.wrap_selection("*", "*")
+            }
+            TextStyle::Strikethrough => self.wrap_selection("~~", "~~"),
+            TextStyle::Highlight => self.wrap_selection("==", "=="),
+            TextStyle::Math => self.wrap_selection("$", "$"),
+            TextStyle::MathBlock => self.wrap_selection("$$\n", "\n$$"),
+            _ => Task::done(Message::NoOp),
+        }
+    }
+
+    /// Wraps the current selection in `open` and `close`, or unwraps it if it is already wrapped
+    fn wrap_selection(&mut self, open: &str, close: &str) -> Task<Message> {
+        let formatted_text = self
+            .content
+            .selection()
+            .map(|selection| toggle_wrap(&selection, open, close));
+
+        self.replace_selection(formatted_text)
+    }
+
+    /// Inserts a block on a line of its own at the caret, leaving the caret on the line after it
+    fn insert_block(&self, block: &str) -> Task<Message> {
+        let (line, col) = self.content.cursor_position();
+        let previous_line = line
+            .checked_sub(1)
+            .and_then(|previous| self.content.line(previous).map(|text| text.to_string()));
+        let current_line = self
+            .content
+            .line(line)
+            .map_or_else(String::new, |text| text.to_string());
+        let insertion = block_insertion(
+            previous_line.as_deref(),
+            current_line.get(..col).unwrap_or(&current_line),
+            block,
+        );
+
+        Task::done(Message::Action(text_editor::Action::Edit(
+            text_editor::Edit::Paste(insertion.into()),
+        )))
+    }
+
+    /// Line comment markers for the code block around the caret, or else for the open file
+    fn comment_markers(&self) -> (&'static str, &'static str) {
+        let (caret_line, _) = self.content.cursor_position();
+        let mut fence_language = None;
+        for line in self.content.lines().take(caret_line) {
+            if let Some(info) = line.trim_start().strip_prefix("```") {
+                fence_language = match fence_language {
+                    Some(_) => None,
+                    None => Some(info.trim().to_string()),
+                };
+            }
+        }
+
+        let language = fence_language.or_else(|| {
+            self.file
+                .as_ref()
+                .and_then(|path| path.extension())
+                .map(|extension| extension.to_string_lossy().into_owned())
+        });
+        comment_markers(language.as_deref().unwrap_or("md"))
+    }
+
+    /// Pastes rewritten lines over the selection they came from, leaving the caret after them
+    fn replace_lines(&self, lines: Option<String>) -> Task<Message> {
+        match lines {
+            Some(lines) => Task::done(Message::Action(text_editor::Action::Edit(
+                text_editor::Edit::Paste(lines.into()),
+            ))),
+            None => Task::none(),
+        }
+    }
+
+    fn replace_selection(&mut self, formatted_text: Option<String>) -> Task<Message> {
+        let mut tasks = Vec::new();
+        if let Some(formatted_text) = formatted_text {
+            tasks.push(Task::done(Message::Action(text_editor::Action::Edit(
+                text_editor::Edit::Paste(formatted_text.into()),
+            ))));
+        }
+
+        tasks.push(Task::done(Message::Action(text_editor::Action::Move(
+            text_editor::Motion::WordLeft,
+        )))); // Move cursor to the right of the inserted text
+        Task::batch(tasks)
+    }
+}
+
+/// An empty undo history, limited as the settings ask
+fn undo_history(settings: &Settings) -> UndoHistory {
+    let mut history = UndoHistory::new(settings.undo_depth);
+    history.set_memory_budget(settings.undo_memory_mb * undo::MEGABYTE);
+    history.set_spill(settings.undo_spill);
+    history
+}
+
+/// Whether a file is markdown by its extension. Files without one are notes, so count as
+/// markdown too.
+fn is_markdown_file(path: &Path) -> bool {
+    path.extension().is_none_or(|extension| {
+        ["md", "markdown", "mdown", "mkd"]
+            .iter()
+            .any(|markdown| extension.eq_ignore_ascii_case(markdown))
+    })
+}
+
+fn preview_scrollable_id() -> scrollable::Id {
+    scrollable::Id::new("markdown-preview")
+}
+
+fn find_input_id() -> text_input::Id {
+    text_input::Id::new("find-query")
+}
+
+/// Keys understood while the preview has focus; everything else is swallowed so it does not
+/// edit the document
+fn preview_key_binding(key_press: text_editor::KeyPress) -> Option<text_editor::Binding<Message>> {
+    use keyboard::key::Named;
+
+    let message = match key_press.key.as_ref() {
+        keyboard::Key::Character(PREVIEW_FOCUS_HOTKEY) if key_press.modifiers.command() => {
+            Message::TogglePreviewFocus
+        }
+        keyboard::Key::Named(Named::Escape) => Message::TogglePreviewFocus,
+        keyboard::Key::Named(Named::ArrowDown) => Message::PreviewScroll(PREVIEW_SCROLL_STEP),
+        keyboard::Key::Named(Named::ArrowUp) => Message::PreviewScroll(-PREVIEW_SCROLL_STEP),
+        keyboard::Key::Named(Named::PageDown) => Message::PreviewScroll(PREVIEW_SCROLL_STEP * 10.0),
+        keyboard::Key::Named(Named::PageUp) => Message::PreviewScroll(-PREVIEW_SCROLL_STEP * 10.0),
+        keyboard::Key::Named(Named::Tab) => Message::PreviewNextLink,
+        keyboard::Key::Named(Named::Enter) => Message::PreviewOpenLink,
+        _ => return None,
+    };
+
+    Some(text_editor::Binding::Custom(message))
+}
+
+/// Link targets in the order they appear, from inline `[text](url)` links and `<url>` autolinks
+fn markdown_links(text: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find(['(', '<']) {
+        let (open, close) = if rest[start..].starts_with('(') {
+            ('(', ')')
+        } else {
+            ('<', '>')
+        };
+        let is_link = match open {
+            '(' => rest[..start].ends_with(']'),
+            _ => rest[start + 1..].starts_with("http"),
+        };
+
+        rest = &rest[start + 1..];
+        if !is_link {
+            continue;
+        }
+        if let Some(end) = rest.find(close) {
+            let target = rest[..end].split_whitespace().next().unwrap_or("");
+            if !target.is_empty() {
+                links.push(target.to_string());
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+
+    links
+}
+
+/// Text that puts a block on a line of its own when pasted after `before_caret`. A blank line
+/// is kept above it, since `---` right under a paragraph would make that paragraph a heading.
+fn block_insertion(previous_line: Option<&str>, before_caret: &str, block: &str) -> String {
+    let separator = if !before_caret.is_empty() {
+        "\n\n"
+    } else if previous_line.is_some_and(|line| !line.trim().is_empty()) {
+        "\n"
+    } else {
+        ""
+    };
+    format!("{separator}{block}\n")
+}
+
+/// Opacity of a cursor name some time after the cursor moved: shown in full for `delay`, then
+/// faded out. A zero delay keeps the name shown.
+fn label_opacity(since_moved: Duration, delay: Duration) -> f32 {
+    if delay.is_zero() || since_moved <= delay {
+        return 1.0;
+    }
+    let fading = since_moved - delay;
+    1.0 - (fading.as_secs_f32() / CURSOR_LABEL_FADE.as_secs_f32()).min(1.0)
+}
+
+fn content_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Share of the word goal reached, from 0 to 1
+fn goal_progress(words: usize, goal: usize) -> f32 {
+    if goal == 0 {
+        return 0.0;
+    }
+    (words as f32 / goal as f32).min(1.0)
+}
+
+/// Whether the text up to the cursor leaves it inside a fenced code block or an inline code span
+fn in_code(text_before: &str) -> bool {
+    let fences = text_before
+        .lines()
+        .filter(|line| line.trim_start().starts_with("```"))
+        .count();
+    let current_line = text_before.rsplit('\n').next().unwrap_or("");
+
+    fences % 2 == 1 || current_line.matches('`').count() % 2 == 1
+}
+
+/// A quote opens when it starts the text or follows whitespace or an opening bracket
+fn opens_quote(text_before: &str) -> bool {
+    match text_before.chars().last() {
+        None => true,
+        Some(prev) => prev.is_whitespace() || "([{\u{201C}\u{2018}".contains(prev),
+    }
+}
+
+/// Whether a letter typed after `text_before` begins a new sentence
+fn is_sentence_start(text_before: &str) -> bool {
+    let current_line = text_before.rsplit('\n').next().unwrap_or("");
+    let line_body = current_line.trim_start_matches(|c: char| {
+        c.is_whitespace() || c.is_ascii_digit() || "#>-*+.)".contains(c)
+    });
+
+    // Start of a paragraph, heading, list item or quote
+    if line_body.is_empty() {
+        let previous = text_before[..text_before.len() - current_line.len()].trim_end_matches('\n');
+        let previous_line = previous.rsplit('\n').next().unwrap_or("");
+        return previous.is_empty()
+            || text_before.ends_with("\n\n")
+            || !current_line.trim().is_empty()
+            || previous_line.trim().is_empty()
+            || ends_sentence(previous_line.trim_end());
+    }
+
+    // Mid-line, a sentence only starts after a terminator followed by whitespace
+    let trimmed = line_body.trim_end();
+    trimmed.len() < line_body.len() && ends_sentence(trimmed)
+}
+
+/// Splits a list item line into its indentation and marker, e.g. `("  ", "- ")` or `("", "3. ")`
+fn list_marker(line: &str) -> Option<(&str, &str)> {
+    let body = line.trim_start();
+    let indent = &line[..line.len() - body.len()];
+
+    let marker_len = if ["- ", "* ", "+ "]
+        .iter()
+        .any(|bullet| body.starts_with(bullet))
+    {
+        2
+    } else {
+        let digits = body.chars().take_while(char::is_ascii_digit).count();
+        let rest = &body[digits..];
+        if digits == 0 || !(rest.starts_with(". ") || rest.starts_with(") ")) {
+            return None;
+        }
+        digits + 2
+    };
+
+    Some((indent, &body[..marker_len]))
+}
+
+/// Rewrites multi-line text pasted into the list item `line` so each further line becomes an
+/// item of its own, numbering on from an ordered item. Returns `None` when there is nothing to do.
+fn continue_list(line: &str, pasted: &str) -> Option<String> {
+    if !pasted.contains('\n') {
+        return None;
+    }
+    let (indent, marker) = list_marker(line)?;
+    let number = marker[..marker.len() - 2].parse::<usize>().ok();
+
+    let mut lines = pasted.split('\n');
+    let mut text = lines.next().unwrap_or("").to_string();
+    for (offset, pasted_line) in lines.enumerate() {
+        text.push('\n');
+        if pasted_line.trim().is_empty() {
+            continue;
+        }
+
+        // Lines copied from another list keep their text but take this list's marker
+        let item = list_marker(pasted_line).map_or(pasted_line.trim_start(), |(_, existing)| {
+            &pasted_line.trim_start()[existing.len()..]
+        });
+        let marker = match number {
+            Some(number) => format!("{}{}", number + offset + 1, &marker[marker.len() - 2..]),
+            None => marker.to_string(),
+        };
+        text.push_str(&format!("{indent}{marker}{item}"));
+    }
+
+    Some(text)
+}
+
+/// Breaks lines longer than `column` characters, after the last space that fits where there is
+/// one. Text without spaces, such as minified code, is cut at the column.
+fn hard_wrap(text: &str, column: usize) -> String {
+    let column = column.max(1);
+    let mut output = String::with_capacity(text.len() + text.len() / column);
+
+    for (index, mut line) in text.split('\n').enumerate() {
+        if index > 0 {
+            output.push('\n');
+        }
+
+        // Only looks as far as the column, so a huge line is not counted again for every break
+        while let Some((limit, _)) = line.char_indices().nth(column) {
+            let (head, rest) = match line[..limit].rfind(' ') {
+                Some(space) if space > 0 => (&line[..space], &line[space + 1..]),
+                _ => line.split_at(limit),
+            };
+            output.push_str(head);
+            output.push('\n');
+            line = rest;
+        }
+        output.push_str(line);
+    }
+
+    output
+}
+
+/// Markers that turn a line into a comment in a language, given by name or file extension.
+/// Markdown and anything unknown use HTML comments.
+fn comment_markers(language: &str) -> (&'static str, &'static str) {
+    match language.to_ascii_lowercase().as_str() {
+        "rs" | "rust" | "c" | "h" | "cpp" | "cc" | "hpp" | "c++" | "cs" | "java" | "js"
+        | "javascript" | "jsx" | "ts" | "typescript" | "tsx" | "go" | "swift" | "kt" | "kotlin"
+        | "scala" | "dart" => ("// ", ""),
+        "py" | "python" | "sh" | "bash" | "zsh" | "shell" | "rb" | "ruby" | "pl" | "perl"
+        | "yaml" | "yml" | "toml" | "r" | "dockerfile" | "makefile" | "ini" => ("# ", ""),
+        "sql" | "lua" | "hs" | "haskell" | "elm" => ("-- ", ""),
+        "css" => ("/* ", " */"),
+        _ => ("<!-- ", " -->"),
+    }
+}
+
+/// Keeps the end of `text`, which tells files apart best, within `max_chars` characters
+fn truncate_start(text: &str, max_chars: usize) -> String {
+    let count = text.chars().count();
+    if count <= max_chars {
+        return text.to_string();
+    }
+
+    let kept = max_chars.saturating_sub(3);
+    let tail: String = text.chars().skip(count - kept).collect();
+    format!("...{tail}")
+}
+
+/// Columns the first `chars` characters of a line take up, with tabs reaching the next stop
+fn visual_column(line: &str, chars: usize) -> usize {
+    line.chars().take(chars).fold(0, |column, c| {
+        if c == '\t' {
+            column + TAB_WIDTH - column % TAB_WIDTH
+        } else {
+            column + 1
+        }
+    })
+}
+
+/// Line and column of a byte offset into text whose lines are joined by newlines
+fn position_at(text: &str, byte: usize) -> (usize, usize) {
+    let before = text.get(..byte).unwrap_or(text);
+    let line = before.matches('\n').count();
+    let col = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count());
+    (line, col)
+}
+
+/// Byte offset of the bracket matching the one just after `at`, or else the one just before it
+fn matching_bracket(text: &str, at: usize) -> Option<usize> {
+    let after = text.get(at..)?.chars().next().map(|c| (at, c));
+    let before = text
+        .get(..at)?
+        .chars()
+        .next_back()
+        .map(|c| (at - c.len_utf8(), c));
+
+    after
+        .into_iter()
+        .chain(before)
+        .find_map(|(position, bracket)| {
+            let mut depth = 0;
+            if let Some(&(open, close)) = BRACKET_PAIRS.iter().find(|(open, _)| *open == bracket) {
+                for (offset, c) in text[position..].char_indices() {
+                    if c == open {
+                        depth += 1;
+                    } else if c == close {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(position + offset);
+                        }
+                    }
+                }
+            } else if let Some(&(open, close)) =
+                BRACKET_PAIRS.iter().find(|(_, close)| *close == bracket)
+            {
+                for (offset, c) in text[..=position].char_indices().rev() {
+                    if c == close {
+                        depth += 1;
+                    } else if c == open {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(offset);
+                        }
+                    }
+                }
+            }
+            None
+        })
+}
+
+/// Character ranges of the non-overlapping matches of `query` in `line`. A whole word match
+/// has no letter, digit or underscore right before or after it.
+fn find_in_line(line: &str, query: &str, ignore_case: bool, whole_word: bool) -> Vec<Range<usize>> {
+    let chars: Vec<char> = line.chars().collect();
+    let query: Vec<char> = query.chars().collect();
+    let same =
+        |a: &char, b: &char| a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase()));
+    let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+
+    let mut matches = Vec::new();
+    if query.is_empty() {
+        return matches;
+    }
+    let mut start = 0;
+    while start + query.len() <= chars.len() {
+        let end = start + query.len();
+        let found = chars[start..end]
+            .iter()
+            .zip(&query)
+            .all(|(a, b)| same(a, b))
+            && !(whole_word
+                && (start > 0 && is_word(&chars[start - 1])
+                    || chars.get(end).is_some_and(is_word)));
+        if found {
+            matches.push(start..end);
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+/// Markdown link to a pasted URL labelled with the selection it replaces, when the paste is a
+/// single web or mail address and the selection one line of text
+fn link_paste(selection: &str, pasted: &str) -> Option<String> {
+    let pasted = pasted.trim();
+    if selection.trim().is_empty()
+        || selection.contains('\n')
+        || pasted.contains(char::is_whitespace)
+    {
+        return None;
+    }
+
+    let url = url::Url::parse(pasted).ok()?;
+    if !matches!(url.scheme(), "http" | "https" | "mailto") {
+        return None;
+    }
+    let label = selection.replace('[', "\\[").replace(']', "\\]");
+    Some(format!("[{}]({})", label, pasted))
+}
+
+/// Comments out the non-blank lines of a selection, keeping their indentation, or removes the
+/// markers again when every one of them is already commented
+fn toggle_comment(selection: &str, open: &str, close: &str) -> String {
+    let (lines, trailing) = selected_lines(selection);
+    let is_commented = |line: &str| {
+        let line = line.trim();
+        line.starts_with(open.trim_end()) && line.ends_with(close.trim_start())
+    };
+    let uncomment = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .all(|line| is_commented(line));
+
+    let toggled: Vec<String> = lines
+        .iter()
+        .map(|line| {
+            if line.trim().is_empty() {
+                return line.to_string();
+            }
+
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let body = line.trim_start();
+            if uncomment {
+                let body = body
+                    .strip_prefix(open)
+                    .or_else(|| body.strip_prefix(open.trim_end()))
+                    .unwrap_or(body);
+                let body = body
+                    .strip_suffix(close)
+                    .or_else(|| body.strip_suffix(close.trim_start()))
+                    .unwrap_or(body);
+                format!("{indent}{body}")
+            } else {
+                format!("{indent}{open}{body}{close}")
+            }
+        })
+        .collect();
+
+    toggled.join("\n") + trailing
+}
+
+/// Splits a selection into its lines, apart from the line break it may end with, which a
+/// rewrite of the lines should leave where it is
+fn selected_lines(selection: &str) -> (Vec<&str>, &str) {
+    match selection.strip_suffix('\n') {
+        Some(lines) => (lines.split('\n').collect(), "\n"),
+        None => (selection.split('\n').collect(), ""),
+    }
+}
+
+fn sort_lines(selection: &str, descending: bool, case_sensitive: bool) -> String {
+    let (mut lines, trailing) = selected_lines(selection);
+    if case_sensitive {
+        lines.sort_unstable();
+    } else {
+        // Lines equal apart from case keep their order
+        lines.sort_by_cached_key(|line| line.to_lowercase());
+    }
+    if descending {
+        lines.reverse();
+    }
+
+    lines.join("\n") + trailing
+}
+
+/// Keeps the first of each run of equal lines, or of all equal lines when `everywhere` is set
+fn unique_lines(selection: &str, everywhere: bool) -> String {
+    let (lines, trailing) = selected_lines(selection);
+    let mut seen = HashSet::new();
+    let mut unique: Vec<&str> = Vec::with_capacity(lines.len());
+
+    for line in lines {
+        let is_duplicate = if everywhere {
+            !seen.insert(line)
+        } else {
+            unique.last() == Some(&line)
+        };
+        if !is_duplicate {
+            unique.push(line);
+        }
+    }
+
+    unique.join("\n") + trailing
+}
+
+fn ends_sentence(text: &str) -> bool {
+    let text = text.trim_end_matches(['"', '\'', ')', '\u{201D}', '\u{2019}']);
+    if !text.ends_with(['.', '!', '?']) {
+        return false;
+    }
+
+    let last_word = text.rsplit(char::is_whitespace).next().unwrap_or("");
+    let lowered = last_word.to_lowercase();
+
+    // Abbreviations and initials such as `J.` do not end a sentence
+    let is_initial = last_word.len() == 2 && last_word.starts_with(char::is_alphabetic);
+    !(text.ends_with('.') && (ABBREVIATIONS.contains(&lowered.as_str()) || is_initial))
+}
+
+fn toggle_wrap(selection: &str, open: &str, close: &str) -> String {
+    if selection.len() >= open.len() + close.len() {
+        if let Some(inner) = selection
+            .strip_prefix(open)
+            .and_then(|rest| rest.strip_suffix(close))
+        {
+            return inner.to_string();
+        }
+    }
+
+    format!("{}{}{}", open, selection, close)
+}
+
+/// White text on black, with saturated accents, for the high contrast setting
+fn high_contrast_theme() -> Theme {
+    Theme::custom(
+        String::from("High Contrast"),
+        iced::theme::Palette {
+            background: Color::BLACK,
+            text: Color::WHITE,
+            primary: Color::from_rgb(1.0, 1.0, 0.0),
+            success: Color::from_rgb(0.0, 1.0, 0.0),
+            danger: Color::from_rgb(1.0, 0.4, 0.4),
+        },
+    )
+}
+
+fn modal<'a, Message>(
+    base: impl Into<Element<'a, Message>>,
+    content: impl Into<Element<'a, Message>>,
+    on_blur: Message,
+) -> Element<'a, Message>
+where
+    Message: Clone + 'a,
+{
+    stack![
+        base.into(),
+        opaque(
+            mouse_area(center(opaque(content)).style(|_theme| {
+                container::Style {
+                    background: Some(
+                        Color {
+                            a: 0.8,
+                            ..Color::BLACK
+                        }
+                        .into(),
+                    ),
+                    ..container::Style::default()
+                }
+            }))
+            .on_press(on_blur)
+        )
+    ]
+    .into()
+}
+
+pub enum Input {
+    Cursors(Vec<(UserId, CursorMarker)>),
+    Names(HashMap<UserId, String>),
+    Participants(Participants),
+    Edit(Document),
+}
+
+fn server_worker() -> impl Stream<Item = Message> {
+    stream::channel(100, |mut output| async move {
+        // Create channel
+        let (sender, mut receiver) = mpsc::channel(100);
+
+        // Send the sender back to the application
+        output.send(Message::WorkerReady(sender)).await.unwrap();
+
+        loop {
+            use iced_futures::futures::StreamExt;
+
+            // Read next input sent from `Application`
+            let input = receiver.select_next_some().await;
+
+            match input {
+                Input::Cursors(cursors) => output
+                    .send(Message::UpdateHostCursors(cursors))
+                    .await
+                    .unwrap(),
+                Input::Names(names) => output.send(Message::UpdateHostNames(names)).await.unwrap(),
+                Input::Participants(participants) => output
+                    .send(Message::UpdateHostParticipants(participants))
+                    .await
+                    .unwrap(),
+                Input::Edit(document) => {
+                    output.send(Message::UpdateHostDoc(document)).await.unwrap()
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iced::highlighter;
+
+    /// An editor with the default settings, keeping what it saves out of the user's own
+    /// config folder
+    fn editor() -> Editor {
+        static EDITORS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        let config_dir = std::env::temp_dir().join(format!(
+            "rust-note-test-config-{}-{}",
+            std::process::id(),
+            EDITORS.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        ));
+        Editor::with_settings(Settings::default(), config_dir).0
+    }
+
+    /// Runs what a message left for the runtime, returning the messages that produced
+    async fn run(task: Task<Message>) -> Vec<Message> {
+        use iced_futures::futures::StreamExt;
+        use iced_runtime::Action;
+
+        let Some(stream) = iced_runtime::task::into_stream(task) else {
+            return Vec::new();
+        };
+        stream
+            .filter_map(|action| async move {
+                match action {
+                    Action::Output(message) => Some(message),
+                    _ => None,
+                }
+            })
+            .collect()
+            .await
+    }
+
+    /// Types `text` into the editor a character at a time, as keystrokes do
+    async fn type_text(editor: &mut Editor, text: &str) {
+        for ch in text.chars() {
+            let edit = match ch {
+                '\n' => text_editor::Edit::Enter,
+                ch => text_editor::Edit::Insert(ch),
+            };
+            let task = editor.update(Message::Action(text_editor::Action::Edit(edit)));
+            run(task).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn typing_into_a_fresh_document_adds_no_newline() {
+        let mut editor = editor();
+        type_text(&mut editor, "x").await;
+        assert!(editor.document.lock().await.has_text("x"));
+
+        type_text(&mut editor, "\n\ny").await;
+        assert!(editor.document.lock().await.has_text("x\n\ny"));
+        assert_eq!(editor.buffer_text(), "x\n\ny");
+    }
+
+    #[tokio::test]
+    async fn an_opened_file_is_shared_without_its_path() {
+        let mut editor = editor();
+        let path = PathBuf::from("/home/someone/private-notes/plan.md");
+        let opened = Ok((path, Arc::new("The plan".to_string())));
+        run(editor.update(Message::Menu(MenuMessage::FileOpened(opened)))).await;
+
+        let doc = editor.document.lock().await.clone();
+        assert!(doc.has_text("The plan"));
+        let message = ProtocolMessage::Document(doc).to_ws_text();
+        assert!(!message.contains("private-notes"), "{message}");
+        assert!(!message.contains("plan.md"), "{message}");
+        std::fs::remove_dir_all(&editor.config_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn opened_files_are_remembered_in_the_editors_config_folder() {
+        let mut editor = editor();
+        let path = PathBuf::from("/home/someone/private-notes/plan.md");
+        let opened = Ok((path.clone(), Arc::new("The plan".to_string())));
+        let task = editor.update(Message::Menu(MenuMessage::FileOpened(opened)));
+        // The list is written by the task rather than while handling the message
+        assert!(!recent_list(&editor.config_dir).exists());
+
+        run(task).await;
+        let saved = std::fs::read_to_string(recent_list(&editor.config_dir)).unwrap();
+        std::fs::remove_dir_all(&editor.config_dir).unwrap();
+        let saved: Vec<PathBuf> = serde_json::from_str(&saved).unwrap();
+        assert_eq!(saved, [path]);
+    }
+
+    #[tokio::test]
+    async fn typing_on_a_blank_line_adds_no_newline() {
+        let mut editor = editor();
+        type_text(&mut editor, "a\n\nb").await;
+        editor.move_caret_to(1, 0);
+        type_text(&mut editor, "c").await;
+        assert!(editor.document.lock().await.has_text("a\nc\nb"));
+    }
+
+    #[tokio::test]
+    async fn the_document_matches_the_editor_after_edits_on_blank_lines() {
+        let mut editor = editor();
+        type_text(&mut editor, "a\n\n\nb\n").await;
+        for (line, typed) in [(4, "end"), (1, "x\n"), (3, "y")] {
+            editor.move_caret_to(line, 0);
+            type_text(&mut editor, typed).await;
+            let shown = editor.buffer_text();
+            assert!(editor.document.lock().await.has_text(&shown), "{shown:?}");
+        }
+        assert_eq!(editor.buffer_text(), "a\nx\n\ny\nb\nend");
+    }
+
+    #[tokio::test]
+    async fn recovered_text_is_offered_until_restored() {
+        let mut editor = editor();
+        // Left behind by an editor that crashed
+        let crashed = Recovery::new(&editor.config_dir);
+        crashed
+            .clone()
+            .write("unsaved work".to_string())
+            .await
+            .unwrap();
+        drop(crashed);
+
+        let found = editor.recovery.clone().find().await;
+        let _ = editor.update(Message::RecoveryFound(found));
+        assert_eq!(editor.recovered_text.as_deref(), Some("unsaved work"));
+
+        run(editor.update(Message::RestoreRecovery)).await;
+        assert_eq!(editor.recovered_text, None);
+        assert_eq!(editor.content.text().trim_end(), "unsaved work");
+
+        // The text now sits in this editor's own dump, which no other editor offers
+        let dumps = std::fs::read_dir(editor.config_dir.join("recovery")).unwrap();
+        assert_eq!(dumps.count(), 1);
+        assert_eq!(Recovery::new(&editor.config_dir).find().await, None);
+        std::fs::remove_dir_all(&editor.config_dir).unwrap();
+    }
+
+    #[test]
+    fn idling_past_the_timeout_locks_the_session() {
+        let mut editor = editor();
+        editor.idle_lock_timeout = Some(Duration::from_secs(60));
+        editor.last_host_activity = Instant::now();
+        let _ = editor.update(Message::CheckIdle);
+        assert!(!editor.session_locked);
+
+        editor.last_host_activity = Instant::now().checked_sub(Duration::from_secs(61)).unwrap();
+        let _ = editor.update(Message::CheckIdle);
+        assert!(editor.session_locked);
+    }
+
+    #[test]
+    fn toggle_wrap_wraps_and_unwraps_custom_delimiters() {
+        let wrapped = toggle_wrap("note", "<mark>", "</mark>");
+        assert_eq!(wrapped, "<mark>note</mark>");
+        assert_eq!(toggle_wrap(&wrapped, "<mark>", "</mark>"), "note");
+
+        assert_eq!(toggle_wrap("x + y", "$", "$"), "$x + y$");
+        assert_eq!(toggle_wrap("$x + y$", "$", "$"), "x + y");
+    }
+
+    #[test]
+    fn toggle_wrap_does_not_unwrap_text_shorter_than_its_delimiters() {
+        // A lone `$` both starts and ends with the delimiter, but holds no pair of them
+        assert_eq!(toggle_wrap("$", "$", "$"), "$$$");
+        assert_eq!(toggle_wrap("", "==", "=="), "====");
+    }
+
+    #[test]
+    fn sentences_start_at_the_top_and_after_terminators() {
+        assert!(is_sentence_start(""));
+        assert!(is_sentence_start("Done. "));
+        assert!(is_sentence_start("Really? "));
+        assert!(is_sentence_start("First paragraph\n\n"));
+        assert!(is_sentence_start("# "));
+        assert!(is_sentence_start("- "));
+        assert!(is_sentence_start("Intro.\n1. "));
+    }
+
+    #[test]
+    fn sentences_do_not_start_mid_sentence_or_after_abbreviations() {
+        assert!(!is_sentence_start("Hello "));
+        assert!(!is_sentence_start("Done."));
+        assert!(!is_sentence_start("Ask Dr. "));
+        assert!(!is_sentence_start("Fruit, e.g. "));
+        assert!(!is_sentence_start("Written by J. "));
+        assert!(!is_sentence_start("a line that goes on\n"));
+    }
+
+    #[test]
+    fn code_spans_and_fences_are_detected() {
+        assert!(in_code("Run `cargo "));
+        assert!(!in_code("Run `cargo` then "));
+        assert!(in_code("```rust\nlet x = 1;\n"));
+        assert!(!in_code("```rust\nlet x = 1;\n```\n"));
+    }
+
+    #[test]
+    fn the_cursor_marker_follows_the_line_height() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("one\ntwo\nthree"));
+        editor.line_height = 30.0;
+        let _ = editor.update(Message::Action(text_editor::Action::Move(
+            text_editor::Motion::DocumentEnd,
+        )));
+        assert_eq!(editor.cursor_marker.height, 30.0);
+        assert_eq!(editor.cursor_marker.y, 60.0);
+    }
+
+    #[test]
+    fn remote_markers_are_redrawn_at_the_local_line_height() {
+        let remote = CursorMarker::new(42.0, 21.0);
+        let local = remote.scaled_to(30.0);
+        assert_eq!(local.height, 30.0);
+        assert_eq!(local.line(), remote.line());
+        assert_eq!(local.y, 60.0);
+    }
+
+    #[test]
+    fn leaving_with_unsynced_edits_asks_first() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        let _ = editor.update(Message::Action(text_editor::Action::Edit(
+            text_editor::Edit::Insert('x'),
+        )));
+        assert_eq!(editor.unsynced_edits, 1);
+
+        let _ = editor.update(Message::LeaveSession);
+        assert!(editor.leave_confirm_open);
+        assert!(editor.joined_session);
+
+        let _ = editor.update(Message::ConfirmLeaveSession);
+        assert!(!editor.leave_confirm_open);
+        assert!(!editor.joined_session);
+        assert_eq!(editor.unsynced_edits, 0);
+    }
+
+    #[tokio::test]
+    async fn an_edit_the_closed_connection_dropped_counts_as_unsynced() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        editor.client_state = State::Connected(client::Connection::closed());
+        editor.modal_content.session_selection = Some(SessionType::Edit);
+
+        type_text(&mut editor, "x").await;
+        assert_eq!(editor.unsynced_edits, 0);
+        let task = editor.update(Message::Action(text_editor::Action::Edit(
+            text_editor::Edit::Insert('y'),
+        )));
+        for message in run(task).await {
+            let _ = editor.update(message);
+        }
+        assert_eq!(editor.unsynced_edits, 1);
+        assert!(editor.document.lock().await.has_text("xy"));
+
+        // Reported after leaving, it is no longer counted
+        let generation = editor.session_generation;
+        let _ = editor.update(Message::ConfirmLeaveSession);
+        let _ = editor.update(Message::EditUnsent(generation));
+        assert_eq!(editor.unsynced_edits, 0);
+    }
+
+    #[tokio::test]
+    async fn leaving_waits_on_the_copy_being_saved() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        editor.unsynced_edits = 1;
+        let _ = editor.update(Message::LeaveSession);
+
+        // A cancelled or failed save keeps the session and asks again
+        for error in [SAVE_CANCELLED.to_string(), "disk full".to_string()] {
+            let _ = editor.update(Message::SaveCopyAndLeave);
+            assert!(!editor.leave_confirm_open);
+            let task = editor.update(Message::Menu(MenuMessage::FileSaved(Err(error))));
+            assert!(!run(task)
+                .await
+                .iter()
+                .any(|message| matches!(message, Message::ConfirmLeaveSession)));
+            assert!(editor.joined_session);
+            assert!(editor.leave_confirm_open);
+            assert_eq!(editor.unsynced_edits, 1);
+        }
+
+        let _ = editor.update(Message::SaveCopyAndLeave);
+        let saved = editor.config_dir.join("copy.md");
+        let task = editor.update(Message::Menu(MenuMessage::FileSaved(Ok(saved))));
+        for message in run(task).await {
+            let _ = editor.update(message);
+        }
+        std::fs::remove_dir_all(&editor.config_dir).unwrap();
+        assert!(!editor.joined_session);
+        assert!(!editor.leave_confirm_open);
+    }
+
+    #[test]
+    fn leaving_with_everything_synced_does_not_ask() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        let _ = editor.update(Message::LeaveSession);
+        assert!(!editor.leave_confirm_open);
+    }
+
+    /// A document last edited by someone other than `editor`
+    fn remote_document(editor: &Editor, text: &str) -> Document {
+        let mut doc = Document::new(text.to_string());
+        doc.last_edit = editor.id.map_or(1, |id| id + 1);
+        doc
+    }
+
+    #[test]
+    fn a_remote_delete_below_the_caret_clamps_it_to_the_new_end() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text(
+            "one\ntwo\nthree\nfour\nfive",
+        ));
+        editor.move_caret_to(4, 3);
+        assert_eq!(editor.content.cursor_position(), (4, 3));
+
+        let _ = editor.update(Message::UpdateHostDoc(remote_document(&editor, "one\nt")));
+        assert_eq!(editor.content.cursor_position(), (1, 1));
+    }
+
+    #[test]
+    fn a_remote_delete_on_the_caret_line_keeps_the_caret_on_it() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("abcd\nefgh"));
+        editor.move_caret_to(0, 4);
+
+        let _ = editor.update(Message::UpdateHostDoc(remote_document(&editor, "ab\nefgh")));
+        assert_eq!(editor.content.cursor_position(), (0, 2));
+    }
+
+    #[test]
+    fn a_remote_delete_elsewhere_keeps_the_caret() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("one\ntwo\nthree"));
+        editor.move_caret_to(1, 2);
+
+        let _ = editor.update(Message::UpdateHostDoc(remote_document(&editor, "one\ntwo")));
+        assert_eq!(editor.content.cursor_position(), (1, 2));
+    }
+
+    #[test]
+    fn three_lines_pasted_into_a_bulleted_list_become_items() {
+        assert_eq!(
+            continue_list("  - ", "milk\neggs\nbread").as_deref(),
+            Some("milk\n  - eggs\n  - bread")
+        );
+        // Markers copied along with the lines are swapped for this list's
+        assert_eq!(
+            continue_list("* first", "second\n- third\n+ fourth").as_deref(),
+            Some("second\n* third\n* fourth")
+        );
+    }
+
+    #[test]
+    fn lines_pasted_into_an_ordered_list_are_numbered_on() {
+        assert_eq!(
+            continue_list("3) ", "c\nd\ne").as_deref(),
+            Some("c\n4) d\n5) e")
+        );
+    }
+
+    #[test]
+    fn single_lines_and_plain_text_are_pasted_as_is() {
+        assert_eq!(continue_list("- ", "one line"), None);
+        assert_eq!(continue_list("Not a list", "a\nb"), None);
+        assert_eq!(continue_list("-no space", "a\nb"), None);
+    }
+
+    /// Lets the preview debounce run out, as if no change came in for a while
+    fn settle(editor: &mut Editor) {
+        editor.last_document_change = Instant::now().checked_sub(PREVIEW_DEBOUNCE).unwrap();
+        let _ = editor.update(Message::RefreshPreview);
+    }
+
+    #[test]
+    fn counts_follow_a_remote_insert() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("one two"));
+        settle(&mut editor);
+        assert_eq!(editor.text_statistics(), (2, 1));
+
+        let remote = remote_document(&editor, "one two\nthree four five");
+        let _ = editor.update(Message::UpdateHostDoc(remote));
+        // The counts are refreshed with the preview, once remote updates settle
+        settle(&mut editor);
+        assert_eq!(editor.text_statistics(), (5, 2));
+    }
+
+    #[test]
+    fn closing_the_window_remembers_the_file_and_caret() {
+        let mut editor = editor();
+        editor.settings.reopen_last_file = true;
+        editor.file = Some(PathBuf::from("journal.md"));
+        editor.set_content(text_editor::Content::with_text("first\nsecond"));
+        editor.move_caret_to(1, 3);
+
+        let _ = editor.update(Message::CloseWindow(iced::window::Id::unique()));
+        assert_eq!(editor.settings.last_file, Some(PathBuf::from("journal.md")));
+        assert_eq!(editor.settings.last_caret, (1, 3));
+    }
+
+    #[test]
+    fn highlighting_follows_the_theme_until_picked_by_hand() {
+        let mut editor = editor();
+        editor.settings.highlight_theme_follows_app = true;
+        let _ = editor.update(Message::Menu(MenuMessage::ThemeSelected(Theme::Light)));
+        assert_eq!(
+            editor.settings.highlight_theme,
+            highlighter::Theme::InspiredGitHub
+        );
+
+        let _ = editor.update(Message::Menu(MenuMessage::HighlightThemeSelected(
+            highlighter::Theme::Base16Ocean,
+        )));
+        let _ = editor.update(Message::Menu(MenuMessage::ThemeSelected(Theme::Dracula)));
+        assert_eq!(
+            editor.settings.highlight_theme,
+            highlighter::Theme::Base16Ocean
+        );
+    }
+
+    #[test]
+    fn switching_access_reconnects_with_the_password_given() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        editor.modal_content.session_selection = Some(SessionType::Read);
+        editor.modal_content.session_password_input = "reading".to_string();
+
+        let _ = editor.update(Message::SwitchAccess);
+        assert!(editor.access_prompt_open);
+        let _ = editor.update(Message::AccessPasswordChanged("writing".to_string()));
+        let _ = editor.update(Message::ConfirmAccessSwitch);
+        assert_eq!(
+            editor.modal_content.session_selection,
+            Some(SessionType::Edit)
+        );
+        assert_eq!(editor.modal_content.session_password_input, "writing");
+
+        // A refused password goes back to the access that worked
+        let _ = editor.update(Message::Echo(
+            editor.session_generation,
+            client::Event::IncorrectPassword,
+        ));
+        assert!(editor.joined_session);
+        assert_eq!(
+            editor.modal_content.session_selection,
+            Some(SessionType::Read)
+        );
+        assert_eq!(editor.modal_content.session_password_input, "reading");
+    }
+
+    #[test]
+    fn a_known_password_switches_access_without_asking() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        editor.modal_content.session_selection = Some(SessionType::Read);
+        editor
+            .known_access_passwords
+            .insert(SessionType::Edit, "writing".to_string());
+
+        let _ = editor.update(Message::SwitchAccess);
+        assert!(!editor.access_prompt_open);
+        assert_eq!(
+            editor.modal_content.session_selection,
+            Some(SessionType::Edit)
+        );
+    }
+
+    #[test]
+    fn preview_items_only_exist_while_the_preview_is_open() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("# Title\n\nSome *text*"));
+        settle(&mut editor);
+        assert!(editor.markdown_text.is_empty());
+
+        let _ = editor.update(Message::ShowMarkdownPreview(true));
+        assert!(!editor.markdown_text.is_empty());
+
+        let _ = editor.update(Message::ShowMarkdownPreview(false));
+        assert!(editor.markdown_text.is_empty());
+
+        // Edits made with the preview closed are not parsed either
+        let _ = editor.update(Message::Action(text_editor::Action::Edit(
+            text_editor::Edit::Insert('!'),
+        )));
+        settle(&mut editor);
+        assert!(editor.markdown_text.is_empty());
+    }
+
+    #[test]
+    fn the_host_sees_which_access_needs_a_password() {
+        let mut editor = editor();
+        editor.started_session = true;
+        editor.read_password = None;
+        editor.edit_password = Some("writing".to_string());
+        assert_eq!(
+            editor.session_info(),
+            "Hosting | Read access: open | Write access: password"
+        );
+    }
+
+    #[test]
+    fn a_joined_session_keeps_files_from_being_opened() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("shared text"));
+        editor.joined_session = true;
+
+        let opened = Ok((PathBuf::from("note.md"), Arc::new("file text".to_string())));
+        let _ = editor.update(Message::Menu(MenuMessage::FileOpened(opened)));
+        assert_eq!(editor.content.text().trim_end(), "shared text");
+        assert_eq!(editor.file, None);
+        assert!(editor.error_banner.is_some());
+    }
+
+    #[test]
+    fn joining_with_unsaved_changes_asks_first() {
+        let mut editor = editor();
+        editor.unsaved_changes = true;
+        let _ = editor.update(Message::JoinSessionPressed);
+        assert!(editor.join_confirm_open);
+        assert!(!editor.joined_session);
+
+        let _ = editor.update(Message::CancelJoinSession);
+        assert!(!editor.join_confirm_open);
+        assert!(editor.unsaved_changes);
+
+        let _ = editor.update(Message::ConfirmJoinSession);
+        assert!(editor.joined_session);
+        assert!(!editor.unsaved_changes);
+    }
+
+    #[tokio::test]
+    async fn the_save_status_follows_a_save_until_the_next_edit() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("draft"));
+        let _ = editor.update(Message::Menu(MenuMessage::SaveFile));
+        assert_eq!(editor.save_status.to_string(), "Saving\u{2026}");
+
+        let failed = Err("disk full".to_string());
+        let _ = editor.update(Message::Menu(MenuMessage::FileSaved(failed)));
+        assert_eq!(editor.save_status.to_string(), "Save failed");
+
+        let cancelled = Err(SAVE_CANCELLED.to_string());
+        let _ = editor.update(Message::Menu(MenuMessage::FileSaved(cancelled)));
+        assert_eq!(editor.save_status.to_string(), "");
+
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(2 * 86400 + 13 * 3600 + 5 * 60);
+        editor.save_status = SaveStatus::Saved(at);
+        assert_eq!(editor.save_status.to_string(), "Saved \u{2713} (13:05 UTC)");
+        type_text(&mut editor, "!").await;
+        assert_eq!(editor.save_status.to_string(), "");
+    }
+
+    /// Line starts measured from scratch, to check the cached ones against
+    fn measured_offsets(text: &str) -> Vec<usize> {
+        std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(at, _)| at + 1))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn cached_line_offsets_follow_edits() {
+        let mut editor = editor();
+        type_text(&mut editor, "one\ntwo\nthree").await;
+        assert_eq!(editor.line_offset(2), 8);
+
+        editor.move_caret_to(0, 0);
+        type_text(&mut editor, "zero ").await;
+        editor.move_caret_to(2, 0);
+        let backspace = text_editor::Action::Edit(text_editor::Edit::Backspace);
+        run(editor.update(Message::Action(backspace))).await;
+
+        let text = editor.buffer_text();
+        for (line, offset) in measured_offsets(&text).into_iter().enumerate() {
+            assert_eq!(editor.line_offset(line), offset, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn a_megabyte_line_is_wrapped() {
+        let line = "word ".repeat(200_000).trim_end().to_string();
+        let wrapped = hard_wrap(&line, 80);
+        assert!(wrapped.lines().all(|line| line.chars().count() <= 80));
+        assert_eq!(wrapped.replace('\n', " "), line);
+
+        let minified = "x".repeat(1_000_000);
+        let wrapped = hard_wrap(&minified, 80);
+        assert_eq!(wrapped.lines().count(), 12_500);
+        assert_eq!(wrapped.replace('\n', ""), minified);
+        assert_eq!(hard_wrap("ééé", 2), "éé\né");
+    }
+
+    #[test]
+    fn cjk_text_is_counted_in_each_mode() {
+        let mixed = "我喜欢 Rust 编程。";
+        assert_eq!(WordCountMode::Whitespace.count(mixed), 3);
+        assert_eq!(WordCountMode::Characters.count(mixed), 10);
+        assert_eq!(WordCountMode::Hybrid.count(mixed), 6);
+
+        let japanese = "東京へ行きます";
+        assert_eq!(WordCountMode::Whitespace.count(japanese), 1);
+        assert_eq!(WordCountMode::Characters.count(japanese), 7);
+        assert_eq!(WordCountMode::Hybrid.count(japanese), 7);
+
+        assert_eq!(WordCountMode::Hybrid.count("two words"), 2);
+    }
+
+    #[test]
+    fn a_cancelled_open_dialog_leaves_the_document_alone() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("keep me"));
+
+        let cancelled = Err(OPEN_CANCELLED.to_string());
+        let _ = editor.update(Message::Menu(MenuMessage::FileOpened(cancelled)));
+        assert_eq!(editor.content.text().trim_end(), "keep me");
+        assert!(editor.error_banner.is_none());
+
+        let failed = Err("permission denied".to_string());
+        let _ = editor.update(Message::Menu(MenuMessage::FileOpened(failed)));
+        assert_eq!(editor.content.text().trim_end(), "keep me");
+        assert!(editor.error_banner.is_some());
+    }
+
+    #[test]
+    fn a_shared_viewport_travels_with_the_cursor() {
+        let marker = CursorMarker {
+            viewport: Some((10, 42)),
+            ..CursorMarker::new(3.0, LINE_HEIGHT)
+        };
+        let text = ProtocolMessage::Cursor(marker).to_ws_text();
+        let Ok(ProtocolMessage::Cursor(received)) = ProtocolMessage::from_ws_text(&text) else {
+            panic!("{text} is not a cursor");
+        };
+        assert_eq!(received.viewport, Some((10, 42)));
+        assert_eq!(received.y, 3.0);
+
+        // Older clients send neither the viewport nor the column
+        let old: CursorMarker = serde_json::from_str(r#"{"y":2.0,"color":[1.0,0.0,0.0]}"#).unwrap();
+        assert_eq!(old.viewport, None);
+        assert_eq!(old.x, 0.0);
+        assert_eq!(old.height, LINE_HEIGHT);
+    }
+
+    #[tokio::test]
+    async fn a_text_file_turns_off_markdown_formatting() {
+        let mut editor = editor();
+        editor.markdown_preview_open = true;
+        run(editor.load_file(PathBuf::from("notes.TXT"), "plain words")).await;
+        assert!(editor.plain_text);
+        assert!(!editor.markdown_preview_open);
+        let bold = run(editor.update(Message::Format(TextStyle::Bold))).await;
+        assert!(bold.is_empty());
+        assert_eq!(editor.buffer_text(), "plain words");
+
+        run(editor.load_file(PathBuf::from("notes.md"), "marked words")).await;
+        assert!(!editor.plain_text);
+        let bold = run(editor.update(Message::Format(TextStyle::Bold))).await;
+        assert!(!bold.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_picked_cursor_color_is_broadcast() {
+        let mut editor = editor();
+        // The settings are saved by the task left behind, which is not run here
+        let _ = editor.update(Message::CursorColorSelected(CursorColor::Teal));
+        assert_eq!(editor.settings.cursor_color, CursorColor::Teal);
+
+        let messages = run(editor.broadcast_cursor()).await;
+        let Some(Message::UpdateHostCursors(cursors)) = messages.first() else {
+            panic!("the host's cursor was not broadcast");
+        };
+        let colors: Vec<_> = cursors.iter().map(|(_, marker)| marker.color).collect();
+        assert_eq!(colors, [CursorColor::Teal.rgb().unwrap()]);
+    }
+
+    #[test]
+    fn cursor_contrast_is_measured_against_the_background() {
+        assert!((contrast_ratio(Color::BLACK, Color::WHITE) - 21.0).abs() < 0.01);
+        assert_eq!(contrast_ratio(Color::WHITE, Color::WHITE), 1.0);
+        let (r, g, b) = CursorColor::Yellow.rgb().unwrap();
+        assert!(contrast_ratio(Color::from_rgb(r, g, b), Color::WHITE) < MIN_CURSOR_CONTRAST);
+        assert!(contrast_ratio(Color::from_rgb(r, g, b), Color::BLACK) >= MIN_CURSOR_CONTRAST);
+    }
+
+    #[test]
+    fn starting_before_the_server_worker_is_ready_is_refused() {
+        let mut editor = editor();
+        editor.settings.confirm_session_start = false;
+        for message in [Message::StartSessionPressed, Message::ConfirmStartSession] {
+            let _ = editor.update(message);
+            assert!(!editor.started_session);
+            assert_eq!(editor.modal_content.session_start_error, NOT_READY_ERROR);
+        }
+
+        let (server_worker, _host_inputs) = mpsc::channel(1);
+        let _ = editor.update(Message::WorkerReady(server_worker));
+        assert!(editor.modal_content.session_start_error.is_empty());
+    }
+
+    #[test]
+    fn comments_are_toggled_on_selected_lines() {
+        let (open, close) = comment_markers("rs");
+        let selection = "fn main() {\n    run();\n\n}\n";
+        let commented = toggle_comment(selection, open, close);
+        assert_eq!(commented, "// fn main() {\n    // run();\n\n// }\n");
+        assert_eq!(toggle_comment(&commented, open, close), selection);
+
+        let (open, close) = comment_markers("Python");
+        assert_eq!(toggle_comment("a = 1\n# b", open, close), "# a = 1\n# # b");
+        assert_eq!(toggle_comment("# a\n#b\n#", open, close), "a\nb\n");
+
+        let (open, close) = comment_markers("md");
+        let commented = toggle_comment("note\n  more", open, close);
+        assert_eq!(commented, "<!-- note -->\n  <!-- more -->");
+        assert_eq!(toggle_comment(&commented, open, close), "note\n  more");
+    }
+
+    #[test]
+    fn the_session_file_is_checked_when_its_path_changes_not_when_drawn() {
+        let path = std::env::temp_dir().join(format!("rust-note-share-{}.md", std::process::id()));
+        std::fs::write(&path, "shared").unwrap();
+
+        let mut editor = editor();
+        editor.session_modal_open = true;
+        let _ = editor.update(Message::FilePathChanged(path.display().to_string()));
+        assert!(editor.modal_content.file_valid);
+
+        // The cached result stands until the path is edited again
+        std::fs::remove_file(&path).unwrap();
+        let _ = editor.view();
+        assert!(editor.modal_content.file_valid);
+
+        let _ = editor.update(Message::FilePathChanged(path.display().to_string()));
+        assert!(!editor.modal_content.file_valid);
+        assert_eq!(
+            editor.modal_content.file_error,
+            "Invalid Markdown file path."
+        );
+    }
+
+    #[test]
+    fn blocks_are_inserted_on_a_line_of_their_own() {
+        assert_eq!(block_insertion(None, "", "---"), "---\n");
+        assert_eq!(block_insertion(Some(""), "", "---"), "---\n");
+        assert_eq!(block_insertion(Some("para"), "", "---"), "\n---\n");
+        assert_eq!(block_insertion(None, "para", "---"), "\n\n---\n");
+        assert_eq!(
+            block_insertion(None, "end", html::PAGE_BREAK),
+            "\n\n<!-- pagebreak -->\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_horizontal_rule_leaves_the_caret_below_it() {
+        let mut editor = editor();
+        type_text(&mut editor, "para").await;
+        for message in run(editor.update(Message::Format(TextStyle::HorizontalRule))).await {
+            run(editor.update(message)).await;
+        }
+        assert_eq!(editor.buffer_text(), "para\n\n---\n");
+        assert_eq!(editor.content.cursor_position(), (3, 0));
+        assert!(editor.document.lock().await.has_text("para\n\n---\n"));
+    }
+
+    #[tokio::test]
+    async fn starting_or_joining_again_while_hosting_does_nothing() {
+        let mut editor = editor();
+        let (server_worker, _host_inputs) = mpsc::channel(1);
+        editor.server_worker = Some(server_worker);
+        editor.started_session = true;
+
+        for message in [
+            Message::StartSessionPressed,
+            Message::ConfirmStartSession,
+            Message::JoinSessionPressed,
+            Message::ConfirmJoinSession,
+        ] {
+            assert!(run(editor.update(message)).await.is_empty());
+            assert!(!editor.start_confirm_open);
+            assert!(!editor.join_confirm_open);
+            assert!(!editor.joined_session);
+            assert!(editor.modal_content.session_start_error.is_empty());
+        }
+    }
+
+    #[test]
+    fn unchanged_text_is_not_written_again() {
+        let does_nothing = |task: Task<Message>| iced_runtime::task::into_stream(task).is_none();
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("saved"));
+        editor.file = Some(PathBuf::from("saved.md"));
+        editor.saved_hash = Some(content_hash(&editor.content.text()));
+        editor.unsaved_changes = true;
+
+        assert!(does_nothing(
+            editor.update(Message::Menu(MenuMessage::SaveFile))
+        ));
+        assert!(!editor.unsaved_changes);
+        assert_eq!(editor.save_status.to_string(), "");
+
+        // The first dump is not run here, the second is skipped all the same
+        assert!(!does_nothing(editor.update(Message::WriteRecovery)));
+        assert!(does_nothing(editor.update(Message::WriteRecovery)));
+
+        editor.set_content(text_editor::Content::with_text("changed"));
+        assert!(!does_nothing(editor.update(Message::WriteRecovery)));
+        assert!(!does_nothing(
+            editor.update(Message::Menu(MenuMessage::SaveFile))
+        ));
+    }
+
+    #[test]
+    fn cursor_labels_fade_after_the_delay() {
+        let delay = Duration::from_secs(2);
+        let half_faded = delay + CURSOR_LABEL_FADE / 2;
+        assert_eq!(label_opacity(Duration::ZERO, delay), 1.0);
+        assert_eq!(label_opacity(delay, delay), 1.0);
+        assert!((label_opacity(half_faded, delay) - 0.5).abs() < 0.01);
+        assert_eq!(label_opacity(delay + CURSOR_LABEL_FADE, delay), 0.0);
+        assert_eq!(label_opacity(delay * 10, delay), 0.0);
+
+        // No delay keeps the names shown
+        assert_eq!(label_opacity(delay * 10, Duration::ZERO), 1.0);
+    }
+
+    #[tokio::test]
+    async fn the_placeholder_is_only_shown_in_an_empty_new_document() {
+        let mut editor = editor();
+        assert_eq!(editor.placeholder(), Some(EDITOR_PLACEHOLDER));
+        let _ = editor.update(Message::ShowMarkdownPreview(true));
+        assert!(editor.markdown_text.is_empty());
+
+        type_text(&mut editor, "x").await;
+        assert_eq!(editor.placeholder(), None);
+
+        editor.set_content(text_editor::Content::new());
+        editor.file = Some(PathBuf::from("empty.md"));
+        assert_eq!(editor.placeholder(), None);
+    }
+
+    #[test]
+    fn tabs_advance_the_cursor_column_to_the_next_stop() {
+        assert_eq!(visual_column("", 0), 0);
+        assert_eq!(visual_column("abc", 2), 2);
+        assert_eq!(visual_column("\tx", 2), TAB_WIDTH + 1);
+        assert_eq!(visual_column("ab\tx", 3), TAB_WIDTH);
+        assert_eq!(visual_column("abcd\t", 5), 2 * TAB_WIDTH);
+        assert_eq!(visual_column("héllo", 99), 5);
 
-                let mut connection = if let State::Connected(ref mut connection) = self.client_state
-                {
-                    Some(connection.clone())
-                } else {
-                    None
-                };
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("one\n\ttwo"));
+        editor
+            .content
+            .perform(text_editor::Action::Move(text_editor::Motion::DocumentEnd));
+        assert_eq!(
+            editor.cursor_position_in_pixels(),
+            (
+                (TAB_WIDTH + 3) as f32 * APPROX_CHAR_WIDTH,
+                editor.line_height
+            )
+        );
 
-                let content_text = self.content.text();
-                let mut index = *running_sum_vec.get(x).unwrap() + y;
+        // Each character takes one column, however many bytes it has
+        editor.set_content(text_editor::Content::with_text("世界 héllo"));
+        editor.move_caret_to(0, 5);
+        assert_eq!(
+            editor.cursor_position_in_pixels(),
+            (5.0 * APPROX_CHAR_WIDTH, 0.0)
+        );
+    }
 
-                let doc_lock = self.document.clone();
-                let is_dirty_lock = self.is_dirty.clone();
-                let selection = self.content.selection().clone();
-                let id = self.id;
+    #[test]
+    fn cursors_from_older_clients_sit_at_the_start_of_the_line() {
+        let marker: CursorMarker =
+            serde_json::from_str(r#"{"y":42.0,"color":[1.0,0.0,0.0]}"#).unwrap();
+        assert_eq!((marker.x, marker.y), (0.0, 42.0));
+    }
 
-                if let Some(session_selection) = self.modal_content.session_selection {
-                    if connection.is_some() && session_selection == SessionType::Read {
-                        match action {
-                            text_editor::Action::Edit(_) => {}
-                            _ => self.content.perform(action.clone()),
-                        }
-                    } else {
-                        self.content.perform(action.clone());
-                    }
-                } else {
-                    self.content.perform(action.clone());
-                }
+    #[test]
+    fn long_names_keep_their_end_on_character_boundaries() {
+        assert_eq!(truncate_start("notes.md", 20), "notes.md");
+        assert_eq!(truncate_start("日本語のメモ帳ファイル.md", 8), "...イル.md");
+        assert_eq!(truncate_start("🦀🦀🦀🦀🦀", 4), "...🦀");
+        assert_eq!(truncate_start("éééé", 4), "éééé");
+        assert_eq!(truncate_start("ééééé", 2), "...");
 
-                // Update markdown preview with the editor's text content
-                self.markdown_text = markdown::parse(&self.content.text()).collect();
+        let mut editor = editor();
+        editor.window_width = 0.0;
+        assert_eq!(editor.status_name_width(), STATUS_NAME_MIN_WIDTH);
+        editor.window_width = 1600.0;
+        assert!(editor.status_name_width() > STATUS_NAME_MIN_WIDTH);
+    }
 
-                let mut tasks = Vec::new();
-                match action {
-                    text_editor::Action::Edit(edit) => {
-                        // Translate local user edit action to document operations
-                        tasks.push(Task::future(async move {
-                            let mut operations = Vec::new();
-                            let mut doc = doc_lock.lock().await;
-                            if let Some(id) = id {
-                                doc.last_edit = id;
-                            }
+    /// Handles `message` and then the edits it turns into, e.g. undoing or replacing
+    async fn replay(editor: &mut Editor, message: Message) {
+        let mut pending = vec![message];
+        while let Some(message) = pending.pop() {
+            let follow_ups = run(editor.update(message))
+                .await
+                .into_iter()
+                .filter(|next| {
+                    matches!(
+                        next,
+                        Message::UndoStep(..) | Message::Action(_) | Message::ReplaceMatches(_)
+                    )
+                });
+            // In the order they were sent, each before what the ones before it led to
+            let follow_ups: Vec<Message> = follow_ups.collect();
+            pending.extend(follow_ups.into_iter().rev());
+        }
+    }
 
-                            let num_deleted = if let Some(s) = selection {
-                                // Find the selection in a slice of the content text
-                                let start = if s.len() > index { 0 } else { index - s.len() };
-                                let end = if index + s.len() > content_text.len() {
-                                    content_text.len()
-                                } else {
-                                    index + s.len()
-                                };
+    #[tokio::test]
+    async fn undo_and_redo_go_through_the_shared_document() {
+        let mut editor = editor();
+        type_text(&mut editor, "ab").await;
 
-                                let text_to_search = content_text.get(start..end).unwrap_or("");
-                                if let Some(i) = text_to_search.find(&s) {
-                                    index = i + start;
-                                    let deletion = doc.delete(index..(index + s.len()));
-                                    operations.push(Operation::Delete(deletion));
+        replay(&mut editor, Message::Undo).await;
+        assert_eq!(editor.buffer_text(), "a");
+        replay(&mut editor, Message::Undo).await;
+        assert_eq!(editor.buffer_text(), "");
+        replay(&mut editor, Message::Redo).await;
+        assert_eq!(editor.buffer_text(), "a");
+        assert!(editor.document.lock().await.has_text("a"));
 
-                                    s.len()
-                                } else {
-                                    // Selection not found
-                                    0
-                                }
-                            } else {
-                                0
-                            };
+        // A fresh edit leaves nothing to redo
+        type_text(&mut editor, "x").await;
+        replay(&mut editor, Message::Redo).await;
+        assert_eq!(editor.buffer_text(), "ax");
 
-                            match edit {
-                                text_editor::Edit::Insert(ch) => {
-                                    let mut text = ch.to_string();
+        // Only the newest edits are kept
+        editor.undo.lock().unwrap().set_depth(1);
+        replay(&mut editor, Message::Undo).await;
+        replay(&mut editor, Message::Undo).await;
+        assert_eq!(editor.buffer_text(), "a");
+        assert!(editor.document.lock().await.has_text("a"));
+    }
 
-                                    if is_blank_line && !doc.check_newline_at(index) {
-                                        text.push('\n'); // Insert newline after character
-                                    }
+    #[tokio::test]
+    async fn a_failed_decode_leaves_the_selection_alone() {
+        let mut editor = editor();
+        type_text(&mut editor, "a b").await;
+        editor.content.perform(text_editor::Action::SelectAll);
+        replay(&mut editor, Message::ConvertSelection(Codec::UrlEncode)).await;
+        assert_eq!(editor.buffer_text(), "a%20b");
+        assert!(editor.document.lock().await.has_text("a%20b"));
 
-                                    let insertion = doc.insert(index, text.clone());
-                                    operations.push(Operation::Insert(insertion));
-                                }
-                                text_editor::Edit::Paste(text) => {
-                                    let mut text = text.to_string();
+        editor.content.perform(text_editor::Action::SelectAll);
+        replay(&mut editor, Message::ConvertSelection(Codec::Base64Decode)).await;
+        assert_eq!(editor.buffer_text(), "a%20b");
+        assert!(editor.error_banner.is_some());
+    }
 
-                                    if is_blank_line && !doc.check_newline_at(index) {
-                                        text.push('\n'); // Insert newline after string
-                                    }
+    #[test]
+    fn events_from_a_left_session_are_ignored() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        let left = editor.session_generation;
+        let _ = editor.update(Message::ConfirmLeaveSession);
+        assert_ne!(editor.session_generation, left);
 
-                                    let insertion = doc.insert(index, text);
-                                    operations.push(Operation::Insert(insertion));
-                                }
-                                text_editor::Edit::Enter => {
-                                    let text = String::from("\n");
+        let _ = editor.update(Message::Echo(left, client::Event::Disconnected));
+        let locked = ProtocolMessage::Locked(true).to_ws_text();
+        let _ = editor.update(Message::Echo(
+            left,
+            client::Event::MessageReceived(client::Message::User(locked.clone())),
+        ));
+        assert!(!editor.reconnecting);
+        assert!(!editor.session_locked);
 
-                                    let insertion = doc.insert(index, text);
-                                    operations.push(Operation::Insert(insertion));
-                                }
-                                text_editor::Edit::Delete => {
-                                    if num_deleted == 0 && doc.len() > index + 1 {
-                                        let deletion = doc.delete(index..(index + 1));
-                                        operations.push(Operation::Delete(deletion));
-                                    }
+        // The same events from the current connection still count
+        let current = editor.session_generation;
+        let _ = editor.update(Message::Echo(
+            current,
+            client::Event::MessageReceived(client::Message::User(locked)),
+        ));
+        assert!(editor.session_locked);
+        let _ = editor.update(Message::Echo(current, client::Event::Disconnected));
+        assert!(editor.reconnecting);
+    }
 
-                                    if doc.len() == 1 {
-                                        let deletion = doc.delete(0..1); // Remove remaining newline character
-                                        operations.push(Operation::Delete(deletion));
-                                    }
-                                }
-                                text_editor::Edit::Backspace => {
-                                    if num_deleted == 0 && doc.len() > 1 {
-                                        let deletion = doc.delete((index - 1)..index);
-                                        operations.push(Operation::Delete(deletion));
-                                    }
+    #[tokio::test]
+    async fn the_sync_lag_counts_host_revisions_not_yet_applied() {
+        let mut editor = editor();
+        assert_eq!(editor.sync_status(), "");
+        editor.joined_session = true;
+        assert_eq!(editor.sync_status(), "Synced");
 
-                                    if doc.len() == 1 {
-                                        let deletion = doc.delete(0..1); // Remove remaining newline character
-                                        operations.push(Operation::Delete(deletion));
-                                    }
-                                }
-                            }
+        let mut doc = remote_document(&editor, "from the host");
+        doc.revision = 3;
+        let received = ProtocolMessage::Document(doc).to_ws_text();
+        let task = editor.update(Message::Echo(
+            editor.session_generation,
+            client::Event::MessageReceived(client::Message::User(received)),
+        ));
+        assert_eq!(editor.sync_status(), "3 edits behind");
 
-                            for op in operations.iter() {
-                                if let Some(conn) = connection.as_mut() {
-                                    match op {
-                                        Operation::Insert(insertion) => {
-                                            conn.send(client::Message::User(format!(
-                                                "Insert: {}",
-                                                serde_json::to_string(insertion).unwrap()
-                                            )));
-                                        }
-                                        Operation::Delete(deletion) => {
-                                            conn.send(client::Message::User(format!(
-                                                "Delete: {}",
-                                                serde_json::to_string(deletion).unwrap()
-                                            )));
-                                        }
-                                    }
-                                }
-                            }
-                            if connection.is_some() {
-                                operations.clear();
-                            }
-                            *is_dirty_lock.lock().await = true;
+        // Applying a copy from a connection that was since left changes nothing
+        let _ = editor.update(Message::RevisionApplied(editor.session_generation + 1, 3));
+        assert_eq!(editor.sync_status(), "3 edits behind");
+        editor.applied_revision = 2;
+        assert_eq!(editor.sync_status(), "1 edit behind");
 
-                            Message::NoOp
-                        }));
-                    }
-                    text_editor::Action::Scroll { lines: _ } => return Task::done(Message::NoOp),
-                    _ => tasks.push(Task::done(Message::NoOp)),
-                }
+        for message in run(task).await {
+            let _ = editor.update(message);
+        }
+        assert_eq!(editor.sync_status(), "Synced");
 
-                let line = self.cursor_position_in_pixels();
-                self.cursor_marker.move_cursor(line);
-                let cursor_marker = self.cursor_marker;
+        // The host's own editor is never behind
+        editor.started_session = true;
+        editor.received_revision = 9;
+        assert_eq!(editor.sync_status(), "");
+    }
 
-                // Check if the user is connected to a session
-                if let State::Connected(ref mut connection) = self.client_state {
-                    if self.joined_session {
-                        let cursor_data = serde_json::to_string(
-                            &json!({ "y": line, "color": self.cursor_marker.color }),
-                        )
-                        .expect("Failed to serialize cursor data");
-                        let message = format!("Cursor: {}", cursor_data);
+    #[test]
+    fn a_malformed_message_from_the_host_is_skipped() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        editor.set_content(text_editor::Content::with_text("kept: as it was"));
 
-                        // Send the message
-                        connection.send(client::Message::User(message));
-                    } else {
-                        println!("Cannot send message; not joined in a session.");
-                    }
-                } else {
-                    // If not, user is the host so update cursor position in the users map and user cursors list
-                    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8080);
-                    let users_lock = self.users.clone();
-                    let is_moved_lock = self.is_moved.clone();
-                    tasks.push(Task::future(async move {
-                        let mut users = users_lock.lock().await;
-                        users.add_user(localhost, Some(cursor_marker));
-                        *is_moved_lock.lock().await = true;
+        for malformed in ["Document: {\"buffer\":\"x\"}", "{\"type\":\"Document\"}"] {
+            let task = editor.update(Message::Echo(
+                editor.session_generation,
+                client::Event::MessageReceived(client::Message::User(malformed.to_string())),
+            ));
+            assert!(iced_runtime::task::into_stream(task).is_none());
+        }
+        assert_eq!(editor.buffer_text(), "kept: as it was");
+    }
 
-                        let cursors = users.get_all_cursors();
-                        Message::UpdateHostCursors(cursors)
-                    }));
-                }
+    /// Hands `message` to a joined editor as if the host had sent it
+    fn from_host(editor: &mut Editor, message: ProtocolMessage) -> Task<Message> {
+        editor.update(Message::Echo(
+            editor.session_generation,
+            client::Event::MessageReceived(client::Message::User(message.to_ws_text())),
+        ))
+    }
 
-                return Task::batch(tasks);
-            }
-            Message::Menu(menu_msg) => match menu_msg {
-                MenuMessage::ThemeSelected(theme) => {
-                    self.theme = theme;
-                }
-                MenuMessage::CloseFile => {
-                    self.file = None;
-                    self.content = text_editor::Content::new();
-                    self.markdown_text = markdown::parse("").collect();
+    #[test]
+    fn the_advertised_limits_are_kept() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        assert_eq!(editor.session_limits, None);
 
-                    let document = self.document.clone();
-                    return Task::future(async move {
-                        let mut doc_lock = document.lock().await;
-                        doc_lock.buffer = "".to_string();
-                        Message::NoOp
-                    });
-                }
-                MenuMessage::FileOpened(result) => match result {
-                    Ok((path, contents)) => {
-                        self.file = Some(path.clone());
-                        self.content = text_editor::Content::with_text(&contents);
-                        self.markdown_text = markdown::parse(&self.content.text()).collect();
-                        println!("File loaded: {:?}", path);
+        let limits = SessionLimits {
+            max_document_bytes: 2048,
+            ..SessionLimits::default()
+        };
+        let _ = from_host(&mut editor, ProtocolMessage::Limits(limits));
+        assert_eq!(editor.session_limits, Some(limits));
+    }
 
-                        let document = self.document.clone();
-                        let content = self.content.text().clone();
-                        return Task::future(async move {
-                            let mut doc_lock = document.lock().await;
-                            doc_lock.buffer = content;
-                            Message::NoOp
-                        });
-                    }
-                    Err(error) => {
-                        println!("Failed to open file: {:?}", error);
-                        return Task::done(Message::NoOp);
-                    }
-                },
-                MenuMessage::OpenFile => {
-                    return Task::perform(open_file(), MenuMessage::FileOpened).map(Message::Menu);
-                }
-                MenuMessage::FileSaved(result) => match result {
-                    Ok(path) => {
-                        println!("File saved at: {}", path.display());
-                    }
-                    Err(error) => {
-                        println!("Failed to save file: {:?}", error);
-                        return Task::done(Message::NoOp);
-                    }
-                },
-                MenuMessage::SaveFile => {
-                    return Task::perform(
-                        save_file(self.file.clone(), self.content.text()),
-                        MenuMessage::FileSaved,
-                    )
-                    .map(Message::Menu);
-                }
-            },
-            Message::Format(text_style) => {
-                let _ = self.format_bar.update(text_style.clone()); // Update the format bar UI
+    #[test]
+    fn a_rejected_edit_fetches_the_hosts_copy_again() {
+        let mut editor = editor();
+        editor.joined_session = true;
+        editor.id = Some(3);
+        let (controller, mut resync_requested) = client::Controller::detached();
+        editor.connection_controller = Some(controller);
 
-                return match text_style {
-                    TextStyle::Bold => self.toggle_formatting(TextStyle::Bold),
-                    TextStyle::Italic => self.toggle_formatting(TextStyle::Italic),
-                    TextStyle::Strikethrough => self.toggle_formatting(TextStyle::Strikethrough),
-                    TextStyle::TextSize(size) => {
-                        // Update the text size
-                        let text_size = if let Ok(size) = size.parse::<f32>() {
-                            iced::Pixels::from(size)
-                        } else {
-                            iced::Pixels::from(DEFAULT_FONT_SIZE)
-                        };
+        // Someone else's edit being refused leaves this copy alone
+        let _ = from_host(&mut editor, ProtocolMessage::Rejected(4));
+        assert!(!resync_requested());
+        assert_eq!(editor.error_banner, None);
 
-                        self.markdown_settings = markdown::Settings::with_text_size(text_size);
-                        Task::done(Message::NoOp)
-                    }
-                };
-            }
-            Message::LinkClicked(url) => {
-                let _ = open::that(url.to_string());
-            }
-            Message::NoOp => {}
-            Message::DeleteLine => {
-                let tasks = vec![
-                    Task::done(Message::Action(text_editor::Action::SelectLine)),
-                    Task::done(Message::Action(text_editor::Action::Edit(
-                        text_editor::Edit::Delete,
-                    ))),
-                ];
+        let _ = from_host(&mut editor, ProtocolMessage::Rejected(3));
+        assert!(resync_requested());
+        assert!(editor.error_banner.is_some());
+    }
 
-                return Task::batch(tasks);
-            }
-            Message::DeleteWord => {
-                let tasks = vec![
-                    Task::done(Message::Action(text_editor::Action::SelectWord)),
-                    Task::done(Message::Action(text_editor::Action::Edit(
-                        text_editor::Edit::Delete,
-                    ))),
-                ];
+    #[test]
+    fn brackets_are_matched_through_nesting() {
+        let text = "f(a[0], {b: (c)})";
+        // From an opening bracket forwards, and a closing one backwards
+        assert_eq!(matching_bracket(text, 1), Some(16));
+        assert_eq!(matching_bracket(text, 16), Some(1));
+        assert_eq!(matching_bracket(text, 3), Some(5));
+        assert_eq!(matching_bracket(text, 8), Some(15));
+        assert_eq!(matching_bracket(text, 12), Some(14));
+        // Just past a closing bracket
+        assert_eq!(matching_bracket(text, 17), Some(1));
+        assert_eq!(matching_bracket(text, 6), Some(3));
+        // Characters of several bytes are stepped over
+        assert_eq!(matching_bracket("(é🦀)", 0), Some(7));
+        assert_eq!(matching_bracket("(é🦀)", 8), Some(0));
+    }
 
-                return Task::batch(tasks);
-            }
-            Message::ShortcutPaletteToggle => {
-                self.shortcut_palette_open = !self.shortcut_palette_open;
-            }
-            Message::ShowMarkdownPreview(toggled) => {
-                self.markdown_preview_open = toggled;
-            }
-            Message::StartSessionPressed => {
-                // Verify that a server worker has been created
-                if self.server_worker.is_none() {
-                    return Task::done(Message::NoOp);
-                }
+    #[test]
+    fn the_caret_jumps_to_the_matching_bracket() {
+        let mut editor = editor();
+        editor.set_content(text_editor::Content::with_text("é {\n  [ü]\n}"));
+        editor.move_caret_to(0, 2);
+        let _ = editor.update(Message::GoToMatchingBracket);
+        assert_eq!(editor.caret_position(), (2, 0));
+        let _ = editor.update(Message::GoToMatchingBracket);
+        assert_eq!(editor.caret_position(), (0, 2));
 
-                self.session_modal_open = !self.session_modal_open;
-                self.started_session = true;
+        editor.move_caret_to(1, 4);
+        let _ = editor.update(Message::GoToMatchingBracket);
+        assert_eq!(editor.caret_position(), (1, 2));
 
-                let doc = self.document.clone();
-                // If a file path is provided, load the file
-                let file_path = self.modal_content.file_path_input.clone();
-                let load_file_task = if !file_path.is_empty() {
-                    Some(load_file(file_path.clone()))
-                } else {
-                    None
-                };
-                let is_dirty_lock = self.is_dirty.clone();
-                let read_password = if self.modal_content.read_password_input.is_empty() {
-                    self.read_password.clone()
-                } else {
-                    Some(self.modal_content.read_password_input.clone())
-                };
-                let edit_password = if self.modal_content.write_password_input.is_empty() {
-                    self.edit_password.clone()
-                } else {
-                    Some(self.modal_content.write_password_input.clone())
-                };
-                let users_lock = self.users.clone();
-                let is_moved_lock = self.is_moved.clone();
-                let server_thread_lock = self.server_thread.clone();
-                let server_worker = self.server_worker.clone().unwrap();
-                self.id = Some(1);
-                return Task::future(async move {
-                    if let Some(load_task) = load_file_task {
-                        match load_task.await {
-                            Ok((_, contents)) => {
-                                // Update the document with loaded file contents
-                                let mut doc_lock = doc.lock().await;
-                                doc_lock.buffer = contents.to_string();
-                                // return Message::UpdateHostDoc(doc_lock.clone());
-                            }
-                            Err(err) => {
-                                // Handle file load error (log or return an error message)
-                                eprintln!("Failed to load file: {:?}", err);
-                                return Message::NoOp;
-                            }
-                        }
-                    }
-                    let mut server_thread = server_thread_lock.lock().await;
-                    users_lock.lock().await.add_user(
-                        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 8080),
-                        None,
-                    );
-                    *server_thread = Some(
-                        start_server(
-                            read_password,
-                            edit_password,
-                            doc.clone(),
-                            is_dirty_lock,
-                            users_lock,
-                            is_moved_lock,
-                            server_worker,
-                        )
-                        .await,
-                    );
-                    let dock_to_update = doc.lock().await.clone(); // Clone document for the update message
-                    Message::UpdateHostDoc(dock_to_update)
-                });
-            }
-            Message::UpdateHostDoc(document) => {
-                // Update text editor content with the document content
-                self.replace_content(&document);
-            }
-            Message::UpdateHostCursors(cursors) => {
-                self.user_cursors = cursors;
-            }
-            Message::Echo(event) => match event {
-                client::Event::ServerDown => {
-                    self.joined_session = false;
-                    self.modal_content.session_join_error =
-                        "Server is down, please contact host.".to_string();
-                }
-                client::Event::IncorrectPassword => {
-                    self.joined_session = false;
-                    self.modal_content.session_join_error =
-                        "Incorrect password, please try again.".to_string();
-                }
-                client::Event::Connected(connection) => {
-                    self.client_state = State::Connected(connection.clone());
-                    self.joined_session = true;
-                    self.session_modal_open = false;
+        // Nothing to match leaves the caret where it was
+        editor.move_caret_to(1, 1);
+        let _ = editor.update(Message::GoToMatchingBracket);
+        assert_eq!(editor.caret_position(), (1, 1));
+    }
+
+    #[test]
+    fn matches_are_found_by_case_and_whole_word() {
+        let line = "Rust, rusty TRUST rust";
+        assert_eq!(
+            find_in_line(line, "rust", false, false),
+            vec![6..10, 18..22]
+        );
+        assert_eq!(
+            find_in_line(line, "rust", true, false),
+            vec![0..4, 6..10, 13..17, 18..22]
+        );
+        assert_eq!(find_in_line(line, "rust", true, true), vec![0..4, 18..22]);
+        // Ranges are in characters, and matches do not overlap
+        assert_eq!(
+            find_in_line("ééé aaa", "é", false, false),
+            vec![0..1, 1..2, 2..3]
+        );
+        assert_eq!(find_in_line("aaaa", "aa", false, false), vec![0..2, 2..4]);
+        assert_eq!(find_in_line("snake_case", "case", false, true), vec![]);
+        assert_eq!(find_in_line("anything", "", false, false), vec![]);
+    }
 
-                    let line = self.cursor_position_in_pixels();
+    #[tokio::test]
+    async fn replace_all_edits_the_shared_document() {
+        let mut editor = editor();
+        type_text(&mut editor, "cat Cat\ncatalog cat").await;
+        let _ = editor.update(Message::OpenFind(true));
+        let _ = editor.update(Message::FindWholeWordToggled(true));
+        let _ = editor.update(Message::FindIgnoreCaseToggled(true));
+        let _ = editor.update(Message::FindChanged("cat".to_string()));
+        assert_eq!(editor.find_matches.len(), 3);
 
-                    let cursor_data = serde_json::to_string(
-                        &json!({ "y": line, "color": self.cursor_marker.color }),
-                    )
-                    .expect("Failed to serialize cursor data");
-                    let message = format!("Cursor: {}", cursor_data);
-                    if self.leave_session {
-                        connection.clone().close();
-                    }
+        let _ = editor.update(Message::ReplaceChanged("dög".to_string()));
+        replay(&mut editor, Message::ReplaceAll).await;
+        assert_eq!(editor.buffer_text(), "dög dög\ncatalog dög");
+        assert!(editor
+            .document
+            .lock()
+            .await
+            .has_text("dög dög\ncatalog dög"));
+    }
 
-                    // Send the message
-                    connection.clone().send(client::Message::User(message));
-                }
-                client::Event::Disconnected => {
-                    self.client_state = State::Disconnected;
-                    println!("DISCONNECTED");
-                    self.user_cursors.clear();
-                }
-                client::Event::MessageReceived(message) => {
-                    // Extract the message as a string
-                    let message_text = message.as_str();
+    #[test]
+    fn unmatched_brackets_go_nowhere() {
+        assert_eq!(matching_bracket("(a[b)", 2), None);
+        // Other kinds of brackets between a pair do not stop it matching
+        assert_eq!(matching_bracket("(a[b)", 0), Some(4));
+        assert_eq!(matching_bracket("a]", 1), None);
+        assert_eq!(matching_bracket("((a)", 0), None);
+        assert_eq!(matching_bracket("no brackets", 3), None);
+        assert_eq!(matching_bracket("", 0), None);
+        assert_eq!(matching_bracket("()", 9), None);
+        // Not a character boundary
+        assert_eq!(matching_bracket("é()", 1), None);
+    }
 
-                    let parts: Vec<&str> = message_text.split(":").collect();
-                    let mut iter = parts.into_iter();
-                    match iter.next() {
-                        Some("Users") => {
-                            // Extract the part of the message that represents users data
-                            if let Some(users_start) = message_text.find("Users:") {
-                                let users_data = &message_text[users_start + 6..]; // Skip "Users:"
+    #[test]
+    fn the_local_users_own_cursor_is_not_drawn() {
+        use crate::server::User;
 
-                                // Attempt to parse the users data into a Users struct (you'll need to know how it's formatted)
-                                if let Ok(users) = serde_json::from_str::<Users>(users_data.trim())
-                                {
-                                    // Clone the Arc<Mutex<Users>> for async access
-                                    let users_lock = self.users.clone();
-                                    self.user_cursors = users.get_all_cursors();
-                                    // Update the mutex with the new users data
-                                    return Task::future(async move {
-                                        let mut locked_users = users_lock.lock().await;
-                                        *locked_users = users;
-                                        Message::NoOp
-                                    });
-                                } else {
-                                    println!("Failed to parse users data");
-                                }
-                            }
-                        }
-                        Some("Document") => {
-                            // Extract the part of the message that represents the document data
-                            if let Some(document_start) = message_text.find("Document:") {
-                                let document_data = &message_text[document_start + 9..]; // Skip "Document:"
+        let mut editor = editor();
+        editor.id = Some(2);
+        let user = |id, y| User {
+            id,
+            cursor: Some(CursorMarker::new(y, LINE_HEIGHT)),
+            name: None,
+            access: SessionType::Edit,
+        };
+        let participants = Participants {
+            users: vec![user(1, 0.0), user(2, 21.0), user(3, 42.0)],
+            host: None,
+        };
+        let received = ProtocolMessage::Users(participants).to_ws_text();
+        let _ = editor.update(Message::Echo(
+            editor.session_generation,
+            client::Event::MessageReceived(client::Message::User(received)),
+        ));
+        let shown: Vec<UserId> = editor.user_cursors.iter().map(|(id, _)| *id).collect();
+        assert_eq!(shown, vec![1, 3]);
 
-                                // Update the document content in the editor
-                                let server_doc =
-                                    serde_json::from_str::<Document>(document_data.trim()).unwrap();
+        // Nor when the host's editor is told about the cursors
+        let cursors = (1..=3)
+            .map(|id| (id, CursorMarker::new(0.0, LINE_HEIGHT)))
+            .collect();
+        let _ = editor.update(Message::UpdateHostCursors(cursors));
+        let shown: Vec<UserId> = editor.user_cursors.iter().map(|(id, _)| *id).collect();
+        assert_eq!(shown, vec![1, 3]);
+    }
 
-                                self.replace_content(&server_doc);
+    #[test]
+    fn only_a_url_over_a_one_line_selection_becomes_a_link() {
+        assert_eq!(
+            link_paste("the docs", " https://example.com/a?b=c\n").as_deref(),
+            Some("[the docs](https://example.com/a?b=c)")
+        );
+        assert_eq!(
+            link_paste("[x] done", "mailto:me@example.com").as_deref(),
+            Some("[\\[x\\] done](mailto:me@example.com)")
+        );
+        assert_eq!(link_paste("", "https://example.com"), None);
+        assert_eq!(link_paste("two\nlines", "https://example.com"), None);
+        assert_eq!(link_paste("word", "not a url"), None);
+        assert_eq!(link_paste("word", "file:///etc/passwd"), None);
+        assert_eq!(link_paste("word", "https://a.com https://b.com"), None);
+    }
 
-                                let doc_lock = self.document.clone();
-                                return Task::future(async move {
-                                    let mut doc = doc_lock.lock().await;
-                                    *doc = server_doc;
+    #[tokio::test]
+    async fn pasting_a_url_over_a_selection_links_it() {
+        let paste = |text: &str| {
+            Message::Action(text_editor::Action::Edit(text_editor::Edit::Paste(
+                Arc::new(text.to_string()),
+            )))
+        };
+        let mut editor = editor();
+        type_text(&mut editor, "see docs").await;
+        editor.content.perform(text_editor::Action::SelectWord);
+        replay(&mut editor, paste("https://example.com")).await;
+        assert_eq!(editor.buffer_text(), "see [docs](https://example.com)");
+        assert!(editor
+            .document
+            .lock()
+            .await
+            .has_text("see [docs](https://example.com)"));
 
-                                    Message::NoOp
-                                });
-                            }
-                        }
-                        Some("Id") => {
-                            // Extract the part of the message that represents the user's id
-                            if let Some(id_start) = message_text.find("Id:") {
-                                let id_data = &message_text[id_start + 3..]; // Skip "Id:"
-
-                                // Update the user's id
-                                let id = serde_json::from_str::<UserId>(id_data.trim()).unwrap();
-                                self.id = Some(id);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            },
-            Message::ReadPasswordChanged(password) => {
-                self.modal_content.read_password_input = password;
-            }
-            Message::WritePasswordChanged(password) => {
-                self.modal_content.write_password_input = password;
-            }
-            Message::SessionPasswordChanged(password) => {
-                self.modal_content.session_password_input = password;
-            }
-            Message::SessionTypeRequested(choice) => {
-                self.modal_content.session_selection = Some(choice);
-            }
-            Message::JoinSessionPressed => {
-                self.joined_session = true;
-            }
-            Message::SessionModalToggle => {
-                self.session_modal_open = !self.session_modal_open;
-            }
-            Message::TabSelected(selected) => {
-                self.active_tab = selected;
-            }
-            Message::FilePathChanged(file_path) => {
-                self.modal_content.file_path_input = file_path;
-                self.modal_content.validate_file();
-            }
-            Message::RequestClose => {
-                println!("Closing server...");
-                let server_thread_lock = self.server_thread.clone();
-                let users_lock = self.users.clone();
+        // Turned off, the URL replaces the selection as any paste does
+        editor.settings.link_paste = false;
+        editor.content.perform(text_editor::Action::SelectAll);
+        replay(&mut editor, paste("https://example.com")).await;
+        assert_eq!(editor.buffer_text(), "https://example.com");
+    }
 
-                return Task::future(async move {
-                    // Abort the server thread if it exists
-                    let server_thread_mutex = server_thread_lock.lock().await;
-                    if let Some(server_thread) = &*server_thread_mutex {
-                        server_thread.abort();
-                    }
+    #[test]
+    fn ticks_spread_the_documents_lines_along_the_strip() {
+        // First and last lines sit at either end, the rest in proportion between them
+        assert_eq!(tick_position(0, 101, 200.0), 0.0);
+        assert_eq!(tick_position(50, 101, 200.0), 100.0);
+        assert_eq!(tick_position(100, 101, 200.0), 200.0);
 
-                    // Clear all users
-                    let mut users = users_lock.lock().await;
-                    users.delete_all_users();
+        // Cursors past the end, from a document that has since shrunk, stay on the strip
+        assert_eq!(tick_position(400, 101, 200.0), 200.0);
 
-                    // Send the close window message
-                    Message::SessionClosed
-                });
-            }
-            Message::LeaveSession => {
-                let connection = if let State::Connected(ref mut connection) = self.client_state {
-                    Some(connection.clone())
-                } else {
-                    None
-                };
-                connection.unwrap().close();
-                self.joined_session = false;
-                self.client_state = State::Disconnected;
-                self.user_cursors.clear();
-                self.id = None;
-            }
-            Message::SessionClosed => {
-                println!("Server closed");
-                self.started_session = false;
-                self.id = None;
-            }
-            Message::CloseWindow(id) => {
-                println!("Window with id {:?} closed", id);
-                return window::close::<iced::window::Id>(id).map(|_| Message::NoOp);
-            }
-            Message::WorkerReady(sender) => {
-                self.server_worker = Some(sender);
-            }
-        }
-        Task::none()
+        // Tiny documents and strips do not divide by zero or go negative
+        assert_eq!(tick_position(0, 1, 200.0), 0.0);
+        assert_eq!(tick_position(0, 0, 200.0), 0.0);
+        assert_eq!(tick_position(1, 2, -3.0), 0.0);
     }
 
-    pub fn theme(&self) -> Theme {
-        self.theme.clone()
+    #[test]
+    fn a_cursor_markers_line_comes_from_its_height() {
+        let marker = |y: f32, height: f32| CursorMarker {
+            x: 0.0,
+            y,
+            color: (0.0, 0.0, 0.0),
+            height,
+            viewport: None,
+        };
+        assert_eq!(marker(0.0, 20.0).line(), 0);
+        assert_eq!(marker(140.0, 20.0).line(), 7);
+        assert_eq!(marker(139.0, 20.0).line(), 7);
+        assert_eq!(marker(140.0, 0.0).line(), 0);
     }
 
-    fn cursor_position_in_pixels(&self) -> f32 {
-        let (line, _) = self.content.cursor_position();
+    #[tokio::test]
+    async fn each_tab_keeps_its_own_document() {
+        let mut editor = editor();
+        type_text(&mut editor, "first").await;
+        run(editor.update(Message::Menu(MenuMessage::NewTab))).await;
+        assert_eq!((editor.tabs.len(), editor.current_tab), (2, 1));
+        assert_eq!(editor.buffer_text(), "");
+        assert!(!editor.unsaved_changes);
+
+        type_text(&mut editor, "second").await;
+        // Undoing everything here stops at this tab's own edits
+        for _ in 0..12 {
+            replay(&mut editor, Message::Undo).await;
+        }
+        assert_eq!(editor.buffer_text(), "");
+        type_text(&mut editor, "second").await;
 
-        // Assuming you know font metrics
-        let line_height = 21.0; // Adjust as per your font size
+        run(editor.update(Message::DocumentTabSelected(0))).await;
+        assert_eq!(editor.buffer_text(), "first");
+        assert!(editor.unsaved_changes);
+        // The shared document follows the active tab
+        assert!(editor.document.lock().await.has_text("first"));
+        replay(&mut editor, Message::Undo).await;
+        assert_eq!(editor.buffer_text(), "firs");
 
-        line as f32 * line_height
+        run(editor.update(Message::DocumentTabSelected(1))).await;
+        assert_eq!(editor.buffer_text(), "second");
+        assert!(editor.document.lock().await.has_text("second"));
     }
 
-    fn replace_content(&mut self, doc: &Document) {
-        if self.id == Some(doc.last_edit) {
-            return;
-        }
+    #[tokio::test]
+    async fn closing_a_tab_with_unsaved_changes_asks_first() {
+        let mut editor = editor();
+        type_text(&mut editor, "draft").await;
+        run(editor.update(Message::Menu(MenuMessage::NewTab))).await;
+        run(editor.update(Message::DocumentTabSelected(0))).await;
 
-        let (line, col) = self.content.cursor_position();
-        self.content = text_editor::Content::with_text(&doc.buffer);
+        run(editor.update(Message::CloseTab(0))).await;
+        assert_eq!(editor.close_tab_pending, Some(0));
+        run(editor.update(Message::CancelCloseTab)).await;
+        assert_eq!(
+            (editor.tabs.len(), editor.buffer_text()),
+            (2, "draft".into())
+        );
 
-        // Start at the beginning
-        self.content.perform(text_editor::Action::Move(
-            text_editor::Motion::DocumentStart,
-        ));
-        // Move to the right row
-        (0..line).for_each(|_| {
-            self.content
-                .perform(text_editor::Action::Move(text_editor::Motion::Down));
-        });
+        run(editor.update(Message::CloseTab(0))).await;
+        run(editor.update(Message::ConfirmCloseTab)).await;
+        assert_eq!((editor.tabs.len(), editor.current_tab), (1, 0));
+        assert_eq!(editor.buffer_text(), "");
 
-        // Scroll to the right col
-        (0..col).for_each(|_| {
-            self.content
-                .perform(text_editor::Action::Move(text_editor::Motion::Right));
-        });
+        // A tab without changes closes straight away, leaving an empty one behind
+        run(editor.update(Message::CloseTab(0))).await;
+        assert_eq!(editor.close_tab_pending, None);
+        assert_eq!(editor.tabs.len(), 1);
+    }
 
-        self.markdown_text = markdown::parse(&doc.buffer).collect();
+    #[tokio::test]
+    async fn closing_a_background_tab_keeps_the_active_one() {
+        let mut editor = editor();
+        type_text(&mut editor, "a").await;
+        run(editor.update(Message::Menu(MenuMessage::NewTab))).await;
+        type_text(&mut editor, "b").await;
+
+        run(editor.update(Message::CloseTab(0))).await;
+        run(editor.update(Message::ConfirmCloseTab)).await;
+        assert_eq!((editor.tabs.len(), editor.current_tab), (1, 0));
+        assert_eq!(editor.buffer_text(), "b");
     }
 
-    fn toggle_formatting(&mut self, format: TextStyle) -> Task<Message> {
-        let mut tasks = Vec::new();
-        // Get the current selection in the editor, if any, and wrap it in the formatting symbol
-        if let Some(selection) = self.content.selection() {
-            // Check if the selection is already formatted in which case we remove the formatting
-            let formatted_text = match format {
-                TextStyle::Bold => {
-                    if selection.starts_with("**") && selection.ends_with("**") {
-                        selection
-                            .strip_prefix("**")
-                            .unwrap()
-                            .strip_suffix("**")
-                            .unwrap()
-                            .to_string()
-                    } else {
-                        format!("**{}**", selection)
-                    }
-                }
-                TextStyle::Italic => {
-                    if (selection.starts_with("***") && selection.ends_with("***"))
-                        || (!(selection.starts_with("**") && selection.ends_with("**"))
-                            && selection.starts_with("*")
-                            && selection.ends_with("*"))
-                    {
-                        selection
-                            .strip_prefix("*")
-                            .unwrap()
-                            .strip_suffix("*")
-                            .unwrap()
-                            .to_string()
-                    } else {
-                        format!("*{}*", selection)
-                    }
-                }
-                TextStyle::Strikethrough => {
-                    if selection.starts_with("~~") && selection.ends_with("~~") {
-                        selection
-                            .strip_prefix("~~")
-                            .unwrap()
-                            .strip_suffix("~~")
-                            .unwrap()
-                            .to_string()
-                    } else {
-                        format!("~~{}~~", selection)
-                    }
-                }
-                _ => {
-                    return Task::done(Message::NoOp);
-                }
-            };
+    #[tokio::test]
+    async fn tabs_stay_put_during_a_session() {
+        let mut editor = editor();
+        run(editor.update(Message::Menu(MenuMessage::NewTab))).await;
+        editor.joined_session = true;
 
-            // tasks.push(Task::done(Message::Action(text_editor::Action::Edit(
-            //     text_editor::Edit::Delete,
-            // ))));
-            tasks.push(Task::done(Message::Action(text_editor::Action::Edit(
-                text_editor::Edit::Paste(formatted_text.into()),
-            ))));
+        run(editor.update(Message::Menu(MenuMessage::NewTab))).await;
+        run(editor.update(Message::DocumentTabSelected(0))).await;
+        run(editor.update(Message::CloseTab(1))).await;
+        assert_eq!((editor.tabs.len(), editor.current_tab), (2, 1));
+        assert!(editor.error_banner.is_some());
+
+        // Background tabs are not part of the session and can still be closed
+        run(editor.update(Message::CloseTab(0))).await;
+        assert_eq!((editor.tabs.len(), editor.current_tab), (1, 0));
+    }
+
+    #[test]
+    fn tab_labels_name_the_file_and_star_unsaved_changes() {
+        let file = PathBuf::from("notes").join("todo.md");
+        assert_eq!(DocumentTab::label(Some(&file), false), "todo.md");
+        assert_eq!(DocumentTab::label(Some(&file), true), "todo.md*");
+        assert_eq!(DocumentTab::label(None, false), "Untitled");
+    }
+
+    #[tokio::test]
+    async fn settings_are_saved_to_the_editors_config_folder() {
+        let mut editor = editor();
+        run(editor.update(Message::UndoDepthSelected(7))).await;
+
+        let saved = Settings::load(&editor.config_dir);
+        std::fs::remove_dir_all(&editor.config_dir).unwrap();
+        assert_eq!(saved.undo_depth, 7);
+    }
+
+    #[test]
+    fn the_gutter_numbers_only_the_lines_in_view() {
+        let mut editor = editor();
+        editor.content = text_editor::Content::with_text(&"line\n".repeat(10_000));
+        editor.window_height = editor.line_height * 20.0;
+        editor.scroll_offset = 5_000;
+        assert_eq!(editor.gutter_lines(), 5_000..5_021);
+
+        // Near the end it stops at the last line
+        editor.scroll_offset = 9_990;
+        assert_eq!(editor.gutter_lines(), 9_990..10_000);
+    }
+
+    /// Average time `update` and its task take over each of the actions
+    async fn time_actions(
+        editor: &mut Editor,
+        actions: impl IntoIterator<Item = text_editor::Action>,
+    ) -> Duration {
+        let mut count = 0;
+        let started = Instant::now();
+        for action in actions {
+            run(editor.update(Message::Action(action))).await;
+            count += 1;
         }
+        started.elapsed() / count.max(1)
+    }
 
-        tasks.push(Task::done(Message::Action(text_editor::Action::Move(
-            text_editor::Motion::WordLeft,
-        )))); // Move cursor to the right of the inserted text
-        Task::batch(tasks)
+    // Timings only mean something in a release build:
+    // `cargo test --release -- --ignored --nocapture editing_a_megabyte_line`
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn editing_a_megabyte_line() {
+        let mut editor = editor();
+        run(editor.load_file(PathBuf::from("long.md"), &"a".repeat(1_000_000))).await;
+        let _ = editor.update(Message::Action(text_editor::Action::Move(
+            text_editor::Motion::DocumentEnd,
+        )));
+
+        let typing = time_actions(
+            &mut editor,
+            (0..200).map(|_| text_editor::Action::Edit(text_editor::Edit::Insert('b'))),
+        )
+        .await;
+        let moving = time_actions(
+            &mut editor,
+            (0..200).map(|_| text_editor::Action::Move(text_editor::Motion::Left)),
+        )
+        .await;
+        println!(
+            "1 MB line: {:?} per insert, {:?} per caret move",
+            typing, moving
+        );
+        assert!(editor.buffer_text().ends_with(&"b".repeat(200)));
     }
-}
 
-fn modal<'a, Message>(
-    base: impl Into<Element<'a, Message>>,
-    content: impl Into<Element<'a, Message>>,
-    on_blur: Message,
-) -> Element<'a, Message>
-where
-    Message: Clone + 'a,
-{
-    stack![
-        base.into(),
-        opaque(
-            mouse_area(center(opaque(content)).style(|_theme| {
-                container::Style {
-                    background: Some(
-                        Color {
-                            a: 0.8,
-                            ..Color::BLACK
-                        }
-                        .into(),
-                    ),
-                    ..container::Style::default()
-                }
-            }))
-            .on_press(on_blur)
+    // `cargo test --release -- --ignored --nocapture scrolling_and_editing_with_the_preview_open`
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn scrolling_and_editing_with_the_preview_open() {
+        let mut editor = editor();
+        let paragraph =
+            "## Heading\n\nSome *emphasis*, a [link](https://example.com) and `code`.\n\n";
+        run(editor.load_file(PathBuf::from("large.md"), &paragraph.repeat(20_000))).await;
+        editor.markdown_preview_open = true;
+        settle(&mut editor);
+        // The gutter keeps the scroll offset above the caret
+        let _ = editor.update(Message::Action(text_editor::Action::Move(
+            text_editor::Motion::DocumentEnd,
+        )));
+
+        let scrolling = time_actions(
+            &mut editor,
+            (0..200).map(|_| text_editor::Action::Scroll { lines: 3 }),
         )
-    ]
-    .into()
-}
+        .await;
+        assert_eq!(editor.scroll_offset, 600);
+        let typing = time_actions(
+            &mut editor,
+            (0..200).map(|_| text_editor::Action::Edit(text_editor::Edit::Insert('x'))),
+        )
+        .await;
+        let started = Instant::now();
+        settle(&mut editor);
+        let refresh = started.elapsed();
+        println!(
+            "{} lines with the preview open: {:?} per scroll, {:?} per insert, {:?} per preview refresh",
+            editor.content.line_count(),
+            scrolling,
+            typing,
+            refresh
+        );
+    }
 
-pub enum Input {
-    Cursors(Vec<CursorMarker>),
-    Edit(Document),
-}
+    #[test]
+    fn goal_progress_is_the_share_of_the_goal_reached() {
+        assert_eq!(goal_progress(0, 1000), 0.0);
+        assert_eq!(goal_progress(500, 1000), 0.5);
+        assert_eq!(goal_progress(1000, 1000), 1.0);
+        assert_eq!(goal_progress(1500, 1000), 1.0);
+        assert_eq!(goal_progress(10, 0), 0.0);
+    }
 
-fn server_worker() -> impl Stream<Item = Message> {
-    stream::channel(100, |mut output| async move {
-        // Create channel
-        let (sender, mut receiver) = mpsc::channel(100);
+    #[test]
+    fn selected_lines_are_sorted() {
+        let selection = "apple\nbanana\nApple\nCherry\n";
+        assert_eq!(
+            sort_lines(selection, false, true),
+            "Apple\nCherry\napple\nbanana\n"
+        );
+        assert_eq!(
+            sort_lines(selection, true, true),
+            "banana\napple\nCherry\nApple\n"
+        );
+        assert_eq!(
+            sort_lines(selection, false, false),
+            "apple\nApple\nbanana\nCherry\n"
+        );
+        assert_eq!(sort_lines("b\na", false, true), "a\nb");
+    }
 
-        // Send the sender back to the application
-        output.send(Message::WorkerReady(sender)).await.unwrap();
+    #[test]
+    fn duplicate_lines_are_removed() {
+        let selection = "a\na\nb\na\nb\nb\n";
+        assert_eq!(unique_lines(selection, false), "a\nb\na\nb\n");
+        assert_eq!(unique_lines(selection, true), "a\nb\n");
+        assert_eq!(unique_lines("x\nx", false), "x");
+    }
 
-        loop {
-            use iced_futures::futures::StreamExt;
+    #[tokio::test]
+    async fn starting_a_session_summarizes_what_is_shared_first() {
+        let mut editor = editor();
+        let (server_worker, _host_inputs) = mpsc::channel(1);
+        editor.server_worker = Some(server_worker);
+        editor.modal_content.host_input = "127.0.0.1".into();
+        editor.modal_content.port_input = "9000".into();
+        editor.modal_content.write_password_input = "secret".into();
 
-            // Read next input sent from `Application`
-            let input = receiver.select_next_some().await;
+        let messages = run(editor.update(Message::StartSessionPressed)).await;
+        assert!(messages.is_empty());
+        assert!(editor.start_confirm_open);
+        assert_eq!(
+            editor.session_share_summary(),
+            "You're about to share the current document at 127.0.0.1:9000.\n\
+             Read access: anyone who can reach this machine\n\
+             Write access: password protected"
+        );
 
-            match input {
-                Input::Cursors(cursors) => output
-                    .send(Message::UpdateHostCursors(cursors))
-                    .await
-                    .unwrap(),
-                Input::Edit(document) => {
-                    output.send(Message::UpdateHostDoc(document)).await.unwrap()
-                }
-            }
-        }
-    })
+        let _ = editor.update(Message::CancelStartSession);
+        assert!(!editor.start_confirm_open);
+
+        editor.settings.confirm_session_start = false;
+        let messages = run(editor.update(Message::StartSessionPressed)).await;
+        assert!(matches!(messages[..], [Message::ConfirmStartSession]));
+        assert!(!editor.start_confirm_open);
+    }
+
+    /// Checks an editor showing `shown` against a shared copy holding `shared`, once edits
+    /// have settled
+    async fn check_consistency(editor: &mut Editor, shown: &str, shared: Document) -> Vec<Message> {
+        editor.set_content(text_editor::Content::with_text(shown));
+        *editor.document.lock().await = shared;
+        editor.last_document_change = Instant::now()
+            .checked_sub(CONSISTENCY_CHECK_INTERVAL)
+            .unwrap();
+        run(editor.update(Message::CheckConsistency)).await
+    }
+
+    #[tokio::test]
+    async fn a_diverged_document_is_repaired_from_the_editor() {
+        let mut host = editor();
+        host.started_session = true;
+        host.id = Some(1);
+        let mut shared = Document::new("stale".to_string());
+        shared.last_edit = 1;
+        check_consistency(&mut host, "fresh", shared).await;
+        assert!(host.document.lock().await.has_text("fresh"));
+        assert!(*host.is_dirty.lock().await);
+
+        // A remote edit the host has not shown yet is not a divergence
+        let shared = remote_document(&host, "remote");
+        check_consistency(&mut host, "fresh", shared).await;
+        assert!(host.document.lock().await.has_text("remote"));
+
+        let mut client = editor();
+        client.joined_session = true;
+        client.id = Some(2);
+        let messages = check_consistency(&mut client, "fresh", Document::new("stale".into())).await;
+        assert!(matches!(messages[..], [Message::ResyncDocument]));
+        assert!(client.document.lock().await.has_text("fresh"));
+    }
+
+    #[test]
+    fn high_contrast_brightens_cursors_and_replaces_the_theme() {
+        let marker = CursorMarker {
+            color: (0.2, 0.1, 0.0),
+            ..CursorMarker::new(0.0, LINE_HEIGHT)
+        };
+        assert_eq!(marker.brightened().color, (1.0, 0.5, 0.0));
+        let black = CursorMarker {
+            color: (0.0, 0.0, 0.0),
+            ..marker
+        };
+        assert_eq!(black.brightened().color, (1.0, 1.0, 1.0));
+
+        let mut editor = editor();
+        let _ = editor.update(Message::HighContrastToggled(true));
+        let palette = editor.theme().palette();
+        assert_eq!(palette.background, Color::BLACK);
+        assert_eq!(palette.text, Color::WHITE);
+
+        let _ = editor.update(Message::UiScaleSelected(150));
+        assert_eq!(editor.scale_factor(), 1.5);
+    }
+
+    #[test]
+    fn reopening_the_open_file_asks_only_over_unsaved_changes() {
+        let mut editor = editor();
+        let path = PathBuf::from("note.md");
+        editor.file = Some(path.clone());
+        editor.set_content(text_editor::Content::with_text("edited"));
+        let reopened = || Ok((path.clone(), Arc::new("on disk".to_string())));
+
+        let _ = editor.update(Message::Menu(MenuMessage::FileOpened(reopened())));
+        assert!(editor.reopen_pending.is_none());
+        assert_eq!(editor.content.text().trim_end(), "edited");
+
+        editor.unsaved_changes = true;
+        let _ = editor.update(Message::Menu(MenuMessage::FileOpened(reopened())));
+        assert!(editor.reopen_pending.is_some());
+        assert_eq!(editor.content.text().trim_end(), "edited");
+
+        let _ = editor.update(Message::CancelReopen);
+        assert!(editor.reopen_pending.is_none());
+        assert!(editor.unsaved_changes);
+
+        let _ = editor.update(Message::Menu(MenuMessage::FileOpened(reopened())));
+        let _ = editor.update(Message::ConfirmReopen);
+        assert_eq!(editor.content.text().trim_end(), "on disk");
+        assert!(!editor.unsaved_changes);
+    }
+
+    #[test]
+    fn the_host_is_marked_among_the_participants() {
+        use crate::server::User;
+
+        let mut editor = editor();
+        editor.id = Some(2);
+        let user = |id, access| User {
+            id,
+            cursor: None,
+            name: None,
+            access,
+        };
+        let participants = Participants {
+            users: vec![
+                user(1, SessionType::Edit),
+                user(2, SessionType::Edit),
+                user(3, SessionType::Read),
+            ],
+            host: Some(1),
+        };
+
+        let _ = editor.update(Message::UpdateHostParticipants(participants));
+        assert_eq!(editor.host_participant, Some(1));
+        assert_eq!(editor.participant_list(), "✏ 1 (host), ✏ 2 (you), 👁 3");
+    }
 }
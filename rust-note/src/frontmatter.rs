@@ -0,0 +1,133 @@
+/// Fields read from a note's YAML front-matter block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Splits a leading `---` delimited front-matter block off the text, returning its metadata and
+/// the remaining body. Text without a complete block is returned untouched.
+pub fn parse(text: &str) -> (Option<Metadata>, &str) {
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (None, text);
+    };
+
+    // The block ends at the first line that is exactly `---`
+    let mut offset = 0;
+    let mut end = None;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end_matches(['\r', '\n']) == "---" {
+            end = Some((offset, offset + line.len()));
+            break;
+        }
+        offset += line.len();
+    }
+
+    let Some((block_end, body_start)) = end else {
+        return (None, text);
+    };
+
+    let mut metadata = Metadata::default();
+    let mut in_tag_list = false;
+
+    for line in rest[..block_end].lines() {
+        if in_tag_list {
+            if let Some(tag) = line.trim_start().strip_prefix("- ") {
+                push_tag(&mut metadata.tags, tag);
+                continue;
+            }
+            in_tag_list = false;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key.trim() {
+            "title" => metadata.title = Some(unquote(value).to_string()).filter(|t| !t.is_empty()),
+            "tags" if value.is_empty() => in_tag_list = true,
+            "tags" => {
+                let value = value
+                    .strip_prefix('[')
+                    .and_then(|value| value.strip_suffix(']'))
+                    .unwrap_or(value);
+                value
+                    .split(',')
+                    .for_each(|tag| push_tag(&mut metadata.tags, tag));
+            }
+            _ => {}
+        }
+    }
+
+    (Some(metadata), &rest[body_start..])
+}
+
+fn push_tag(tags: &mut Vec<String>, tag: &str) {
+    let tag = unquote(tag.trim());
+    if !tag.is_empty() {
+        tags.push(tag.to_string());
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| {
+            value
+                .strip_prefix(*quote)
+                .and_then(|value| value.strip_suffix(*quote))
+        })
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_front_matter_is_parsed_and_split_off() {
+        let text = "---\ntitle: \"Weekly notes\"\ntags: [work, 'planning']\n---\n# Monday\n";
+        let (metadata, body) = parse(text);
+        assert_eq!(
+            metadata,
+            Some(Metadata {
+                title: Some("Weekly notes".to_string()),
+                tags: vec!["work".to_string(), "planning".to_string()],
+            })
+        );
+        assert_eq!(body, "# Monday\n");
+    }
+
+    #[test]
+    fn tags_may_be_listed_one_per_line() {
+        let text = "---\r\ntags:\r\n  - rust\r\n  - notes\r\ntitle: List\r\n---\r\nBody";
+        let (metadata, body) = parse(text);
+        let metadata = metadata.unwrap();
+        assert_eq!(metadata.tags, ["rust", "notes"]);
+        assert_eq!(metadata.title.as_deref(), Some("List"));
+        assert_eq!(body, "Body");
+    }
+
+    #[test]
+    fn text_without_front_matter_is_untouched() {
+        let text = "# Title\n---\nNot metadata\n---\n";
+        assert_eq!(parse(text), (None, text));
+        assert_eq!(parse(""), (None, ""));
+    }
+
+    #[test]
+    fn malformed_front_matter_is_left_in_the_text() {
+        // Never closed
+        let text = "---\ntitle: Draft\n# Heading\n";
+        assert_eq!(parse(text), (None, text));
+
+        // Lines that are not `key: value` are skipped rather than failing the block
+        let (metadata, body) = parse("---\njust words\ntitle:\n---\nBody");
+        assert_eq!(metadata, Some(Metadata::default()));
+        assert_eq!(body, "Body");
+    }
+}
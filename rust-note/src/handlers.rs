@@ -1,6 +1,8 @@
 use crate::{
-    editor::CursorMarker,
-    server::{AppState, Deletion, Insertion},
+    editor::{Input, SessionType},
+    privacy::DisplayAddr,
+    protocol::ProtocolMessage,
+    server::{AppState, EditRate, Operation, Room, DEFAULT_ROOM},
 };
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use axum::{
@@ -18,7 +20,7 @@ use futures::{
     sink::SinkExt,
     stream::{SplitSink, SplitStream, StreamExt},
 };
-use std::{borrow::Cow, net::SocketAddr};
+use std::{borrow::Cow, net::SocketAddr, sync::atomic::Ordering};
 use tokio::sync::broadcast::Receiver;
 
 pub async fn auth(
@@ -26,15 +28,16 @@ pub async fn auth(
     req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    let parsed_hash = match req.uri().path() {
-        "/read" => {
+    let parsed_hash = match ws_route(req.uri().path()).map(|(access, _)| access) {
+        // Commenting is reading with notes on the side, so it takes the read password
+        Some("read") | Some("comment") => {
             if state.read_access_hash.is_none() {
                 return Ok(next.run(req).await);
             }
 
             PasswordHash::new(state.read_access_hash.as_ref().unwrap()).unwrap()
         }
-        "/edit" => {
+        Some("edit") => {
             if state.write_access_hash.is_none() {
                 return Ok(next.run(req).await);
             }
@@ -65,6 +68,26 @@ pub async fn auth(
     }
 }
 
+pub async fn metrics_handler(state: State<AppState>) -> String {
+    let rooms: Vec<Room> = state.rooms.lock().await.values().cloned().collect();
+    let mut current_users = 0;
+    for room in &rooms {
+        current_users += room.users.lock().await.len();
+    }
+    state.metrics.render(current_users, rooms.len())
+}
+
+/// Splits a websocket path such as `/edit` or `/edit/notes` into its access level and room
+fn ws_route(path: &str) -> Option<(&str, &str)> {
+    let mut segments = path.trim_start_matches('/').splitn(2, '/');
+    let access = segments
+        .next()
+        .filter(|access| ["read", "comment", "edit"].contains(access))?;
+    let room = segments.next().unwrap_or(DEFAULT_ROOM);
+
+    Room::is_valid_name(room).then_some((access, room))
+}
+
 pub async fn ws_handler(
     state: State<AppState>,
     ws: WebSocketUpgrade,
@@ -77,11 +100,25 @@ pub async fn ws_handler(
     } else {
         String::from("Unknown browser")
     };
-    println!("`{user_agent}` at {addr} connected.");
+    println!("`{user_agent}` at {} connected.", DisplayAddr(addr));
+    state
+        .metrics
+        .total_connections
+        .fetch_add(1, Ordering::Relaxed);
 
-    match req.uri().path() {
-        "/read" => ws.on_upgrade(move |socket| handle_read_socket(socket, addr, state)),
-        "/edit" => ws.on_upgrade(move |socket| handle_edit_socket(socket, addr, state)),
+    let route = ws_route(req.uri().path());
+    let room = route.map(|(_, room)| room.to_string());
+
+    match route.map(|(access, _)| access).zip(room) {
+        Some(("read", room)) => {
+            ws.on_upgrade(move |socket| handle_read_socket(socket, addr, state, room))
+        }
+        Some(("comment", room)) => ws.on_upgrade(move |socket| {
+            handle_edit_socket(socket, addr, state, room, Access::Comment)
+        }),
+        Some(("edit", room)) => {
+            ws.on_upgrade(move |socket| handle_edit_socket(socket, addr, state, room, Access::Edit))
+        }
         _ => {
             let res = Response::new(Body::empty());
             let (mut parts, body) = res.into_parts();
@@ -92,36 +129,80 @@ pub async fn ws_handler(
     }
 }
 
-async fn handle_read_socket(socket: WebSocket, who: SocketAddr, State(state): State<AppState>) {
-    let (sender, _) = socket.split();
+/// What a client connected to an edit socket may send
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    /// Cursors and comments, but no changes to the text
+    Comment,
+    Edit,
+}
 
-    let rx = state.tx.subscribe();
+async fn handle_read_socket(
+    socket: WebSocket,
+    who: SocketAddr,
+    State(state): State<AppState>,
+    room: String,
+) {
+    let peer = DisplayAddr(who);
+    let (sender, mut receiver) = socket.split();
+
+    let room = state.join_room(&room, who, SessionType::Read).await;
+    let rx = room.tx.subscribe();
 
     // Broadcast the content of the document to all clients
-    let mut send_task = tokio::spawn(broadcast(sender, rx, who, state.clone()));
+    let mut send_task = tokio::spawn(broadcast(sender, rx, who, state.clone(), room.clone()));
+
+    // Readers send nothing of their own, but reading on tells when they leave
+    let mut recv_task = tokio::spawn(async move {
+        let mut n_msg = 0;
+        while let Some(Ok(msg)) = receiver.next().await {
+            n_msg += 1;
+            if let Message::Close(_) = msg {
+                break;
+            }
+        }
+        n_msg
+    });
 
     // If any one of the tasks exit, abort the other.
     tokio::select! {
         rv_a = (&mut send_task) => {
             match rv_a {
-                Ok(a) => println!("{a} messages sent to {who}"),
+                Ok(a) => println!("{a} messages sent to {peer}"),
                 Err(a) => println!("Error sending messages {a:?}")
             }
+            recv_task.abort();
         },
+        rv_b = (&mut recv_task) => {
+            match rv_b {
+                Ok(b) => println!("Received {b} messages"),
+                Err(b) => println!("Error receiving messages {b:?}")
+            }
+            send_task.abort();
+        }
     }
 
-    println!("Websocket context {who} destroyed");
+    println!("Websocket context {peer} destroyed");
+    state.leave_room(&room, who).await;
+
+    if room.is_hosted() {
+        let cursors = room.users.lock().await.get_all_cursors();
+        state.notify_host(Input::Cursors(cursors)).await;
+    }
 }
 
 async fn handle_edit_socket(
     mut socket: WebSocket,
     who: SocketAddr,
-    State(mut state): State<AppState>,
+    State(state): State<AppState>,
+    room: String,
+    access: Access,
 ) {
+    let peer = DisplayAddr(who);
     if socket.send(Message::Ping(vec![1, 2, 3])).await.is_ok() {
-        println!("Pinged {who}...");
+        println!("Pinged {peer}...");
     } else {
-        println!("Could not send ping {who}!");
+        println!("Could not send ping {peer}!");
         return;
     }
 
@@ -129,33 +210,44 @@ async fn handle_edit_socket(
         if let Ok(msg) = msg {
             match msg {
                 Message::Pong(v) => {
-                    println!(">>> {who} sent pong with {v:?}");
+                    println!(">>> {peer} sent pong with {v:?}");
                 }
                 _ => {
-                    println!("client {who} did not pong my ping");
+                    println!("client {peer} did not pong my ping");
                     return;
                 }
             }
         } else {
-            println!("client {who} abruptly disconnected");
+            println!("client {peer} abruptly disconnected");
             return;
         }
     }
 
     let (sender, receiver) = socket.split();
 
-    let rx = state.tx.subscribe();
+    let session_type = match access {
+        Access::Comment => SessionType::Comment,
+        Access::Edit => SessionType::Edit,
+    };
+    let room = state.join_room(&room, who, session_type).await;
+    let rx = room.tx.subscribe();
 
     // Broadcast the content of the document to client
-    let mut send_task = tokio::spawn(broadcast(sender, rx, who, state.clone()));
+    let mut send_task = tokio::spawn(broadcast(sender, rx, who, state.clone(), room.clone()));
 
     // This second task will receive messages from client
-    let mut recv_task = tokio::spawn(process_message(receiver, who, state.clone()));
+    let mut recv_task = tokio::spawn(process_message(
+        receiver,
+        who,
+        state.clone(),
+        room.clone(),
+        access,
+    ));
 
     tokio::select! {
         rv_a = (&mut send_task) => {
             match rv_a {
-                Ok(a) => println!("{a} messages sent to {who}"),
+                Ok(a) => println!("{a} messages sent to {peer}"),
                 Err(a) => println!("Error sending messages {a:?}")
             }
         },
@@ -167,18 +259,13 @@ async fn handle_edit_socket(
         }
     }
 
-    println!("Websocket context {who} destroyed");
-    // Remove user from the list of users
-    let mut users = state.users.lock().await;
-    users.remove_user(who);
-    *state.is_moved.lock().await = true;
+    println!("Websocket context {peer} destroyed");
+    state.leave_room(&room, who).await;
 
-    let cursors = users.get_all_cursors();
-    state
-        .server_worker
-        .send(crate::editor::Input::Cursors(cursors))
-        .await
-        .unwrap();
+    if room.is_hosted() {
+        let cursors = room.users.lock().await.get_all_cursors();
+        state.notify_host(Input::Cursors(cursors)).await;
+    }
 }
 
 async fn broadcast(
@@ -186,54 +273,83 @@ async fn broadcast(
     mut rx: Receiver<String>,
     who: SocketAddr,
     state: AppState,
+    room: Room,
 ) -> i32 {
+    let peer = DisplayAddr(who);
     let mut n_msg = 0;
 
     // Send the document, cursors, and the client's id to the client that just connected
     // This is the first message that the client will receive
     {
-        let doc = state.document.lock().await;
-        let mut users = state.users.lock().await;
+        let doc = room.document.lock().await;
+        let mut users = room.users.lock().await;
         // Get the id of the user, if it does not exist, add it
         let id = users
             .get_id(who)
-            .unwrap_or_else(|| users.add_user(who, None)) as u64;
+            .unwrap_or_else(|| users.add_user(who, None));
+
+        let document = ProtocolMessage::Document(doc.clone());
+        if sender
+            .send(Message::Text(document.to_ws_text()))
+            .await
+            .is_err()
+        {
+            return n_msg;
+        }
 
-        let doc_json = serde_json::to_string(&*doc).unwrap();
         if sender
-            .send(Message::Text(format!("Document: {}", doc_json)))
+            .send(Message::Text(ProtocolMessage::Id(id).to_ws_text()))
             .await
             .is_err()
         {
             return n_msg;
         }
 
+        let limits = ProtocolMessage::Limits(state.limits);
         if sender
-            .send(Message::Text(format!("Id: {}", id)))
+            .send(Message::Text(limits.to_ws_text()))
             .await
             .is_err()
         {
             return n_msg;
         }
 
-        let users_json = serde_json::to_string(&*users).unwrap();
+        let participants = ProtocolMessage::Users(users.participants(state.host_in(&room)));
         if sender
-            .send(Message::Text(format!("Users: {}", users_json)))
+            .send(Message::Text(participants.to_ws_text()))
             .await
             .is_err()
         {
             return n_msg;
         }
 
-        println!("New client connected, document, id and cursors sent to {who}");
-        n_msg += 3;
+        let is_locked = *state.is_locked.lock().await;
+        if sender
+            .send(Message::Text(
+                ProtocolMessage::Locked(is_locked).to_ws_text(),
+            ))
+            .await
+            .is_err()
+        {
+            return n_msg;
+        }
+
+        println!(
+            "New client connected, document, id, limits, cursors and lock state sent to {peer}"
+        );
+        n_msg += 5;
     }
 
     // Forward the broadcasts to the client
     while let Ok(msg) = rx.recv().await {
+        let len = msg.len() as u64;
         if sender.send(Message::Text(msg)).await.is_err() {
             break;
         }
+        state
+            .metrics
+            .bytes_broadcast
+            .fetch_add(len, Ordering::Relaxed);
         n_msg += 1;
     }
 
@@ -253,92 +369,812 @@ async fn broadcast(
 async fn process_message(
     mut receiver: SplitStream<WebSocket>,
     who: SocketAddr,
-    mut state: AppState,
+    state: AppState,
+    room: Room,
+    access: Access,
 ) -> i32 {
+    let peer = DisplayAddr(who);
     let mut n_msg = 0;
+    let mut edit_rate = EditRate::new(state.limits.edits_per_second);
     while let Some(Ok(msg)) = receiver.next().await {
         n_msg += 1;
 
         match msg {
             Message::Text(t) => {
-                println!(">>> {who} sent str: {t:?}");
-                let parts: Vec<&str> = t.split(":").collect();
-                let mut iter = parts.into_iter();
-                match iter.next() {
-                    Some("Insert") => {
-                        let s = iter.collect::<Vec<&str>>().join(":");
-                        match serde_json::from_str::<Insertion>(s.trim()) {
-                            Ok(insertion) => {
-                                if let Some(id) = state.users.lock().await.get_id(who) {
-                                    let mut doc = state.document.lock().await;
-                                    doc.last_edit = id;
-                                    doc.insert(insertion.insert_at, insertion.clone().text);
-
-                                    *state.is_dirty.lock().await = true;
-                                }
+                println!(">>> {peer} sent str: {t:?}");
+                let message = match ProtocolMessage::from_ws_text(&t) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        println!("Error parsing message from {peer}: {e}");
+                        continue;
+                    }
+                };
+                if message.is_edit() && edit_rate.record() {
+                    println!("{peer} is editing faster than the server accepts");
+                    if let Some(id) = room.users.lock().await.get_id(who) {
+                        let _ = room.tx.send(ProtocolMessage::Throttled(id).to_ws_text());
+                    }
+                }
+                match message {
+                    ProtocolMessage::Insert(_) | ProtocolMessage::Delete(_)
+                        if access == Access::Comment =>
+                    {
+                        println!("Ignoring edit from {peer}, who may only comment");
+                    }
+                    ProtocolMessage::Insert(_) | ProtocolMessage::Delete(_)
+                        if *state.is_locked.lock().await =>
+                    {
+                        println!("Ignoring edit from {peer}, the session is locked");
+                    }
+                    ProtocolMessage::Insert(insertion) => {
+                        if let Some(id) = room.users.lock().await.get_id(who) {
+                            let mut doc = room.document.lock().await;
+                            let max_bytes = state.limits.max_document_bytes;
+                            if doc.byte_len() + insertion.text.len() > max_bytes {
+                                println!(
+                                    "Refusing insert from {peer}, the document would grow past {max_bytes} bytes"
+                                );
+                                let _ = room.tx.send(ProtocolMessage::Rejected(id).to_ws_text());
+                                continue;
+                            }
+                            let insert_at = insertion.insert_at;
+                            if doc
+                                .apply_concurrent(id, Operation::Insert(insertion))
+                                .is_empty()
+                            {
+                                println!(
+                                    "Ignoring insert at {insert_at} from {peer}, outside the document of {} bytes",
+                                    doc.byte_len()
+                                );
+                                continue;
                             }
-                            Err(e) => println!("Error parsing insert: {e}"),
+                            state
+                                .metrics
+                                .operations_applied
+                                .fetch_add(1, Ordering::Relaxed);
+
+                            *room.is_dirty.lock().await = true;
                         }
                     }
-                    Some("Delete") => {
-                        let s = iter.collect::<Vec<&str>>().join(":");
-                        match serde_json::from_str::<Deletion>(s.trim()) {
-                            Ok(deletion) => {
-                                if let Some(id) = state.users.lock().await.get_id(who) {
-                                    let mut doc = state.document.lock().await;
-                                    doc.last_edit = id;
-                                    doc.delete(deletion.clone().range);
-
-                                    *state.is_dirty.lock().await = true;
-                                }
+                    ProtocolMessage::Delete(deletion) => {
+                        if let Some(id) = room.users.lock().await.get_id(who) {
+                            let mut doc = room.document.lock().await;
+                            let range = deletion.range.clone();
+                            if doc
+                                .apply_concurrent(id, Operation::Delete(deletion))
+                                .is_empty()
+                            {
+                                println!(
+                                    "Ignoring delete of {:?} from {peer}, already deleted or outside the document of {} bytes",
+                                    range,
+                                    doc.byte_len()
+                                );
+                                continue;
                             }
-                            Err(e) => println!("Error parsing delete: {e}"),
+                            state
+                                .metrics
+                                .operations_applied
+                                .fetch_add(1, Ordering::Relaxed);
+
+                            *room.is_dirty.lock().await = true;
                         }
                     }
-                    Some("Cursor") => {
-                        let s = iter.collect::<Vec<&str>>().join(":");
-                        match serde_json::from_str::<CursorMarker>(s.trim()) {
-                            Ok(cursor) => {
-                                let mut users = state.users.lock().await;
-                                users.add_user(who, Some(cursor));
-                                *state.is_moved.lock().await = true;
-
-                                let cursors = users.get_all_cursors();
-                                state
-                                    .server_worker
-                                    .send(crate::editor::Input::Cursors(cursors))
-                                    .await
-                                    .unwrap();
-                            }
-                            Err(e) => println!("Error parsing cursor: {e}"),
+                    ProtocolMessage::Cursor(cursor) => {
+                        let mut users = room.users.lock().await;
+                        users.add_user(who, Some(cursor));
+                        *room.is_moved.lock().await = true;
+
+                        if room.is_hosted() {
+                            let cursors = users.get_all_cursors();
+                            state.notify_host(Input::Cursors(cursors)).await;
                         }
                     }
-                    _ => {}
+                    ProtocolMessage::Name(name) => {
+                        let mut users = room.users.lock().await;
+                        users.set_name(who, &name);
+                        *room.is_moved.lock().await = true;
+
+                        if room.is_hosted() {
+                            state.notify_host(Input::Names(users.names())).await;
+                        }
+                    }
+                    // Comments leave the document alone, so they are passed on to the room as sent
+                    ProtocolMessage::Comment(_) => {
+                        let _ = room.tx.send(t.to_string());
+                    }
+                    // The rest only ever come from the host
+                    _ => println!("Ignoring a host message sent by {peer}"),
                 }
             }
             Message::Binary(d) => {
-                println!(">>> {} sent {} bytes: {:?}", who, d.len(), d);
+                println!(">>> {} sent {} bytes: {:?}", peer, d.len(), d);
             }
             Message::Close(c) => {
                 if let Some(cf) = c {
                     println!(
                         ">>> {} sent close with code {} and reason `{}`",
-                        who, cf.code, cf.reason
+                        peer, cf.code, cf.reason
                     );
                 } else {
-                    println!(">>> {who} somehow sent close message without CloseFrame");
+                    println!(">>> {peer} somehow sent close message without CloseFrame");
                 }
                 break;
             }
 
             Message::Pong(v) => {
-                println!(">>> {who} sent pong with {v:?}");
+                println!(">>> {peer} sent pong with {v:?}");
             }
             Message::Ping(v) => {
-                println!(">>> {who} sent ping with {v:?}");
+                println!(">>> {peer} sent ping with {v:?}");
             }
         }
     }
 
     n_msg
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        tests::{free_address, host, host_on, host_with_passwords, TestHost},
+        Deletion, Insertion, SessionLimits, UserId,
+    };
+    use async_tungstenite::{
+        tokio::{connect_async, ConnectStream},
+        tungstenite, WebSocketStream,
+    };
+    use std::time::Duration;
+
+    type Client = WebSocketStream<ConnectStream>;
+
+    /// Joins through `path`, e.g. `edit` or `read/notes`, once the server has said who this is
+    async fn join(host: &TestHost, path: &str) -> (Client, UserId) {
+        let (mut client, _) = connect_async(format!("ws://{}/{}", host.address, path))
+            .await
+            .unwrap();
+        let id = match receive_until(&mut client, |message| {
+            matches!(message, ProtocolMessage::Id(_))
+        })
+        .await
+        {
+            Some(ProtocolMessage::Id(id)) => id,
+            other => panic!("expected an id, got {:?}", other),
+        };
+        (client, id)
+    }
+
+    /// Connects through `path` the way the client does, with the password in the header
+    async fn connect_with_password(
+        host: &TestHost,
+        path: &str,
+        password: &str,
+    ) -> Result<Client, tungstenite::Error> {
+        use tungstenite::client::IntoClientRequest;
+
+        let mut request = format!("ws://{}/{}", host.address, path).into_client_request()?;
+        request
+            .headers_mut()
+            .insert(http::header::AUTHORIZATION, password.parse().unwrap());
+        connect_async(request).await.map(|(client, _)| client)
+    }
+
+    /// Next protocol message, answering the server's ping on the way
+    async fn receive(client: &mut Client) -> Option<ProtocolMessage> {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(2), client.next())
+                .await
+                .ok()??;
+            match message {
+                Ok(tungstenite::Message::Text(text)) => {
+                    return ProtocolMessage::from_ws_text(&text).ok()
+                }
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    async fn receive_until(
+        client: &mut Client,
+        mut wanted: impl FnMut(&ProtocolMessage) -> bool,
+    ) -> Option<ProtocolMessage> {
+        loop {
+            let message = receive(client).await?;
+            if wanted(&message) {
+                return Some(message);
+            }
+        }
+    }
+
+    async fn send(client: &mut Client, message: ProtocolMessage) {
+        client
+            .send(tungstenite::Message::Text(message.to_ws_text()))
+            .await
+            .unwrap();
+    }
+
+    /// Waits until the server has handled everything this client sent before, by moving the
+    /// client's cursor and waiting for the move to come back
+    async fn sync(client: &mut Client, id: UserId) {
+        let marker = crate::editor::CursorMarker::new(7.0, 1.0);
+        send(client, ProtocolMessage::Cursor(marker)).await;
+        let moved = receive_until(client, |message| match message {
+            ProtocolMessage::Users(participants) => participants
+                .users
+                .iter()
+                .any(|user| user.id == id && user.cursor.is_some_and(|cursor| cursor.y == 7.0)),
+            _ => false,
+        })
+        .await;
+        assert!(moved.is_some(), "the cursor move never came back");
+    }
+
+    #[tokio::test]
+    async fn a_locked_session_rejects_inserts_and_deletes() {
+        let host = host().await;
+        host.document.lock().await.replace("abc");
+        *host.is_locked.lock().await = true;
+
+        let (mut client, id) = join(&host, "edit").await;
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, 0, "x".to_string())),
+        )
+        .await;
+        send(
+            &mut client,
+            ProtocolMessage::Delete(Deletion::new(id, 1..2)),
+        )
+        .await;
+        sync(&mut client, id).await;
+
+        assert!(host.document.lock().await.has_text("abc"));
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn a_comment_client_may_comment_but_not_edit() {
+        let host = host().await;
+        host.document.lock().await.replace("abc");
+
+        let (mut reader, _) = join(&host, "read").await;
+        let (mut commenter, id) = join(&host, "comment").await;
+        send(
+            &mut commenter,
+            ProtocolMessage::Insert(Insertion::new(id, 0, "x".to_string())),
+        )
+        .await;
+        send(
+            &mut commenter,
+            ProtocolMessage::Delete(Deletion::new(id, 1..2)),
+        )
+        .await;
+        send(
+            &mut commenter,
+            ProtocolMessage::Comment("a: \"note\"".into()),
+        )
+        .await;
+        sync(&mut commenter, id).await;
+
+        let comment = receive_until(&mut reader, |message| {
+            matches!(message, ProtocolMessage::Comment(_))
+        })
+        .await;
+        assert!(matches!(comment, Some(ProtocolMessage::Comment(text)) if text == "a: \"note\""));
+        assert!(host.document.lock().await.has_text("abc"));
+
+        // Rooms other than the default one take comments too
+        join(&host, "comment/notes").await;
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn interleaved_host_and_client_edits_converge() {
+        let host = host().await;
+        let (mut client, id) = join(&host, "edit").await;
+
+        for _ in 0..5 {
+            {
+                let mut doc = host.document.lock().await;
+                let end = doc.byte_len();
+                let typed = Insertion::new(host.host_id, end, "h".to_string());
+                assert!(doc.apply(host.host_id, Operation::Insert(typed)).is_some());
+            }
+            *host.is_dirty.lock().await = true;
+            send(
+                &mut client,
+                ProtocolMessage::Insert(Insertion::new(id, 0, "c".to_string())),
+            )
+            .await;
+        }
+        sync(&mut client, id).await;
+
+        let text = host.document.lock().await.text();
+        assert_eq!(text.matches('h').count(), 5, "{text}");
+        assert_eq!(text.matches('c').count(), 5, "{text}");
+        *host.is_dirty.lock().await = true;
+        let converged = receive_until(
+            &mut client,
+            |message| matches!(message, ProtocolMessage::Document(doc) if doc.has_text(&text)),
+        )
+        .await;
+        assert!(converged.is_some(), "the client never saw {text}");
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn a_name_is_trimmed_and_told_to_the_host() {
+        let mut host = host().await;
+        let (mut client, id) = join(&host, "edit").await;
+
+        let long_name = format!("  Ada: the {}  ", "very ".repeat(10));
+        send(&mut client, ProtocolMessage::Name(long_name)).await;
+        let names = loop {
+            let input = tokio::time::timeout(Duration::from_secs(2), host.host_inputs.next())
+                .await
+                .unwrap()
+                .unwrap();
+            if let Input::Names(names) = input {
+                break names;
+            }
+        };
+        assert_eq!(names[&id], "Ada: the very very very very ver");
+        assert_eq!(names[&id].chars().count(), 32);
+
+        // A blank name goes back to the numbered label
+        send(&mut client, ProtocolMessage::Name(" ".to_string())).await;
+        let cleared = receive_until(&mut client, |message| {
+            matches!(message, ProtocolMessage::Users(participants)
+                if participants.users.iter().any(|user| user.id == id && user.name.is_none()))
+        })
+        .await;
+        assert!(cleared.is_some());
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn text_with_colons_is_inserted_as_typed() {
+        let host = host().await;
+        let (mut client, id) = join(&host, "edit").await;
+
+        let url = "http://a:b@example.com:8080";
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, 0, url.to_string())),
+        )
+        .await;
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, url.len(), " 12:30".to_string())),
+        )
+        .await;
+        let echoed = receive_until(&mut client, |message| {
+            matches!(message, ProtocolMessage::Document(doc) if doc.byte_len() > url.len())
+        })
+        .await;
+        assert!(matches!(echoed, Some(ProtocolMessage::Document(doc))
+            if doc.has_text("http://a:b@example.com:8080 12:30")));
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn a_malformed_message_is_skipped_and_a_pasted_link_kept_whole() {
+        let host = host().await;
+        let (mut client, id) = join(&host, "edit").await;
+
+        for malformed in [
+            "Insert: 0:[a](http://x)",
+            "{\"type\":\"Insert\",\"data\":3}",
+        ] {
+            client
+                .send(tungstenite::Message::Text(malformed.to_string()))
+                .await
+                .unwrap();
+        }
+        let link = "[notes](http://host:8080/a:b) at 09:15:30";
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, 0, link.to_string())),
+        )
+        .await;
+        sync(&mut client, id).await;
+        assert!(host.document.lock().await.has_text(link));
+        host.server.shutdown().await;
+    }
+
+    /// Waits for the host's editor to be told of participants that `matches`
+    async fn host_participants(
+        host: &mut TestHost,
+        mut matches: impl FnMut(&[UserId]) -> bool,
+    ) -> bool {
+        let wait = async {
+            while let Some(input) = host.host_inputs.next().await {
+                if let Input::Participants(participants) = input {
+                    let ids: Vec<UserId> = participants.users.iter().map(|user| user.id).collect();
+                    if matches(&ids) {
+                        return true;
+                    }
+                }
+            }
+            false
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn the_hosts_participant_list_follows_joins_and_leaves() {
+        let mut host = host().await;
+        let (mut editor, editor_id) = join(&host, "edit").await;
+        let (mut reader, reader_id) = join(&host, "read").await;
+        assert!(
+            host_participants(&mut host, |ids| {
+                ids.contains(&editor_id) && ids.contains(&reader_id)
+            })
+            .await
+        );
+
+        reader.close(None).await.unwrap();
+        assert!(host_participants(&mut host, |ids| !ids.contains(&reader_id)).await);
+        editor.close(None).await.unwrap();
+        assert!(host_participants(&mut host, |ids| !ids.contains(&editor_id)).await);
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn someone_joining_after_a_leave_gets_an_id_of_their_own() {
+        let mut host = host().await;
+        let (mut first, first_id) = join(&host, "edit").await;
+        let (_second, second_id) = join(&host, "edit").await;
+        first.close(None).await.unwrap();
+        assert!(host_participants(&mut host, |ids| !ids.contains(&first_id)).await);
+
+        let (_third, third_id) = join(&host, "edit").await;
+        assert_ne!(third_id, second_id);
+        assert_ne!(third_id, first_id);
+        assert_ne!(third_id, host.host_id);
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn clients_receive_a_document_the_host_opened() {
+        let host = host().await;
+        host.document.lock().await.replace("before");
+        let (mut client, _) = join(&host, "edit").await;
+
+        {
+            let mut doc = host.document.lock().await;
+            doc.replace("opened by the host");
+            doc.last_edit = host.host_id;
+        }
+        *host.is_dirty.lock().await = true;
+
+        let opened = receive_until(&mut client, |message| {
+            matches!(message, ProtocolMessage::Document(doc) if doc.has_text("opened by the host"))
+        })
+        .await;
+        assert!(opened.is_some());
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn a_closed_host_editor_does_not_stop_the_session() {
+        let mut host = host().await;
+        host.host_inputs.close();
+
+        let (mut client, id) = join(&host, "edit").await;
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, 0, "still here".to_string())),
+        )
+        .await;
+        sync(&mut client, id).await;
+        assert!(host.document.lock().await.has_text("still here"));
+
+        // Both the edit and the cursor move were forwarded to the missing editor, yet the
+        // connection keeps going
+        sync(&mut client, id).await;
+        host.server.shutdown().await;
+    }
+
+    /// Next document the server forwarded to the host's editor
+    async fn next_host_edit(host: &mut TestHost) -> Option<crate::server::Document> {
+        loop {
+            let input = tokio::time::timeout(Duration::from_secs(2), host.host_inputs.next())
+                .await
+                .ok()??;
+            if let Input::Edit(doc) = input {
+                return Some(doc);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn only_edits_from_other_users_are_forwarded_to_the_host() {
+        let mut host = host().await;
+        let (mut client, id) = join(&host, "edit").await;
+
+        {
+            let mut doc = host.document.lock().await;
+            doc.replace("host text");
+            doc.last_edit = host.host_id;
+        }
+        *host.is_dirty.lock().await = true;
+        let broadcast = receive_until(&mut client, |message| {
+            matches!(message, ProtocolMessage::Document(doc) if doc.has_text("host text"))
+        })
+        .await;
+        assert!(broadcast.is_some());
+
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, 0, "> ".to_string())),
+        )
+        .await;
+        let forwarded = next_host_edit(&mut host).await.unwrap();
+        assert!(forwarded.has_text("> host text"));
+        assert_eq!(forwarded.last_edit, id);
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn participants_are_sent_without_their_addresses() {
+        let host = host().await;
+        let (mut first, _) = join(&host, "edit").await;
+        let (_second, _) = join(&host, "read").await;
+
+        let mut sent = Vec::new();
+        let everyone = receive_until(&mut first, |message| {
+            sent.push(message.to_ws_text());
+            matches!(message, ProtocolMessage::Users(participants) if participants.users.len() == 3)
+        })
+        .await;
+        assert!(everyone.is_some());
+
+        for message in sent {
+            assert!(!message.contains("127.0.0.1"), "{message}");
+        }
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn editing_past_the_rate_limit_throttles_without_dropping_edits() {
+        let limits = SessionLimits {
+            edits_per_second: 2,
+            ..SessionLimits::default()
+        };
+        let host = host_on(free_address(), limits).await.unwrap();
+        let (mut client, id) = join(&host, "edit").await;
+
+        for ch in ["c", "b", "a"] {
+            send(
+                &mut client,
+                ProtocolMessage::Insert(Insertion::new(id, 0, ch.to_string())),
+            )
+            .await;
+        }
+        let throttled = receive_until(
+            &mut client,
+            |message| matches!(message, ProtocolMessage::Throttled(throttled) if *throttled == id),
+        )
+        .await;
+        assert!(throttled.is_some());
+
+        sync(&mut client, id).await;
+        assert!(host.document.lock().await.has_text("abc"));
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn an_insert_past_the_size_limit_is_rejected_to_its_sender() {
+        let limits = SessionLimits {
+            max_document_bytes: 8,
+            ..SessionLimits::default()
+        };
+        let host = host_on(free_address(), limits).await.unwrap();
+        let (mut client, id) = join(&host, "edit").await;
+        let advertised = receive_until(&mut client, |message| {
+            matches!(message, ProtocolMessage::Limits(_))
+        })
+        .await;
+        assert!(matches!(advertised, Some(ProtocolMessage::Limits(sent)) if sent == limits));
+
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, 0, "too long!".to_string())),
+        )
+        .await;
+        let rejected = receive_until(
+            &mut client,
+            |message| matches!(message, ProtocolMessage::Rejected(rejected) if *rejected == id),
+        )
+        .await;
+        assert!(rejected.is_some());
+
+        // Edits that fit are still taken
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, 0, "fits".to_string())),
+        )
+        .await;
+        sync(&mut client, id).await;
+        assert!(host.document.lock().await.has_text("fits"));
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn edits_in_one_room_are_not_broadcast_to_another() {
+        let host = host().await;
+        let (mut alpha, alpha_id) = join(&host, "edit/alpha").await;
+        let (mut beta, beta_id) = join(&host, "edit/beta").await;
+
+        send(
+            &mut alpha,
+            ProtocolMessage::Insert(Insertion::new(alpha_id, 0, "a".to_string())),
+        )
+        .await;
+        let in_alpha = receive_until(
+            &mut alpha,
+            |message| matches!(message, ProtocolMessage::Document(doc) if !doc.has_text("")),
+        )
+        .await;
+        assert!(matches!(in_alpha, Some(ProtocolMessage::Document(doc)) if doc.has_text("a")));
+
+        send(
+            &mut beta,
+            ProtocolMessage::Insert(Insertion::new(beta_id, 0, "b".to_string())),
+        )
+        .await;
+        let in_beta = receive_until(
+            &mut beta,
+            |message| matches!(message, ProtocolMessage::Document(doc) if !doc.has_text("")),
+        )
+        .await;
+        assert!(matches!(in_beta, Some(ProtocolMessage::Document(doc)) if doc.has_text("b")));
+
+        // Neither room touched the host's own document
+        assert!(host.document.lock().await.has_text(""));
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn a_room_closes_when_its_last_user_leaves() {
+        let host = host().await;
+        let (mut first, _) = join(&host, "edit/notes").await;
+        let (mut second, _) = join(&host, "edit/notes").await;
+        let (mut main, _) = join(&host, "edit").await;
+        assert_eq!(metric(&host, "rust_note_rooms").await, 2);
+
+        first.close(None).await.unwrap();
+        second.close(None).await.unwrap();
+        main.close(None).await.unwrap();
+
+        // The host's room stays open with nobody in it
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while metric(&host, "rust_note_rooms").await > 1 {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "the room was never closed"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(metric(&host, "rust_note_rooms").await, 1);
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn readers_are_listed_as_read_only_until_they_leave() {
+        let host = host().await;
+        let (mut editor, _) = join(&host, "edit").await;
+        let (mut reader, reader_id) = join(&host, "read").await;
+
+        let listed = receive_until(&mut editor, |message| {
+            matches!(message, ProtocolMessage::Users(participants)
+                if participants.users.iter().any(|user| user.id == reader_id))
+        })
+        .await;
+        let Some(ProtocolMessage::Users(participants)) = listed else {
+            panic!("the reader was never listed");
+        };
+        let user = participants.users.iter().find(|user| user.id == reader_id);
+        assert_eq!(user.map(|user| user.access), Some(SessionType::Read));
+
+        reader.close(None).await.unwrap();
+        let gone = receive_until(&mut editor, |message| {
+            matches!(message, ProtocolMessage::Users(participants)
+                if participants.users.iter().all(|user| user.id != reader_id))
+        })
+        .await;
+        assert!(gone.is_some());
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn a_room_closes_when_its_last_reader_leaves() {
+        let host = host().await;
+        let (mut reader, _) = join(&host, "read/notes").await;
+        assert_eq!(metric(&host, "rust_note_rooms").await, 2);
+
+        reader.close(None).await.unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        while metric(&host, "rust_note_rooms").await > 1 {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "the room was never closed"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        host.server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn switching_from_read_to_edit_takes_the_edit_password() {
+        let host = host_with_passwords("reading", "writing").await;
+        let reader = connect_with_password(&host, "read", "reading").await;
+        assert!(reader.is_ok());
+
+        let refused = connect_with_password(&host, "edit", "reading").await;
+        assert!(
+            matches!(refused, Err(tungstenite::Error::Http(ref response)) if response.status() == 401)
+        );
+
+        let mut writer = connect_with_password(&host, "edit", "writing")
+            .await
+            .unwrap();
+        let id = match receive_until(&mut writer, |message| {
+            matches!(message, ProtocolMessage::Id(_))
+        })
+        .await
+        {
+            Some(ProtocolMessage::Id(id)) => id,
+            other => panic!("expected an id, got {:?}", other),
+        };
+        send(
+            &mut writer,
+            ProtocolMessage::Insert(Insertion::new(id, 0, "now editing".to_string())),
+        )
+        .await;
+        sync(&mut writer, id).await;
+        assert!(host.document.lock().await.has_text("now editing"));
+        host.server.shutdown().await;
+    }
+
+    /// Current value of one of the counters the host serves on `/metrics`
+    async fn metric(host: &TestHost, name: &str) -> u64 {
+        let metrics = reqwest::get(format!("http://{}/metrics", host.address))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        metrics
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_else(|| panic!("metrics lack {name}"))
+    }
+
+    #[tokio::test]
+    async fn applied_operations_are_counted_in_metrics() {
+        let host = host().await;
+        assert_eq!(metric(&host, "rust_note_operations_applied_total").await, 0);
+
+        let (mut client, id) = join(&host, "edit").await;
+        send(
+            &mut client,
+            ProtocolMessage::Insert(Insertion::new(id, 0, "ab".to_string())),
+        )
+        .await;
+        send(
+            &mut client,
+            ProtocolMessage::Delete(Deletion::new(id, 0..1)),
+        )
+        .await;
+        sync(&mut client, id).await;
+
+        assert_eq!(metric(&host, "rust_note_operations_applied_total").await, 2);
+        host.server.shutdown().await;
+    }
+}
@@ -0,0 +1,324 @@
+use iced::{
+    font::{Family, Style, Weight},
+    widget::markdown,
+    Theme,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf};
+
+/// Built-in stylesheets for exported pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ExportStyle {
+    #[default]
+    Clean,
+    Serif,
+    Dark,
+}
+
+impl ExportStyle {
+    pub const ALL: [ExportStyle; 3] = [ExportStyle::Clean, ExportStyle::Serif, ExportStyle::Dark];
+
+    pub fn css(self) -> &'static str {
+        match self {
+            ExportStyle::Clean => {
+                "body { max-width: 45em; margin: 2em auto; padding: 0 1em; font-family: sans-serif; line-height: 1.5; color: #222; }\n\
+                 h1, h2, h3 { line-height: 1.2; }\n\
+                 a { color: #0366d6; }\n\
+                 pre, code { font-family: monospace; background: #f4f4f4; }\n\
+                 pre { padding: 0.75em; overflow-x: auto; }\n"
+            }
+            ExportStyle::Serif => {
+                "body { max-width: 38em; margin: 3em auto; padding: 0 1em; font-family: Georgia, serif; font-size: 1.1em; line-height: 1.6; color: #2b2b2b; background: #fdfbf7; }\n\
+                 h1, h2, h3 { font-weight: normal; line-height: 1.2; }\n\
+                 a { color: #8b4513; }\n\
+                 pre, code { font-family: monospace; font-size: 0.9em; background: #f3efe6; }\n\
+                 pre { padding: 0.75em; overflow-x: auto; }\n"
+            }
+            ExportStyle::Dark => {
+                "body { max-width: 45em; margin: 2em auto; padding: 0 1em; font-family: sans-serif; line-height: 1.5; color: #ddd; background: #1e1e1e; }\n\
+                 h1, h2, h3 { line-height: 1.2; color: #fff; }\n\
+                 a { color: #6cb6ff; }\n\
+                 pre, code { font-family: monospace; background: #2d2d2d; }\n\
+                 pre { padding: 0.75em; overflow-x: auto; }\n"
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExportStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            ExportStyle::Clean => "Clean",
+            ExportStyle::Serif => "Serif",
+            ExportStyle::Dark => "Dark",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// The stylesheet a page is exported with: the custom one if a file is set, else the built-in
+/// `style`
+pub async fn stylesheet(custom: Option<PathBuf>, style: ExportStyle) -> Result<String, String> {
+    match custom {
+        Some(path) => tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|err| format!("Failed to read stylesheet: {}", err)),
+        None => Ok(style.css().to_string()),
+    }
+}
+
+/// Line that starts a new page when the document is printed from its HTML export
+pub const PAGE_BREAK: &str = "<!-- pagebreak -->";
+
+/// Splits markdown at its page break lines
+pub fn split_pages(markdown: &str) -> Vec<String> {
+    let mut pages = vec![String::new()];
+    for line in markdown.split_inclusive('\n') {
+        if line.trim() == PAGE_BREAK {
+            pages.push(String::new());
+        } else if let Some(page) = pages.last_mut() {
+            page.push_str(line);
+        }
+    }
+    pages
+}
+
+/// Renders parsed markdown pages as a standalone HTML page, with `css` inline so the file can
+/// be shared on its own. Printing it starts each page on a new sheet.
+pub fn from_markdown(pages: &[Vec<markdown::Item>], title: &str, css: &str) -> String {
+    let mut body = String::new();
+    // Spans only come out of parsed text with a style applied, only their fonts are kept
+    let style = markdown::Style::from_palette(Theme::Light.palette());
+    for (index, items) in pages.iter().enumerate() {
+        if index > 0 {
+            body.push_str("<div class=\"page-break\"></div>\n");
+        }
+        write_items(&mut body, items, style);
+    }
+
+    format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n\
+         <style>\n\
+         {}\n\
+         .page-break {{ break-after: page; page-break-after: always; }}\n\
+         </style>\n\
+         </head>\n\
+         <body>\n\
+         {}\
+         </body>\n\
+         </html>\n",
+        escape(title),
+        // A stylesheet cannot close the style block early and inject markup
+        css.trim_end().replace("</", "<\\/"),
+        body
+    )
+}
+
+fn write_items(output: &mut String, items: &[markdown::Item], style: markdown::Style) {
+    for item in items {
+        match item {
+            markdown::Item::Heading(level, text) => {
+                let level = *level as u8;
+                output.push_str(&format!(
+                    "<h{level}>{}</h{level}>\n",
+                    inline_html(text, style)
+                ));
+            }
+            markdown::Item::Paragraph(text) => {
+                output.push_str(&format!("<p>{}</p>\n", inline_html(text, style)));
+            }
+            markdown::Item::CodeBlock(text) => {
+                // Highlighted code comes in many spans, only their text matters here
+                let code: String = text
+                    .spans(style)
+                    .iter()
+                    .map(|span| span.text.as_ref())
+                    .collect();
+                output.push_str(&format!("<pre><code>{}</code></pre>\n", escape(&code)));
+            }
+            markdown::Item::List { start, items } => {
+                let tag = match start {
+                    Some(1) => String::from("ol"),
+                    Some(start) => format!("ol start=\"{start}\""),
+                    None => String::from("ul"),
+                };
+                output.push_str(&format!("<{tag}>\n"));
+                for list_item in items {
+                    output.push_str("<li>");
+                    write_items(output, list_item, style);
+                    output.push_str("</li>\n");
+                }
+                output.push_str(if start.is_some() {
+                    "</ol>\n"
+                } else {
+                    "</ul>\n"
+                });
+            }
+        }
+    }
+}
+
+fn inline_html(text: &markdown::Text, style: markdown::Style) -> String {
+    let mut output = String::new();
+
+    for span in text.spans(style).iter() {
+        let mut html = escape(&span.text);
+        if let Some(font) = span.font {
+            if font.family == Family::Monospace {
+                html = format!("<code>{html}</code>");
+            }
+            if font.weight == Weight::Bold {
+                html = format!("<strong>{html}</strong>");
+            }
+            if font.style == Style::Italic {
+                html = format!("<em>{html}</em>");
+            }
+        }
+        if span.strikethrough {
+            html = format!("<del>{html}</del>");
+        }
+        if let Some(url) = span.link.as_ref().filter(|url| is_safe_link(url)) {
+            html = format!("<a href=\"{}\">{html}</a>", escape(url.as_str()));
+        }
+        output.push_str(&html);
+    }
+
+    output
+}
+
+/// Whether a link can go in a published page as it is. Anything that could run in the reader's
+/// browser, like `javascript:` or `data:`, is left as plain text. The parser already drops links
+/// it cannot resolve, so relative ones never get this far.
+fn is_safe_link(url: &markdown::Url) -> bool {
+    matches!(url.scheme(), "http" | "https" | "mailto")
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(markdown_text: &str, title: &str, css: &str) -> String {
+        let pages: Vec<Vec<markdown::Item>> = split_pages(markdown_text)
+            .iter()
+            .map(|page| markdown::parse(page).collect())
+            .collect();
+        from_markdown(&pages, title, css)
+    }
+
+    fn body(html: &str) -> &str {
+        let start = html.find("<body>\n").unwrap() + "<body>\n".len();
+        &html[start..html.find("</body>").unwrap()]
+    }
+
+    #[test]
+    fn markdown_becomes_html_markup() {
+        let html = page(
+            "# Trip **plan**\n\
+             \n\
+             Read *the* [guide](https://example.com/guide) with `code`.\n\
+             \n\
+             - one\n\
+             - two\n\
+             \n\
+             3. three\n",
+            "Trip",
+            ExportStyle::Clean.css(),
+        );
+        assert!(html.starts_with("<!DOCTYPE html>\n"), "{html}");
+        assert!(html.contains("<title>Trip</title>"), "{html}");
+        assert!(html.contains(ExportStyle::Clean.css().trim_end()), "{html}");
+        assert_eq!(
+            body(&html),
+            "<h1>Trip <strong>plan</strong></h1>\n\
+             <p>Read <em>the</em> <a href=\"https://example.com/guide\">guide</a> with <code>code</code>.</p>\n\
+             <ul>\n<li><p>one</p>\n</li>\n<li><p>two</p>\n</li>\n</ul>\n\
+             <ol start=\"3\">\n<li><p>three</p>\n</li>\n</ol>\n"
+        );
+    }
+
+    #[test]
+    fn text_and_styles_cannot_inject_markup() {
+        // Raw HTML is dropped by the parser, but entities come through as text
+        let html = page(
+            "a &lt;b&gt; & \"c\"\n",
+            "<script>x</script>",
+            "p {}</style><script>",
+        );
+        assert!(
+            html.contains("<title>&lt;script&gt;x&lt;/script&gt;</title>"),
+            "{html}"
+        );
+        assert!(html.contains("p {}<\\/style><script>"), "{html}");
+        assert_eq!(body(&html), "<p>a &lt;b&gt; &amp; &quot;c&quot;</p>\n");
+    }
+
+    #[test]
+    fn only_web_and_mail_links_are_kept() {
+        let html = page(
+            "[run](javascript:alert(1)) [data](data:text/html,hi) [up](../secret.md) \
+             [web](https://example.com/a?b=\"c\")\n",
+            "",
+            "",
+        );
+        assert_eq!(
+            body(&html),
+            "<p>run data up <a href=\"https://example.com/a?b=%22c%22\">web</a></p>\n"
+        );
+
+        let url = |text: &str| markdown::Url::parse(text).unwrap();
+        assert!(is_safe_link(&url("mailto:someone@example.com")));
+        assert!(is_safe_link(&url("http://example.com")));
+        assert!(!is_safe_link(&url("javascript:alert(1)")));
+        assert!(!is_safe_link(&url("data:text/html,<script>x</script>")));
+        assert!(!is_safe_link(&url("file:///etc/passwd")));
+    }
+
+    #[tokio::test]
+    async fn the_chosen_stylesheet_is_embedded() {
+        let css = stylesheet(None, ExportStyle::Serif).await.unwrap();
+        assert!(page("text", "", &css).contains("font-family: Georgia, serif;"));
+
+        let path = std::env::temp_dir().join(format!("rust-note-style-{}.css", std::process::id()));
+        std::fs::write(&path, "p { color: rebeccapurple; }\n").unwrap();
+        let css = stylesheet(Some(path.clone()), ExportStyle::Serif).await;
+        std::fs::remove_file(&path).unwrap();
+        let html = page("text", "", &css.unwrap());
+        assert!(
+            html.contains("<style>\np { color: rebeccapurple; }\n"),
+            "{html}"
+        );
+        assert!(!html.contains("Georgia"), "{html}");
+
+        assert!(stylesheet(Some(path), ExportStyle::Clean).await.is_err());
+    }
+
+    #[test]
+    fn page_breaks_split_the_document() {
+        let text = format!("one\n{PAGE_BREAK}\ntwo\n");
+        assert_eq!(split_pages(&text), ["one\n", "two\n"]);
+        assert_eq!(
+            body(&page(&text, "", "")),
+            "<p>one</p>\n<div class=\"page-break\"></div>\n<p>two</p>\n"
+        );
+    }
+}
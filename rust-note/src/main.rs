@@ -1,8 +1,20 @@
 // Custom widgets
 mod client;
+mod codec;
+mod diagnostics;
 mod editor;
+mod frontmatter;
 mod handlers;
+mod html;
+mod math;
+mod oplog;
+mod plaintext;
+mod privacy;
+mod protocol;
+mod recovery;
 mod server;
+mod settings;
+mod undo;
 mod widgets;
 
 use editor::Editor;
@@ -12,6 +24,7 @@ pub async fn main() -> iced::Result {
     iced::application(Editor::title, Editor::update, Editor::view)
         .font(include_bytes!("../fonts/format-bar-icons.ttf").as_slice())
         .theme(Editor::theme)
+        .scale_factor(Editor::scale_factor)
         .exit_on_close_request(false)
         .subscription(Editor::subscription)
         .run_with(Editor::new)
@@ -0,0 +1,165 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MathKind {
+    /// `$...$` within a single line
+    Inline,
+    /// `$$...$$`, which may span several lines
+    Block,
+}
+
+/// A math expression in the buffer, with byte ranges for the whole span and its contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct MathSpan {
+    pub kind: MathKind,
+    pub range: Range<usize>,
+    pub content: Range<usize>,
+}
+
+/// Finds the math spans in a markdown buffer, skipping escaped dollars and code
+pub fn find_spans(text: &str) -> Vec<MathSpan> {
+    let bytes = text.as_bytes();
+    let mut spans = Vec::new();
+    let mut in_fence = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let at_line_start = i == 0 || bytes[i - 1] == b'\n';
+        if at_line_start && text[i..].trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            i = text[i..].find('\n').map_or(bytes.len(), |end| i + end + 1);
+            continue;
+        }
+
+        match bytes[i] {
+            b'\\' => i += 1 + text[i + 1..].chars().next().map_or(0, char::len_utf8),
+            b'`' => {
+                // Skip the inline code span, if it is closed
+                let ticks = text[i..].bytes().take_while(|&b| b == b'`').count();
+                let fence = &text[i..i + ticks];
+                i = text[i + ticks..]
+                    .find(fence)
+                    .map_or(i + ticks, |end| i + ticks + end + ticks);
+            }
+            b'$' if text[i..].starts_with("$$") => match text[i + 2..].find("$$") {
+                Some(end) => {
+                    let content = i + 2..i + 2 + end;
+                    spans.push(MathSpan {
+                        kind: MathKind::Block,
+                        range: i..content.end + 2,
+                        content: content.clone(),
+                    });
+                    i = content.end + 2;
+                }
+                None => i += 2,
+            },
+            b'$' => match inline_end(text, i + 1) {
+                Some(end) => {
+                    spans.push(MathSpan {
+                        kind: MathKind::Inline,
+                        range: i..end + 1,
+                        content: i + 1..end,
+                    });
+                    i = end + 1;
+                }
+                None => i += 1,
+            },
+            _ => i += 1,
+        }
+    }
+
+    spans
+}
+
+/// Rewrites math spans as code so the markdown parser keeps them verbatim, and the preview
+/// shows them apart from the surrounding prose
+pub fn preserve(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for span in find_spans(text) {
+        output.push_str(&text[last..span.range.start]);
+        let content = text[span.content.clone()].trim();
+
+        match span.kind {
+            MathKind::Inline => {
+                let ticks = if content.contains('`') { "``" } else { "`" };
+                output.push_str(&format!("{ticks} {content} {ticks}"));
+            }
+            MathKind::Block => output.push_str(&format!("\n```math\n{content}\n```\n")),
+        }
+        last = span.range.end;
+    }
+
+    output.push_str(&text[last..]);
+    output
+}
+
+/// Closing `$` of an inline span opened just before `start`. Like most markdown math
+/// extensions, the contents may not start or end with a space, so prices like "$5 and $10"
+/// are left alone.
+fn inline_end(text: &str, start: usize) -> Option<usize> {
+    let rest = &text[start..];
+    if rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let mut escaped = false;
+    for (offset, c) in rest.char_indices() {
+        match c {
+            '\n' => return None,
+            '\\' => escaped = !escaped,
+            '$' if !escaped => {
+                return (offset > 0 && !rest[..offset].ends_with(char::is_whitespace))
+                    .then_some(start + offset);
+            }
+            _ => escaped = false,
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(text: &str) -> Vec<(MathKind, &str)> {
+        find_spans(text)
+            .into_iter()
+            .map(|span| (span.kind, &text[span.content]))
+            .collect()
+    }
+
+    #[test]
+    fn inline_and_block_math_are_detected() {
+        let text = "Euler: $e^{i\\pi} + 1 = 0$.\n$$\n\\int_0^1 x\\,dx\n$$\n";
+        assert_eq!(
+            contents(text),
+            [
+                (MathKind::Inline, "e^{i\\pi} + 1 = 0"),
+                (MathKind::Block, "\n\\int_0^1 x\\,dx\n"),
+            ]
+        );
+
+        let span = &find_spans("a $x$ b")[0];
+        assert_eq!(span.range, 2..5);
+    }
+
+    #[test]
+    fn prices_escapes_and_code_are_not_math() {
+        assert!(find_spans("It costs $5 and $10 now").is_empty());
+        assert!(find_spans("Escaped \\$x\\$ dollars").is_empty());
+        assert!(find_spans("Run `echo $x$` here").is_empty());
+        assert!(find_spans("```sh\necho $HOME$\n```\n").is_empty());
+        assert!(find_spans("$x\ny$").is_empty());
+    }
+
+    #[test]
+    fn preserved_math_survives_as_code() {
+        assert_eq!(preserve("Area $\\pi r^2$ here"), "Area ` \\pi r^2 ` here");
+        assert_eq!(preserve("$$ a^2 $$"), "\n```math\na^2\n```\n");
+    }
+}
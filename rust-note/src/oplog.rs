@@ -0,0 +1,136 @@
+use crate::privacy::DisplayPath;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Oldest entries are dropped once the log grows past this
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub timestamp_ms: u128,
+    pub direction: Direction,
+    pub message: String,
+}
+
+/// Record of the messages a client exchanged with the session, for reproducing sync bugs
+#[derive(Debug, Default)]
+pub struct OpLog {
+    enabled: bool,
+    next_seq: u64,
+    entries: VecDeque<Entry>,
+}
+
+impl OpLog {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn record<S: Into<String>>(&mut self, direction: Direction, message: S) {
+        if !self.enabled {
+            return;
+        }
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            seq: self.next_seq,
+            timestamp_ms,
+            direction,
+            message: message.into(),
+        });
+        self.next_seq += 1;
+    }
+
+    pub fn to_jsonl(&self) -> String {
+        self.entries
+            .iter()
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .map(|line| line + "\n")
+            .collect()
+    }
+}
+
+pub async fn export(contents: String) -> Result<PathBuf, String> {
+    let path = rfd::AsyncFileDialog::new()
+        .set_file_name("rust-note-oplog.jsonl")
+        .save_file()
+        .await
+        .as_ref()
+        .map(rfd::FileHandle::path)
+        .map(Path::to_owned)
+        .ok_or_else(|| "Export dialog was closed without selection.".to_string())?;
+
+    tokio::fs::write(&path, contents)
+        .await
+        .map_err(|err| format!("Failed to write operation log: {}", err))?;
+
+    println!("Operation log exported to: {}", DisplayPath(&path));
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(log: &OpLog) -> Vec<serde_json::Value> {
+        log.to_jsonl()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn nothing_is_recorded_until_enabled() {
+        let mut log = OpLog::default();
+        log.record(Direction::Sent, "Insert: {}");
+        assert_eq!(log.to_jsonl(), "");
+    }
+
+    #[test]
+    fn enabled_logs_write_an_entry_per_edit() {
+        let mut log = OpLog::default();
+        log.set_enabled(true);
+        log.record(Direction::Sent, "Insert: a");
+        log.record(Direction::Received, "Document: ab");
+        log.record(Direction::Sent, "Delete: 0..1");
+
+        let entries = lines(&log);
+        assert_eq!(entries.len(), 3);
+        for (seq, entry) in entries.iter().enumerate() {
+            assert_eq!(entry["seq"], seq as u64);
+        }
+        assert_eq!(entries[1]["direction"], "received");
+        assert_eq!(entries[2]["message"], "Delete: 0..1");
+    }
+
+    #[test]
+    fn the_oldest_entries_are_dropped_past_the_limit() {
+        let mut log = OpLog::default();
+        log.set_enabled(true);
+        for _ in 0..=MAX_ENTRIES {
+            log.record(Direction::Sent, "Cursor");
+        }
+
+        let entries = lines(&log);
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0]["seq"], 1);
+    }
+}
@@ -0,0 +1,144 @@
+use iced::{widget::markdown, Theme};
+
+/// Rebuilds the prose of parsed markdown without its syntax. Links keep their label, followed by
+/// the URL in parentheses when `include_urls` is set.
+pub fn from_markdown(items: &[markdown::Item], include_urls: bool) -> String {
+    let mut output = String::new();
+    // Spans only come out of parsed text with a style applied, which plain text then ignores
+    let style = markdown::Style::from_palette(Theme::Light.palette());
+
+    write_items(&mut output, items, "", include_urls, style);
+    output.trim_end().to_string() + "\n"
+}
+
+fn write_items(
+    output: &mut String,
+    items: &[markdown::Item],
+    indent: &str,
+    include_urls: bool,
+    style: markdown::Style,
+) {
+    for item in items {
+        match item {
+            markdown::Item::Heading(_, text) | markdown::Item::Paragraph(text) => {
+                output.push_str(indent);
+                output.push_str(&plain_text(text, include_urls, style));
+                output.push_str("\n\n");
+            }
+            markdown::Item::CodeBlock(text) => {
+                for line in plain_text(text, false, style).lines() {
+                    output.push_str(indent);
+                    output.push_str(line);
+                    output.push('\n');
+                }
+                output.push('\n');
+            }
+            markdown::Item::List { start, items } => {
+                for (index, list_item) in items.iter().enumerate() {
+                    let bullet = match start {
+                        Some(start) => format!("{}. ", start + index as u64),
+                        None => String::from("\u{2022} "),
+                    };
+
+                    // The first block of an item follows its bullet, the rest line up below it
+                    let mut item_output = String::new();
+                    let nested_indent = " ".repeat(bullet.chars().count());
+                    write_items(
+                        &mut item_output,
+                        list_item,
+                        &nested_indent,
+                        include_urls,
+                        style,
+                    );
+                    let item_output = item_output.trim_end().replace("\n\n", "\n");
+
+                    output.push_str(indent);
+                    output.push_str(&bullet);
+                    output.push_str(item_output.trim_start());
+                    output.push('\n');
+                }
+                output.push('\n');
+            }
+        }
+    }
+}
+
+fn plain_text(text: &markdown::Text, include_urls: bool, style: markdown::Style) -> String {
+    let mut output = String::new();
+    let mut open_link: Option<markdown::Url> = None;
+
+    for span in text.spans(style).iter() {
+        // A link's label can be split over several spans, so its URL goes after the last one
+        if open_link.is_some() && open_link != span.link {
+            push_url(&mut output, open_link.take(), include_urls);
+        }
+        open_link.clone_from(&span.link);
+        output.push_str(&span.text);
+    }
+    push_url(&mut output, open_link, include_urls);
+
+    output
+}
+
+fn push_url(output: &mut String, url: Option<markdown::Url>, include_urls: bool) {
+    if let Some(url) = url.filter(|_| include_urls) {
+        output.push_str(&format!(" ({})", url));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(markdown_text: &str, include_urls: bool) -> String {
+        let items: Vec<markdown::Item> = markdown::parse(markdown_text).collect();
+        from_markdown(&items, include_urls)
+    }
+
+    const SAMPLE: &str = "# Trip **plan**\n\
+        \n\
+        Pack *light* and read the [guide](https://example.com/guide).\n\
+        \n\
+        - `passport`\n\
+        - tickets\n\
+        \n\
+        1. Book\n\
+        2. Go\n\
+        \n\
+        ```\n\
+        let x = 1;\n\
+        ```\n";
+
+    #[test]
+    fn markdown_syntax_is_stripped() {
+        assert_eq!(
+            plain(SAMPLE, false),
+            "Trip plan\n\
+            \n\
+            Pack light and read the guide.\n\
+            \n\
+            \u{2022} passport\n\
+            \u{2022} tickets\n\
+            \n\
+            1. Book\n\
+            2. Go\n\
+            \n\
+            let x = 1;\n"
+        );
+    }
+
+    #[test]
+    fn link_urls_follow_their_label_when_asked_for() {
+        assert_eq!(
+            plain(
+                "See [the **full** guide](https://example.com/guide) now.",
+                true
+            ),
+            "See the full guide (https://example.com/guide) now.\n"
+        );
+        assert_eq!(
+            plain("See [the guide](https://example.com) now.", false),
+            "See the guide now.\n"
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use std::{fmt, net::SocketAddr, path::Path};
+
+/// Shows a local path in full in debug builds, and only its file name otherwise
+pub struct DisplayPath<'a>(pub &'a Path);
+
+impl fmt::Display for DisplayPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if cfg!(debug_assertions) {
+            return write!(f, "{}", self.0.display());
+        }
+
+        match self.0.file_name() {
+            Some(name) => write!(f, "{}", name.to_string_lossy()),
+            None => f.write_str("<file>"),
+        }
+    }
+}
+
+/// Shows a peer address in full in debug builds, and only its port otherwise
+#[derive(Clone, Copy)]
+pub struct DisplayAddr(pub SocketAddr);
+
+impl fmt::Display for DisplayAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if cfg!(debug_assertions) {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "peer :{}", self.0.port())
+        }
+    }
+}
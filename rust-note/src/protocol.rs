@@ -0,0 +1,164 @@
+use crate::{
+    editor::CursorMarker,
+    server::{Deletion, Document, Insertion, Operation, Participants, SessionLimits, UserId},
+};
+use serde::{Deserialize, Serialize};
+
+/// Everything sent over a session's websocket, in either direction. Each message is a JSON
+/// object tagged with its kind, so text inside one, like a URL, is never read as protocol.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum ProtocolMessage {
+    // From clients
+    Insert(Insertion),
+    Delete(Deletion),
+    Cursor(CursorMarker),
+    /// Name to show by the sender's cursor
+    Name(String),
+    /// Passed on to the room as sent, the document is left alone
+    Comment(String),
+
+    // From the host
+    Document(Document),
+    /// The receiving client's own id
+    Id(UserId),
+    /// Sent on joining, so clients know what the server will refuse
+    Limits(SessionLimits),
+    Users(Participants),
+    Locked(bool),
+    /// Asks the client with this id to slow down its edits
+    Throttled(UserId),
+    /// Tells the client with this id an edit of theirs was refused, so its copy is out of date
+    Rejected(UserId),
+}
+
+impl ProtocolMessage {
+    pub fn to_ws_text(&self) -> String {
+        serde_json::to_string(self).expect("protocol messages are plain data")
+    }
+
+    pub fn from_ws_text(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+
+    /// Whether the message changes the document's text
+    pub fn is_edit(&self) -> bool {
+        matches!(
+            self,
+            ProtocolMessage::Insert(_) | ProtocolMessage::Delete(_)
+        )
+    }
+}
+
+impl From<Operation> for ProtocolMessage {
+    fn from(operation: Operation) -> Self {
+        match operation {
+            Operation::Insert(insertion) => ProtocolMessage::Insert(insertion),
+            Operation::Delete(deletion) => ProtocolMessage::Delete(deletion),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{editor::SessionType, server::Users};
+
+    // Colons, quotes, escapes, newlines and characters of several widths
+    const AWKWARD: &str = "Insert: {\"type\":\"Id\"}\n\t\\n 12:30 — 世界 🦀";
+
+    /// One message of every kind, each carrying awkward text where it can
+    fn every_message() -> Vec<ProtocolMessage> {
+        let mut document = Document::new(AWKWARD.to_string());
+        document.last_edit = 3;
+        let mut users = Users::new();
+        let addr = "127.0.0.1:4000".parse().unwrap();
+        users.join(addr, SessionType::Comment);
+        users.set_name(addr, AWKWARD);
+
+        vec![
+            ProtocolMessage::Insert(Insertion::new(1, 0, AWKWARD.to_string())),
+            ProtocolMessage::Insert(Insertion::new(
+                1,
+                4,
+                "http://a:b@example.com:8080".to_string(),
+            )),
+            ProtocolMessage::Delete(Deletion::new(2, 3..9)),
+            ProtocolMessage::Cursor(CursorMarker::new(42.0, 21.0)),
+            ProtocolMessage::Name(AWKWARD.to_string()),
+            ProtocolMessage::Comment(AWKWARD.to_string()),
+            ProtocolMessage::Document(document),
+            ProtocolMessage::Id(7),
+            ProtocolMessage::Limits(SessionLimits::default()),
+            ProtocolMessage::Users(users.participants(Some(1))),
+            ProtocolMessage::Locked(true),
+            ProtocolMessage::Throttled(7),
+            ProtocolMessage::Rejected(7),
+        ]
+    }
+
+    /// Names the kind of a message, so a new kind cannot be left out of `every_message`
+    fn kind(message: &ProtocolMessage) -> &'static str {
+        match message {
+            ProtocolMessage::Insert(_) => "Insert",
+            ProtocolMessage::Delete(_) => "Delete",
+            ProtocolMessage::Cursor(_) => "Cursor",
+            ProtocolMessage::Name(_) => "Name",
+            ProtocolMessage::Comment(_) => "Comment",
+            ProtocolMessage::Document(_) => "Document",
+            ProtocolMessage::Id(_) => "Id",
+            ProtocolMessage::Limits(_) => "Limits",
+            ProtocolMessage::Users(_) => "Users",
+            ProtocolMessage::Locked(_) => "Locked",
+            ProtocolMessage::Throttled(_) => "Throttled",
+            ProtocolMessage::Rejected(_) => "Rejected",
+        }
+    }
+
+    #[test]
+    fn every_message_survives_the_wire() {
+        let messages = every_message();
+        let kinds: std::collections::HashSet<_> = messages.iter().map(kind).collect();
+        assert_eq!(kinds.len(), 12);
+
+        for message in messages {
+            let text = message.to_ws_text();
+            let parsed = ProtocolMessage::from_ws_text(&text).unwrap();
+            assert_eq!(kind(&parsed), kind(&message), "{text}");
+            assert_eq!(parsed.to_ws_text(), text);
+        }
+    }
+
+    #[test]
+    fn text_that_looks_like_protocol_stays_text() {
+        for text in [AWKWARD, "http://example.com", "http://a:b@example.com:8080"] {
+            let insert = ProtocolMessage::Insert(Insertion::new(1, 0, text.to_string()));
+            match ProtocolMessage::from_ws_text(&insert.to_ws_text()).unwrap() {
+                ProtocolMessage::Insert(insertion) => {
+                    assert_eq!(insertion.text, text);
+                    assert_eq!((insertion.made_by, insertion.insert_at), (1, 0));
+                }
+                other => panic!("{text:?} came back as {other:?}"),
+            }
+
+            let comment = ProtocolMessage::Comment(text.to_string()).to_ws_text();
+            assert!(
+                matches!(ProtocolMessage::from_ws_text(&comment), Ok(ProtocolMessage::Comment(c)) if c == text)
+            );
+        }
+
+        let Ok(ProtocolMessage::Document(document)) = ProtocolMessage::from_ws_text(
+            &ProtocolMessage::Document(Document::new(AWKWARD.into())).to_ws_text(),
+        ) else {
+            panic!("the document did not come back");
+        };
+        assert!(document.has_text(AWKWARD));
+    }
+
+    #[test]
+    fn colon_prefixed_text_is_not_a_message() {
+        for text in ["Insert: 0:hello", "Id:3", "http://example.com", ""] {
+            assert!(ProtocolMessage::from_ws_text(text).is_err(), "{text}");
+        }
+    }
+}
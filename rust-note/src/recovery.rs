@@ -0,0 +1,250 @@
+use crate::privacy::DisplayPath;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+/// Crash-recovery dumps of unsaved text, one per running editor, kept in a folder only the user
+/// can read. Each editor holds a lock on its own dump, so a dump that can be locked was left
+/// behind by an editor that did not exit cleanly.
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    folder: PathBuf,
+    // This editor's dump, created the first time it is written
+    own: Arc<Mutex<Option<Dump>>>,
+    // Dump left by a crash while it is offered, locked so no other editor offers it too
+    found: Arc<Mutex<Option<Dump>>>,
+}
+
+#[derive(Debug)]
+struct Dump {
+    path: PathBuf,
+    file: File,
+}
+
+impl Recovery {
+    /// Dumps kept in the `recovery` folder of `config_dir`
+    pub fn new(config_dir: &Path) -> Self {
+        Self {
+            folder: config_dir.join("recovery"),
+            own: Arc::default(),
+            found: Arc::default(),
+        }
+    }
+
+    /// Replaces the text in this editor's dump
+    pub async fn write(self, contents: String) -> Result<(), String> {
+        blocking(move || {
+            let mut own = self.own.lock().unwrap_or_else(|e| e.into_inner());
+            let dump = match own.as_mut() {
+                Some(dump) => dump,
+                None => own.insert(Dump::create(&self.folder)?),
+            };
+            dump.file.set_len(0)?;
+            dump.file.seek(SeekFrom::Start(0))?;
+            dump.file.write_all(contents.as_bytes())
+        })
+        .await
+        .map_err(|err| format!("Failed to write recovery file: {}", err))
+    }
+
+    /// Deletes this editor's dump, once there is nothing left to recover
+    pub async fn remove(self) {
+        let _ = blocking(move || {
+            if let Some(dump) = self.own.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                fs::remove_file(&dump.path)?;
+            }
+            Ok(())
+        })
+        .await;
+    }
+
+    /// Text of the newest dump left behind by an unclean exit, if any. It stays claimed by this
+    /// editor until it is discarded.
+    pub async fn find(self) -> Option<String> {
+        blocking(move || {
+            let (dump, contents) = Dump::claim_newest(&self.folder)?;
+            *self.found.lock().unwrap_or_else(|e| e.into_inner()) = Some(dump);
+            Ok(contents)
+        })
+        .await
+        .ok()
+    }
+
+    /// Deletes the dump offered by `find`, once it has been restored or turned down
+    pub async fn discard_found(self) {
+        let _ = blocking(move || {
+            if let Some(dump) = self.found.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                fs::remove_file(&dump.path)?;
+                println!("Removed recovery file {}", DisplayPath(&dump.path));
+            }
+            Ok(())
+        })
+        .await;
+    }
+}
+
+impl Dump {
+    /// Creates a dump of this editor's own, named so no other editor uses it
+    fn create(folder: &Path) -> io::Result<Self> {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(folder)?;
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        let name = format!("{}-{}", std::process::id(), nanos);
+
+        // Locked before it gets the name other editors look for
+        let partial = folder.join(format!("{}.partial", name));
+        let mut options = File::options();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options.open(&partial)?;
+        file.try_lock()?;
+        let path = folder.join(format!("{}.md", name));
+        fs::rename(&partial, &path)?;
+        Ok(Self { path, file })
+    }
+
+    /// Locks the newest dump with text in it that no running editor holds. Blank ones left
+    /// behind are deleted on the way.
+    fn claim_newest(folder: &Path) -> io::Result<(Self, String)> {
+        let mut dumps: Vec<(SystemTime, PathBuf)> = fs::read_dir(folder)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let modified = path.metadata().ok()?.modified().ok()?;
+                (path.extension()? == "md").then_some((modified, path))
+            })
+            .collect();
+        dumps.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+        for (_, path) in dumps {
+            let Ok(mut file) = File::options().read(true).write(true).open(&path) else {
+                continue;
+            };
+            // Still held by the editor that wrote it
+            if file.try_lock().is_err() {
+                continue;
+            }
+            let mut contents = String::new();
+            if file.read_to_string(&mut contents).is_ok() && !contents.trim().is_empty() {
+                return Ok((Self { path, file }, contents));
+            }
+            let _ = fs::remove_file(&path);
+        }
+        Err(io::ErrorKind::NotFound.into())
+    }
+}
+
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .unwrap_or_else(|err| Err(io::Error::other(err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rust-note-recovery-{}-{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    #[tokio::test]
+    async fn a_running_editors_dump_is_not_offered_to_another() {
+        let config_dir = config_dir("running");
+        let running = Recovery::new(&config_dir);
+        running.clone().write("unsaved".to_string()).await.unwrap();
+        running
+            .clone()
+            .write("unsaved work".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(Recovery::new(&config_dir).find().await, None);
+
+        // Once the editor is gone without removing it, its dump is offered
+        drop(running);
+        let next = Recovery::new(&config_dir);
+        assert_eq!(next.clone().find().await.as_deref(), Some("unsaved work"));
+        // ...to one editor only
+        assert_eq!(Recovery::new(&config_dir).find().await, None);
+
+        next.clone().discard_found().await;
+        assert_eq!(Recovery::new(&config_dir).find().await, None);
+        fs::remove_dir_all(&config_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn editors_keep_dumps_of_their_own() {
+        let config_dir = config_dir("own");
+        let first = Recovery::new(&config_dir);
+        let second = Recovery::new(&config_dir);
+        first.clone().write("first".to_string()).await.unwrap();
+        second.clone().write("second".to_string()).await.unwrap();
+
+        // A clean exit leaves only the other editor's dump behind
+        first.remove().await;
+        drop(second);
+        let found = Recovery::new(&config_dir).find().await;
+        fs::remove_dir_all(&config_dir).unwrap();
+        assert_eq!(found.as_deref(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn blank_or_missing_dumps_are_not_offered() {
+        let config_dir = config_dir("blank");
+        assert_eq!(Recovery::new(&config_dir).find().await, None);
+
+        let blank = Recovery::new(&config_dir);
+        blank.clone().write(" \n".to_string()).await.unwrap();
+        drop(blank);
+        assert_eq!(Recovery::new(&config_dir).find().await, None);
+        // and are cleared away
+        assert_eq!(
+            fs::read_dir(config_dir.join("recovery")).unwrap().count(),
+            0
+        );
+        fs::remove_dir_all(&config_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dumps_are_private_to_the_user() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let config_dir = config_dir("private");
+        let recovery = Recovery::new(&config_dir);
+        recovery.clone().write("secret".to_string()).await.unwrap();
+
+        let folder = config_dir.join("recovery");
+        let dump = fs::read_dir(&folder)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let modes = (mode(&folder), mode(&dump));
+        recovery.remove().await;
+        fs::remove_dir_all(&config_dir).unwrap();
+        assert_eq!(modes, (0o700, 0o600));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    editor::{CursorColor, LineHighlightColor, WordCountMode},
+    html::ExportStyle,
+    undo,
+};
+use iced::highlighter;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Path, PathBuf};
+
+/// User preferences that survive restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    #[serde(with = "highlight_theme")]
+    pub highlight_theme: highlighter::Theme,
+    /// Cleared once a highlight theme is picked by hand
+    pub highlight_theme_follows_app: bool,
+    pub auto_capitalize: bool,
+    pub smart_quotes: bool,
+    pub show_line_numbers: bool,
+    pub highlight_current_line: bool,
+    pub line_highlight_color: LineHighlightColor,
+    /// Mark spaces, tabs and trailing whitespace in the editor
+    pub show_whitespace: bool,
+    /// Color collaborators see this user's cursor in
+    pub cursor_color: CursorColor,
+    /// Seconds a collaborator's name stays by their cursor after it moves, 0 to always show it
+    pub cursor_label_fade: u64,
+    /// Number of this user's edits that can be undone
+    pub undo_depth: usize,
+    /// Megabytes the undo history may hold in memory before the oldest steps go
+    pub undo_memory_mb: usize,
+    /// Move the text of old undo steps to a temporary file instead of forgetting them
+    pub undo_spill: bool,
+    /// Space between the editor's border and its text
+    pub editor_padding: u16,
+    /// Space around the editor, gutter and preview
+    pub editor_margin: u16,
+    /// Column that overly long lines are broken at when wrapping them is accepted
+    pub hard_wrap_column: usize,
+    /// Replaces the theme with strong foreground and background colors
+    pub high_contrast: bool,
+    /// Size of the whole interface, in percent
+    pub ui_scale: u16,
+    pub op_log_enabled: bool,
+    /// Let the others in a session see which lines are on screen
+    pub share_viewport: bool,
+    /// Summarize what will be shared and ask before a session starts
+    pub confirm_session_start: bool,
+    pub reopen_last_file: bool,
+    /// Turn each line pasted into a list item into an item of its own
+    pub list_paste: bool,
+    /// Turn the selection into a link when a URL is pasted over it
+    pub link_paste: bool,
+    /// Sort capitalized lines before lowercase ones rather than alongside them
+    pub sort_case_sensitive: bool,
+    /// Follow link labels with their URL in plain text exports
+    pub plain_text_link_urls: bool,
+    /// Built-in stylesheet for published HTML
+    pub export_style: ExportStyle,
+    /// Stylesheet file published HTML uses in place of the built-in one
+    pub export_css: Option<PathBuf>,
+    /// Number of words to aim for, 0 when there is no goal
+    pub word_goal: usize,
+    pub word_count_mode: WordCountMode,
+    pub last_file: Option<PathBuf>,
+    /// Line and column of the caret in `last_file` when the app closed
+    pub last_caret: (usize, usize),
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            highlight_theme: highlighter::Theme::SolarizedDark,
+            highlight_theme_follows_app: true,
+            auto_capitalize: false,
+            smart_quotes: false,
+            show_line_numbers: false,
+            highlight_current_line: false,
+            line_highlight_color: LineHighlightColor::default(),
+            show_whitespace: false,
+            cursor_color: CursorColor::default(),
+            cursor_label_fade: 3,
+            undo_depth: undo::DEFAULT_DEPTH,
+            undo_memory_mb: undo::DEFAULT_MEMORY_MB,
+            undo_spill: false,
+            editor_padding: 5,
+            editor_margin: 0,
+            hard_wrap_column: 120,
+            high_contrast: false,
+            ui_scale: 100,
+            op_log_enabled: false,
+            share_viewport: false,
+            confirm_session_start: true,
+            reopen_last_file: false,
+            list_paste: false,
+            link_paste: true,
+            sort_case_sensitive: false,
+            plain_text_link_urls: true,
+            export_style: ExportStyle::default(),
+            export_css: None,
+            word_goal: 0,
+            word_count_mode: WordCountMode::default(),
+            last_file: None,
+            last_caret: (0, 0),
+        }
+    }
+}
+
+impl Settings {
+    /// The user's folder for the settings and anything else kept between launches
+    pub fn config_dir() -> PathBuf {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .unwrap_or_else(std::env::temp_dir);
+
+        config_dir.join("rust-note")
+    }
+
+    fn path(config_dir: &Path) -> PathBuf {
+        config_dir.join("settings.json")
+    }
+
+    /// Reads the settings saved in `config_dir`, falling back to the defaults if there are none
+    pub fn load(config_dir: &Path) -> Self {
+        std::fs::read_to_string(Self::path(config_dir))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// File and caret position to open on startup, if reopening is on and the file is still
+    /// there. A file that was moved or deleted since the last run is skipped silently.
+    pub fn file_to_reopen(&self) -> Option<(PathBuf, (usize, usize))> {
+        self.last_file
+            .clone()
+            .filter(|path| self.reopen_last_file && path.exists())
+            .map(|path| (path, self.last_caret))
+    }
+
+    pub async fn save(self, config_dir: PathBuf) -> Result<(), String> {
+        let path = Self::path(&config_dir);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|err| format!("Failed to create settings directory: {}", err))?;
+        }
+
+        let contents = serde_json::to_string_pretty(&self)
+            .map_err(|err| format!("Failed to serialize settings: {}", err))?;
+        tokio::fs::write(&path, contents)
+            .await
+            .map_err(|err| format!("Failed to write settings: {}", err))
+    }
+}
+
+// `highlighter::Theme` has no serde support, so it is stored by its display name
+mod highlight_theme {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        theme: &highlighter::Theme,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&theme.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<highlighter::Theme, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(highlighter::Theme::ALL
+            .iter()
+            .copied()
+            .find(|theme| theme.to_string() == name)
+            .unwrap_or(highlighter::Theme::SolarizedDark))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Settings as the next launch reads them back
+    fn relaunched(settings: &Settings) -> Settings {
+        serde_json::from_str(&serde_json::to_string_pretty(settings).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn the_last_file_is_reopened_on_the_next_launch() {
+        let path = std::env::temp_dir().join(format!("rust-note-last-{}.md", std::process::id()));
+        std::fs::write(&path, "journal").unwrap();
+
+        let settings = Settings {
+            reopen_last_file: true,
+            last_file: Some(path.clone()),
+            last_caret: (3, 7),
+            ..Settings::default()
+        };
+        assert_eq!(
+            relaunched(&settings).file_to_reopen(),
+            Some((path.clone(), (3, 7)))
+        );
+
+        let disabled = Settings {
+            reopen_last_file: false,
+            ..settings.clone()
+        };
+        assert_eq!(relaunched(&disabled).file_to_reopen(), None);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(relaunched(&settings).file_to_reopen(), None);
+    }
+}
@@ -0,0 +1,542 @@
+use crate::{privacy::DisplayPath, server::Operation};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem,
+    ops::Range,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Edits kept for undoing unless the settings say otherwise
+pub const DEFAULT_DEPTH: usize = 100;
+
+/// Megabytes the undo history may hold in memory unless the settings say otherwise
+pub const DEFAULT_MEMORY_MB: usize = 16;
+
+pub const MEGABYTE: usize = 1024 * 1024;
+
+// Tells apart the spill files of histories in the same process
+static SPILL_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// One of this user's own edits, in a form that can be reversed. Positions are byte offsets
+/// into the shared document.
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// Text that was inserted. Someone typing inside it splits it into several ranges, so that
+    /// undoing leaves their text alone.
+    Inserted(Vec<Range<usize>>),
+    /// Text that was deleted, with where it goes back
+    Deleted { at: usize, text: String },
+}
+
+/// Which history the next recorded edit reverses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    Undo,
+    Redo,
+}
+
+/// Selective undo: only this user's edits are reversed, with their positions carried past the
+/// edits others make in the meantime. Undoing an edit applies its inverse as a new edit, which
+/// is what redo then reverses.
+#[derive(Debug)]
+pub struct UndoHistory {
+    steps: Vec<Entry>,
+    undone: Vec<Entry>,
+    depth: usize,
+    // Bytes the histories may take up, counting deleted text and inserted ranges
+    memory_budget: usize,
+    // Where deleted text goes once the budget is reached, rather than the step being forgotten
+    spill: Option<SpillFile>,
+    spill_enabled: bool,
+    // Set while an undo or redo is being applied, so it lands in the other history
+    replay: Option<Replay>,
+}
+
+/// A step as a history keeps it
+#[derive(Debug)]
+enum Entry {
+    Kept(Step),
+    /// A deletion whose text was moved to the spill file. The step keeps an empty text, so its
+    /// position is still carried past other edits.
+    Spilled {
+        step: Step,
+        offset: u64,
+        len: usize,
+    },
+}
+
+/// Temporary file holding the text of spilled deletions, removed once the history is dropped
+#[derive(Debug)]
+struct SpillFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl Default for UndoHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEPTH)
+    }
+}
+
+impl UndoHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            steps: Vec::new(),
+            undone: Vec::new(),
+            depth,
+            memory_budget: DEFAULT_MEMORY_MB * MEGABYTE,
+            spill: None,
+            spill_enabled: false,
+            replay: None,
+        }
+    }
+
+    /// Changes how many bytes the history may hold in memory, evicting the oldest steps past it
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = bytes;
+        self.enforce_memory_budget();
+    }
+
+    /// Whether the text of old deletions goes to a temporary file once the memory budget is
+    /// reached, so they can still be undone. Text already there stays readable.
+    pub fn set_spill(&mut self, enabled: bool) {
+        self.spill_enabled = enabled;
+    }
+
+    /// Bytes the history holds in memory
+    pub fn memory_used(&self) -> usize {
+        self.steps.iter().chain(&self.undone).map(Entry::size).sum()
+    }
+
+    /// Changes how many edits are kept, forgetting the oldest ones past it
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        for history in [&mut self.steps, &mut self.undone] {
+            let excess = history.len().saturating_sub(depth);
+            history.drain(..excess);
+        }
+    }
+
+    /// Records the operations one local edit made, each deletion with the text it removed. A
+    /// fresh edit leaves nothing to redo.
+    pub fn record_action(&mut self, operations: Vec<(Operation, Option<String>)>) {
+        let replay = self.replay.take();
+        if replay.is_none() {
+            self.undone.clear();
+        }
+
+        for (operation, deleted) in operations {
+            let step = match operation {
+                Operation::Insert(insertion) => {
+                    let inserted = insertion.insert_at..insertion.insert_at + insertion.text.len();
+                    Step::Inserted(vec![inserted])
+                }
+                Operation::Delete(deletion) => match deleted {
+                    Some(text) => Step::Deleted {
+                        at: deletion.range.start,
+                        text,
+                    },
+                    None => continue,
+                },
+            };
+            let history = match replay {
+                Some(Replay::Undo) => &mut self.undone,
+                Some(Replay::Redo) | None => &mut self.steps,
+            };
+            if history.len() >= self.depth {
+                history.remove(0);
+            }
+            if self.depth > 0 {
+                history.push(Entry::Kept(step));
+            }
+        }
+        self.enforce_memory_budget();
+    }
+
+    /// Marks the next recorded edit as reversing one from `replay`'s history
+    pub fn replaying(&mut self, replay: Replay) {
+        self.replay = Some(replay);
+    }
+
+    /// Takes the edit the next undo or redo reverses
+    pub fn pop(&mut self, replay: Replay) -> Option<Step> {
+        let entry = match replay {
+            Replay::Undo => self.steps.pop(),
+            Replay::Redo => self.undone.pop(),
+        }?;
+
+        match entry {
+            Entry::Kept(step) => Some(step),
+            Entry::Spilled { step, offset, len } => {
+                let Step::Deleted { at, .. } = step else {
+                    return Some(step);
+                };
+                let spill = self.spill.as_mut()?;
+                match spill.read(offset, len) {
+                    Ok(text) => Some(Step::Deleted { at, text }),
+                    Err(e) => {
+                        eprintln!(
+                            "Failed to read undo step back from {}: {}",
+                            DisplayPath(&spill.path),
+                            e
+                        );
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Spills or forgets the oldest steps until the history fits its memory budget
+    fn enforce_memory_budget(&mut self) {
+        let mut used = self.memory_used();
+        while used > self.memory_budget {
+            if self.spill_enabled {
+                if let Some(saved) = self.spill_oldest() {
+                    used -= saved;
+                    continue;
+                }
+            }
+
+            let oldest = if self.steps.is_empty() {
+                &mut self.undone
+            } else {
+                &mut self.steps
+            };
+            if oldest.is_empty() {
+                break;
+            }
+            used -= oldest.remove(0).size();
+        }
+    }
+
+    /// Moves the text of the oldest deletion still in memory to the spill file, returning the
+    /// bytes that freed
+    fn spill_oldest(&mut self) -> Option<usize> {
+        let entry = self.steps.iter_mut().chain(self.undone.iter_mut()).find(
+            |entry| matches!(entry, Entry::Kept(Step::Deleted { text, .. }) if !text.is_empty()),
+        )?;
+
+        if self.spill.is_none() {
+            match SpillFile::create() {
+                Ok(spill) => self.spill = Some(spill),
+                Err(e) => {
+                    eprintln!("Failed to create an undo spill file: {}", e);
+                    self.spill_enabled = false;
+                    return None;
+                }
+            }
+        }
+        let spill = self.spill.as_mut()?;
+
+        let before = entry.size();
+        let Entry::Kept(Step::Deleted { at, text }) = entry else {
+            return None;
+        };
+        let offset = match spill.append(text) {
+            Ok(offset) => offset,
+            Err(e) => {
+                eprintln!(
+                    "Failed to spill an undo step to {}: {}",
+                    DisplayPath(&spill.path),
+                    e
+                );
+                self.spill_enabled = false;
+                return None;
+            }
+        };
+
+        let len = text.len();
+        *entry = Entry::Spilled {
+            step: Step::Deleted {
+                at: *at,
+                text: String::new(),
+            },
+            offset,
+            len,
+        };
+        Some(before - entry.size())
+    }
+
+    /// Moves the recorded positions past a change someone else made, given the document before
+    /// and after it
+    pub fn rebase(&mut self, before: &str, after: &str) {
+        if before == after || (self.steps.is_empty() && self.undone.is_empty()) {
+            return;
+        }
+
+        let (removed, inserted) = changed_span(before, after);
+        for history in [&mut self.steps, &mut self.undone] {
+            if !removed.is_empty() {
+                history
+                    .iter_mut()
+                    .for_each(|entry| entry.step_mut().rebase_delete(&removed));
+            }
+            if inserted > 0 {
+                history
+                    .iter_mut()
+                    .for_each(|entry| entry.step_mut().rebase_insert(removed.start, inserted));
+            }
+            history.retain(|entry| match entry {
+                Entry::Kept(Step::Inserted(ranges)) => !ranges.is_empty(),
+                _ => true,
+            });
+        }
+    }
+}
+
+impl Entry {
+    fn step_mut(&mut self) -> &mut Step {
+        match self {
+            Entry::Kept(step) | Entry::Spilled { step, .. } => step,
+        }
+    }
+
+    /// Bytes this entry takes up in memory
+    fn size(&self) -> usize {
+        let held = match self {
+            Entry::Kept(Step::Inserted(ranges)) => ranges.len() * mem::size_of::<Range<usize>>(),
+            Entry::Kept(Step::Deleted { text, .. }) => text.len(),
+            Entry::Spilled { .. } => 0,
+        };
+        mem::size_of::<Entry>() + held
+    }
+}
+
+impl SpillFile {
+    fn create() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "rust-note-undo-{}-{}",
+            std::process::id(),
+            SPILL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self { path, file, len: 0 })
+    }
+
+    /// Writes `text` at the end of the file, returning where it starts
+    fn append(&mut self, text: &str) -> io::Result<u64> {
+        let offset = self.len;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(text.as_bytes())?;
+        self.len += text.len() as u64;
+        Ok(offset)
+    }
+
+    fn read(&mut self, offset: u64, len: usize) -> io::Result<String> {
+        let mut bytes = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut bytes)?;
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Step {
+    fn rebase_insert(&mut self, at: usize, len: usize) {
+        match self {
+            Step::Inserted(ranges) => {
+                *ranges = ranges
+                    .drain(..)
+                    .flat_map(|range| {
+                        if at <= range.start {
+                            let shifted = range.start + len..range.end + len;
+                            vec![shifted]
+                        } else if at >= range.end {
+                            vec![range]
+                        } else {
+                            // Typed into the middle of this text, which stays when it is undone
+                            vec![range.start..at, at + len..range.end + len]
+                        }
+                    })
+                    .collect();
+            }
+            Step::Deleted { at: position, .. } => {
+                if at <= *position {
+                    *position += len;
+                }
+            }
+        }
+    }
+
+    fn rebase_delete(&mut self, removed: &Range<usize>) {
+        let map = |position: usize| {
+            if position <= removed.start {
+                position
+            } else if position >= removed.end {
+                position - removed.len()
+            } else {
+                removed.start
+            }
+        };
+
+        match self {
+            Step::Inserted(ranges) => {
+                *ranges = ranges
+                    .drain(..)
+                    .map(|range| map(range.start)..map(range.end))
+                    .filter(|range| !range.is_empty())
+                    .collect();
+            }
+            Step::Deleted { at, .. } => *at = map(*at),
+        }
+    }
+}
+
+/// The bytes of `before` that differ from `after`, and how many bytes replace them there
+fn changed_span(before: &str, after: &str) -> (Range<usize>, usize) {
+    let mut prefix = before
+        .bytes()
+        .zip(after.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !before.is_char_boundary(prefix) || !after.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+
+    let longest_suffix = (before.len() - prefix).min(after.len() - prefix);
+    let mut suffix = before
+        .bytes()
+        .rev()
+        .zip(after.bytes().rev())
+        .take(longest_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !before.is_char_boundary(before.len() - suffix)
+        || !after.is_char_boundary(after.len() - suffix)
+    {
+        suffix -= 1;
+    }
+
+    (prefix..before.len() - suffix, after.len() - suffix - prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{Deletion, Insertion};
+
+    fn insert(at: usize, text: &str) -> Vec<(Operation, Option<String>)> {
+        vec![(
+            Operation::Insert(Insertion {
+                made_by: 1,
+                insert_at: at,
+                text: text.to_string(),
+                base: None,
+            }),
+            None,
+        )]
+    }
+
+    fn delete(at: usize, text: &str) -> Vec<(Operation, Option<String>)> {
+        vec![(
+            Operation::Delete(Deletion {
+                made_by: 1,
+                range: at..at + text.len(),
+                base: None,
+            }),
+            Some(text.to_string()),
+        )]
+    }
+
+    /// Records ten deletions of 100 bytes each, the n-th one made of the digit n
+    fn ten_deletions(history: &mut UndoHistory) {
+        for n in 0..10 {
+            history.record_action(delete(0, &n.to_string().repeat(100)));
+        }
+    }
+
+    /// Texts of the deletions still there to undo, newest first
+    fn undoable_deletions(history: &mut UndoHistory) -> Vec<String> {
+        std::iter::from_fn(|| history.pop(Replay::Undo))
+            .map(|step| match step {
+                Step::Deleted { text, .. } => text,
+                other => panic!("expected a deletion, got {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn going_past_the_budget_forgets_the_oldest_steps() {
+        let entry = mem::size_of::<Entry>() + 100;
+        let mut history = UndoHistory::default();
+        history.set_memory_budget(3 * entry + entry / 2);
+        ten_deletions(&mut history);
+        assert!(history.memory_used() <= 3 * entry + entry / 2);
+
+        let kept = undoable_deletions(&mut history);
+        let newest: Vec<String> = [9, 8, 7].map(|n| n.to_string().repeat(100)).into();
+        assert_eq!(kept, newest);
+    }
+
+    #[test]
+    fn spilled_steps_can_still_be_undone() {
+        // Room for every step, but for the text of only two of them
+        let budget = 10 * mem::size_of::<Entry>() + 250;
+        let mut history = UndoHistory::default();
+        history.set_spill(true);
+        history.set_memory_budget(budget);
+        ten_deletions(&mut history);
+        assert!(history.memory_used() <= budget);
+        let spill_path = history.spill.as_ref().unwrap().path.clone();
+        assert!(spill_path.exists());
+
+        let kept = undoable_deletions(&mut history);
+        let all: Vec<String> = (0..10).rev().map(|n| n.to_string().repeat(100)).collect();
+        assert_eq!(kept, all);
+
+        // The spill file goes with the history
+        drop(history);
+        assert!(!spill_path.exists());
+    }
+
+    /// Removes the ranges an undone insertion left, last first so earlier ones stay in place
+    fn undo_insertion(document: &str, step: Step) -> String {
+        let Step::Inserted(mut ranges) = step else {
+            panic!("expected an insertion, got {step:?}");
+        };
+        ranges.sort_by_key(|range| range.start);
+        let mut document = document.to_string();
+        for range in ranges.into_iter().rev() {
+            document.replace_range(range, "");
+        }
+        document
+    }
+
+    #[test]
+    fn undoing_an_insert_keeps_text_someone_else_added_around_it() {
+        let mut history = UndoHistory::default();
+        history.record_action(insert(0, "hello"));
+
+        // Another user writes before and after it
+        history.rebase("hello", ">> hello");
+        history.rebase(">> hello", ">> hello world");
+
+        let undone = undo_insertion(">> hello world", history.pop(Replay::Undo).unwrap());
+        assert_eq!(undone, ">>  world");
+    }
+
+    #[test]
+    fn undoing_an_insert_keeps_text_someone_else_typed_inside_it() {
+        let mut history = UndoHistory::default();
+        history.record_action(insert(0, "héllo"));
+
+        // Another user types between the two l's
+        history.rebase("héllo", "hél—lo");
+
+        let undone = undo_insertion("hél—lo", history.pop(Replay::Undo).unwrap());
+        assert_eq!(undone, "—");
+    }
+}
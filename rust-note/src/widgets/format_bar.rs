@@ -1,24 +1,42 @@
-use iced::widget::{button, container, row, text, text_input, tooltip};
+use iced::widget::{button, column, container, row, text, text_input, tooltip};
 use iced::{Alignment, Element, Font, Length, Task};
 
 pub const DEFAULT_FONT_SIZE: u16 = 16;
 
+// Below this window width the secondary controls move into the overflow menu
+const COMPACT_WIDTH: f32 = 900.0;
+
 #[derive(Debug, Clone)]
 pub enum TextStyle {
     Bold,
     Italic,
     Strikethrough,
+    Highlight,
+    Math,
+    MathBlock,
+    HorizontalRule,
+    PageBreak,
+    Wrap(String, String),
+    WrapOpenChanged(String),
+    WrapCloseChanged(String),
+    ToggleOverflow,
     TextSize(String),
 }
 
 pub struct FormatBar {
     text_size: String,
+    wrap_open: String,
+    wrap_close: String,
+    overflow_open: bool,
 }
 
 impl FormatBar {
     pub fn new() -> Self {
         Self {
             text_size: DEFAULT_FONT_SIZE.to_string(),
+            wrap_open: String::new(),
+            wrap_close: String::new(),
+            overflow_open: false,
         }
     }
 
@@ -33,12 +51,21 @@ impl FormatBar {
             TextStyle::TextSize(text_size) => {
                 self.text_size = text_size;
             }
+            TextStyle::WrapOpenChanged(open) => {
+                self.wrap_open = open;
+            }
+            TextStyle::WrapCloseChanged(close) => {
+                self.wrap_close = close;
+            }
+            TextStyle::ToggleOverflow => {
+                self.overflow_open = !self.overflow_open;
+            }
             _ => {}
         }
         Task::none()
     }
 
-    pub fn view(&self) -> Element<TextStyle> {
+    pub fn view(&self, window_width: f32) -> Element<'_, TextStyle> {
         let text_size = &self.text_size.to_string();
 
         let text_size_input = text_input("16", text_size)
@@ -56,18 +83,87 @@ impl FormatBar {
             TextStyle::Strikethrough,
         );
 
-        row![
-            bold_button,
-            italic_button,
-            strikethrough_button,
+        let math_button = format_bar_button(text("$").into(), "Inline math", TextStyle::Math);
+        let math_block_button =
+            format_bar_button(text("$$").into(), "Math block", TextStyle::MathBlock);
+
+        let rule_button = format_bar_button(
+            text("\u{2015}").into(),
+            "Horizontal rule",
+            TextStyle::HorizontalRule,
+        );
+        let page_break_button =
+            format_bar_button(text("\u{21A1}").into(), "Page break", TextStyle::PageBreak);
+
+        let wrap_open_input = text_input("<open>", &self.wrap_open)
+            .on_input(TextStyle::WrapOpenChanged)
+            .width(Length::Fixed(70.0))
+            .padding(10)
+            .size(16);
+        let wrap_close_input = text_input("</close>", &self.wrap_close)
+            .on_input(TextStyle::WrapCloseChanged)
+            .width(Length::Fixed(70.0))
+            .padding(10)
+            .size(16);
+        let wrap_button = {
+            let button = button(text("Wrap")).padding(5);
+            if self.wrap_open.is_empty() && self.wrap_close.is_empty() {
+                button
+            } else {
+                button.on_press(TextStyle::Wrap(
+                    self.wrap_open.clone(),
+                    self.wrap_close.clone(),
+                ))
+            }
+        };
+
+        let secondary_controls = row![
             container(text_size_icon(20))
                 .align_x(Alignment::Center)
                 .align_y(Alignment::Center),
-            text_size_input
+            text_size_input,
+            math_button,
+            math_block_button,
+            rule_button,
+            page_break_button,
+            wrap_open_input,
+            wrap_close_input,
+            wrap_button
         ]
         .spacing(10)
-        .align_y(Alignment::Center)
-        .into()
+        .align_y(Alignment::Center);
+
+        if window_width >= COMPACT_WIDTH {
+            return row![
+                bold_button,
+                italic_button,
+                strikethrough_button,
+                secondary_controls
+            ]
+            .spacing(10)
+            .align_y(Alignment::Center)
+            .into();
+        }
+
+        let overflow_button =
+            format_bar_button(text("…").into(), "More", TextStyle::ToggleOverflow);
+        let primary_controls = row![
+            bold_button,
+            italic_button,
+            strikethrough_button,
+            overflow_button
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center);
+
+        if self.overflow_open {
+            column![primary_controls, secondary_controls]
+                .spacing(10)
+                .align_x(Alignment::Center)
+                .into()
+        } else {
+            primary_controls.into()
+        }
     }
 }
 
@@ -1,32 +1,116 @@
+use crate::privacy::DisplayPath;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use iced::widget::{button, pick_list, row};
-use iced::{Alignment, Element, Length, Theme};
+use iced::widget::{button, pick_list, row, text_input};
+use iced::{highlighter, Alignment, Element, Length, Theme};
+
+// Largest remote document that will be loaded into the editor
+const MAX_URL_BYTES: usize = 5 * 1024 * 1024;
+
+/// Error from `open_file` when the open dialog is closed without picking a file
+pub const OPEN_CANCELLED: &str = "File dialog closed without selection.";
+
+/// Error from `save_file` when the save dialog is closed without picking a file
+pub const SAVE_CANCELLED: &str = "Save file dialog was closed without selection.";
+
+// Files kept in the Recent Files menu
+const MAX_RECENT_FILES: usize = 10;
 
 #[derive(Debug, Clone)]
 pub enum MenuMessage {
     ThemeSelected(Theme),
+    HighlightThemeSelected(highlighter::Theme),
+    NewTab,
     OpenFile,
+    OpenRecent(PathBuf),
     FileOpened(Result<(PathBuf, Arc<String>), String>),
+    OpenFolder,
+    FolderOpened(Result<(PathBuf, Vec<PathBuf>), String>),
+    UrlChanged(String),
+    OpenUrl,
+    UrlOpened(Result<(String, Arc<String>), String>),
     SaveFile,
     CloseFile,
     FileSaved(Result<PathBuf, String>),
+    ExportOpLog,
+    ExportPlainText,
+    PlainTextExported(Result<PathBuf, String>),
+    PublishSnapshot,
+    SnapshotPublished(Result<PathBuf, String>),
+    OpLogExported(Result<PathBuf, String>),
+    ReportProblem,
+    ReportSaved(Result<PathBuf, String>),
 }
 
-pub struct MenuBar;
+pub struct MenuBar {
+    url_input: String,
+    // File the recent files are saved to
+    recent_list: PathBuf,
+    recent_files: Vec<PathBuf>,
+}
+
+/// Entry of the Recent Files menu, named by the file and the folder it is in
+#[derive(Debug, Clone, PartialEq)]
+struct RecentFile(PathBuf);
+
+impl fmt::Display for RecentFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |path: &Path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        };
+        match (name(&self.0), self.0.parent().and_then(name)) {
+            (Some(file), Some(folder)) => write!(f, "{} ({})", file, folder),
+            (Some(file), None) => f.write_str(&file),
+            _ => write!(f, "{}", self.0.display()),
+        }
+    }
+}
 
 impl MenuBar {
-    pub fn new() -> Self {
-        Self {}
+    /// A menu bar offering the recent files saved in `recent_list`
+    pub fn new(recent_list: PathBuf) -> Self {
+        Self {
+            url_input: String::new(),
+            recent_files: read_recent(&recent_list),
+            recent_list,
+        }
+    }
+
+    /// Puts a file just opened or saved first in the Recent Files menu, returning the save of
+    /// the updated list
+    pub fn add_recent(
+        &mut self,
+        path: PathBuf,
+    ) -> impl std::future::Future<Output = Result<(), String>> {
+        self.recent_files.retain(|known| *known != path);
+        self.recent_files.insert(0, path);
+        self.recent_files.truncate(MAX_RECENT_FILES);
+        write_recent(self.recent_list.clone(), self.recent_files.clone())
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url_input
+    }
+
+    pub fn set_url(&mut self, url: String) {
+        self.url_input = url;
     }
 
     pub fn view(
         &self,
         theme: Theme,
+        highlight_theme: highlighter::Theme,
         disable_open_file: bool,
         file_opened: bool,
     ) -> Element<'_, MenuMessage> {
+        let new_tab = if disable_open_file {
+            button("New Tab").padding(5)
+        } else {
+            button("New Tab").on_press(MenuMessage::NewTab).padding(5)
+        };
         let file_picker = if disable_open_file {
             button("Open File").padding(5)
         } else {
@@ -34,6 +118,23 @@ impl MenuBar {
                 .on_press(MenuMessage::OpenFile)
                 .padding(5)
         };
+        let folder_picker = if disable_open_file {
+            button("Open Folder").padding(5)
+        } else {
+            button("Open Folder")
+                .on_press(MenuMessage::OpenFolder)
+                .padding(5)
+        };
+        let url_input = text_input("https://...", &self.url_input)
+            .on_input(MenuMessage::UrlChanged)
+            .width(Length::Fixed(200.0))
+            .padding(5);
+        let url_open = if disable_open_file || self.url_input.trim().is_empty() {
+            button("Open URL").padding(5)
+        } else {
+            button("Open URL").on_press(MenuMessage::OpenUrl).padding(5)
+        };
+
         let file_save = button("Save File")
             .on_press(MenuMessage::SaveFile)
             .padding(5);
@@ -46,14 +147,97 @@ impl MenuBar {
                 .padding(5)
         };
 
+        let export_log = button("Export Log")
+            .on_press(MenuMessage::ExportOpLog)
+            .padding(5);
+
+        let report = button("Report Problem")
+            .on_press(MenuMessage::ReportProblem)
+            .padding(5);
+
+        let export_text = button("Export Text")
+            .on_press(MenuMessage::ExportPlainText)
+            .padding(5);
+
+        let publish = button("Publish")
+            .on_press(MenuMessage::PublishSnapshot)
+            .padding(5);
+
+        let recent_files: Vec<RecentFile> =
+            self.recent_files.iter().cloned().map(RecentFile).collect();
+        let recent = if disable_open_file || recent_files.is_empty() {
+            row![]
+        } else {
+            row![pick_list(recent_files, None::<RecentFile>, |recent| {
+                MenuMessage::OpenRecent(recent.0)
+            })
+            .placeholder("Recent Files")
+            .width(Length::Shrink)
+            .padding(5)]
+        };
+
         let theme_selector = pick_list(Theme::ALL, Some(theme), MenuMessage::ThemeSelected)
             .width(Length::Shrink)
             .padding(5);
 
-        row![file_picker, file_save, file_close, theme_selector]
-            .spacing(10)
-            .align_y(Alignment::Center)
-            .into()
+        let highlight_theme_selector = pick_list(
+            highlighter::Theme::ALL,
+            Some(highlight_theme),
+            MenuMessage::HighlightThemeSelected,
+        )
+        .width(Length::Shrink)
+        .padding(5);
+
+        row![
+            new_tab,
+            file_picker,
+            recent,
+            folder_picker,
+            url_input,
+            url_open,
+            file_save,
+            file_close,
+            export_log,
+            export_text,
+            publish,
+            report,
+            theme_selector,
+            highlight_theme_selector
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)
+        .into()
+    }
+}
+
+/// The syntax highlighting theme that fits an app theme, matching its colors where one is close
+/// and otherwise at least whether it is light or dark
+pub fn default_highlight_theme(theme: &Theme) -> highlighter::Theme {
+    match theme {
+        Theme::SolarizedDark => highlighter::Theme::SolarizedDark,
+        Theme::CatppuccinFrappe | Theme::CatppuccinMacchiato | Theme::CatppuccinMocha => {
+            highlighter::Theme::Base16Mocha
+        }
+        Theme::Nord
+        | Theme::TokyoNight
+        | Theme::TokyoNightStorm
+        | Theme::Moonfly
+        | Theme::Nightfly
+        | Theme::Oxocarbon => highlighter::Theme::Base16Ocean,
+        Theme::Dark
+        | Theme::Dracula
+        | Theme::GruvboxDark
+        | Theme::KanagawaWave
+        | Theme::KanagawaDragon
+        | Theme::Ferra => highlighter::Theme::Base16Eighties,
+        Theme::Light
+        | Theme::SolarizedLight
+        | Theme::GruvboxLight
+        | Theme::CatppuccinLatte
+        | Theme::TokyoNightLight
+        | Theme::KanagawaLotus => highlighter::Theme::InspiredGitHub,
+        Theme::Custom(_) if theme.extended_palette().is_dark => highlighter::Theme::SolarizedDark,
+        Theme::Custom(_) => highlighter::Theme::InspiredGitHub,
     }
 }
 
@@ -63,11 +247,62 @@ pub async fn open_file() -> Result<(PathBuf, Arc<String>), String> {
         .add_filter("Text Files", &["md", "txt"])
         .pick_file()
         .await
-        .ok_or_else(|| "File dialog closed without selection.".to_string())?;
+        .ok_or_else(|| OPEN_CANCELLED.to_string())?;
 
-    load_file(picked_file)
+    load_file(picked_file).await
+}
+
+/// Lets the user pick a folder of notes, returning it with the notes found in it
+pub async fn open_folder() -> Result<(PathBuf, Vec<PathBuf>), String> {
+    let picked_folder = rfd::AsyncFileDialog::new()
+        .set_title("Open a folder of notes...")
+        .pick_folder()
         .await
-        .map_err(|_| "File dialog was closed.".to_string())
+        .ok_or_else(|| OPEN_CANCELLED.to_string())?;
+
+    let root = picked_folder.path().to_path_buf();
+    let notes = list_notes(&root).await?;
+    println!("Folder opened: {}", DisplayPath(&root));
+    Ok((root, notes))
+}
+
+/// Every markdown and text file under a folder, sorted by path. Hidden files and folders are
+/// skipped.
+pub async fn list_notes(root: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut notes = Vec::new();
+    let mut folders = vec![root.to_path_buf()];
+
+    while let Some(folder) = folders.pop() {
+        let mut entries = tokio::fs::read_dir(&folder)
+            .await
+            .map_err(|err| format!("IO error reading folder: {}", err))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|err| format!("IO error reading folder: {}", err))?
+        {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            match entry.file_type().await {
+                Ok(file_type) if file_type.is_dir() => folders.push(path),
+                Ok(file_type)
+                    if file_type.is_file()
+                        && path
+                            .extension()
+                            .is_some_and(|extension| extension == "md" || extension == "txt") =>
+                {
+                    notes.push(path)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    notes.sort();
+    Ok(notes)
 }
 
 pub async fn load_file(path: impl Into<PathBuf>) -> Result<(PathBuf, Arc<String>), String> {
@@ -78,10 +313,89 @@ pub async fn load_file(path: impl Into<PathBuf>) -> Result<(PathBuf, Arc<String>
         .map(Arc::new)
         .map_err(|err| format!("IO error reading file: {}", err))?; // Convert error to a simple string
 
-    println!("File loaded successfully from: {}", path.display()); // Log successful load
+    println!("File loaded successfully from: {}", DisplayPath(&path)); // Log successful load
     Ok((path, contents))
 }
 
+/// Where the recent files are kept in `config_dir`, next to the settings
+pub fn recent_list(config_dir: &Path) -> PathBuf {
+    config_dir.join("recent.json")
+}
+
+/// Files listed in `list`, most recent first. Files that are gone are left out.
+fn read_recent(list: &Path) -> Vec<PathBuf> {
+    let recent: Vec<PathBuf> = std::fs::read_to_string(list)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+    recent.into_iter().filter(|path| path.exists()).collect()
+}
+
+async fn write_recent(list: PathBuf, recent: Vec<PathBuf>) -> Result<(), String> {
+    if let Some(parent) = list.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|err| format!("Failed to save recent files: {}", err))?;
+    }
+    let contents = serde_json::to_string(&recent)
+        .map_err(|err| format!("Failed to save recent files: {}", err))?;
+    tokio::fs::write(&list, contents)
+        .await
+        .map_err(|err| format!("Failed to save recent files: {}", err))
+}
+
+pub async fn open_url(url: String) -> Result<(String, Arc<String>), String> {
+    let parsed = url::Url::parse(url.trim()).map_err(|err| format!("Invalid URL: {}", err))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("Only http(s) URLs can be opened.".to_string());
+    }
+
+    let mut response = reqwest::get(parsed.clone())
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("Failed to fetch URL: {}", err))?;
+
+    // Reject anything that is clearly not text before downloading it
+    if let Some(content_type) = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        let is_text = content_type.starts_with("text/")
+            || content_type.contains("markdown")
+            || content_type.contains("json");
+        if !is_text {
+            return Err(format!("URL does not point to text ({}).", content_type));
+        }
+    }
+
+    if response
+        .content_length()
+        .is_some_and(|length| length as usize > MAX_URL_BYTES)
+    {
+        return Err("Remote document is too large to open.".to_string());
+    }
+
+    // The declared length can be missing or wrong, so enforce the limit while reading
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| format!("Failed to read response: {}", err))?
+    {
+        if body.len() + chunk.len() > MAX_URL_BYTES {
+            return Err("Remote document is too large to open.".to_string());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let contents =
+        String::from_utf8(body).map_err(|_| "URL does not point to UTF-8 text.".to_string())?;
+
+    println!("URL loaded successfully from: {}", parsed); // Log successful load
+    Ok((parsed.to_string(), Arc::new(contents)))
+}
+
 pub async fn save_file(path: Option<PathBuf>, contents: String) -> Result<PathBuf, String> {
     let path = if let Some(path) = path {
         path
@@ -92,13 +406,141 @@ pub async fn save_file(path: Option<PathBuf>, contents: String) -> Result<PathBu
             .as_ref()
             .map(rfd::FileHandle::path)
             .map(Path::to_owned)
-            .ok_or_else(|| "Save file dialog was closed without selection.".to_string())?
+            .ok_or_else(|| SAVE_CANCELLED.to_string())?
     };
 
     tokio::fs::write(&path, contents)
         .await
         .map_err(|err| format!("Failed to write file: {}", err))?; // Convert error to a simple string
 
-    println!("File saved successfully at: {}", path.display()); // Log successful save
+    println!("File saved successfully at: {}", DisplayPath(&path)); // Log successful save
     Ok(path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves `body` as `content_type` on a local port, returning the URL it is served at
+    async fn serve(content_type: &'static str, body: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = axum::Router::new().route(
+            "/note.md",
+            axum::routing::get(move || async move {
+                ([(reqwest::header::CONTENT_TYPE, content_type)], body)
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}/note.md", address)
+    }
+
+    #[tokio::test]
+    async fn a_markdown_url_opens_as_a_document() {
+        let url = serve("text/markdown", "# Remote\n\nFetched over http.").await;
+
+        let (opened, contents) = open_url(url.clone()).await.unwrap();
+        assert_eq!(opened, url);
+        assert_eq!(contents.as_str(), "# Remote\n\nFetched over http.");
+    }
+
+    #[tokio::test]
+    async fn urls_that_are_not_text_are_refused() {
+        let url = serve("image/png", "not really a png").await;
+        assert!(open_url(url).await.is_err());
+
+        assert!(open_url("ftp://example.com/note.md".to_string())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn recent_files_are_deduplicated_capped_and_pruned() {
+        let root = std::env::temp_dir().join(format!("rust-note-recent-{}", std::process::id()));
+        let list = recent_list(&root.join("config"));
+        let mut menubar = MenuBar::new(list.clone());
+        assert!(menubar.recent_files.is_empty());
+
+        std::fs::create_dir_all(&root).unwrap();
+        let files: Vec<PathBuf> = (0..12).map(|i| root.join(format!("{i}.md"))).collect();
+        for file in &files {
+            std::fs::write(file, "").unwrap();
+            menubar.add_recent(file.clone()).await.unwrap();
+        }
+
+        // Newest first, and only the last ten survive
+        let expected: Vec<PathBuf> = files[2..].iter().rev().cloned().collect();
+        assert_eq!(menubar.recent_files, expected);
+        assert_eq!(read_recent(&list), expected);
+
+        // Reopening a file moves it to the front rather than listing it twice
+        menubar.add_recent(files[5].clone()).await.unwrap();
+        let recent = read_recent(&list);
+        assert_eq!(recent.len(), MAX_RECENT_FILES);
+        assert_eq!(recent[0], files[5]);
+        assert_eq!(recent.iter().filter(|path| **path == files[5]).count(), 1);
+
+        // Files deleted since are left out on the next launch
+        std::fs::remove_file(&files[11]).unwrap();
+        let recent = MenuBar::new(list).recent_files;
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(!recent.contains(&files[11]));
+        assert_eq!(recent.len(), MAX_RECENT_FILES - 1);
+    }
+
+    #[test]
+    fn highlighting_is_as_light_or_dark_as_the_app_theme() {
+        for theme in Theme::ALL {
+            assert_eq!(
+                default_highlight_theme(theme).is_dark(),
+                theme.extended_palette().is_dark,
+                "{theme}"
+            );
+        }
+    }
+
+    #[test]
+    fn close_themes_get_matching_highlighting() {
+        assert_eq!(
+            default_highlight_theme(&Theme::SolarizedDark),
+            highlighter::Theme::SolarizedDark
+        );
+        assert_eq!(
+            default_highlight_theme(&Theme::CatppuccinMocha),
+            highlighter::Theme::Base16Mocha
+        );
+        assert_eq!(
+            default_highlight_theme(&Theme::Light),
+            highlighter::Theme::InspiredGitHub
+        );
+    }
+
+    #[tokio::test]
+    async fn a_folder_lists_its_markdown_and_text_files() {
+        let root = std::env::temp_dir().join(format!("rust-note-notes-{}", std::process::id()));
+        for folder in ["journal", ".git"] {
+            std::fs::create_dir_all(root.join(folder)).unwrap();
+        }
+        for file in [
+            "b.md",
+            "a.txt",
+            "image.png",
+            ".hidden.md",
+            "journal/day.md",
+            ".git/HEAD.md",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let notes = list_notes(&root).await;
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            notes.unwrap(),
+            [
+                root.join("a.txt"),
+                root.join("b.md"),
+                root.join("journal/day.md")
+            ]
+        );
+    }
+}